@@ -5,9 +5,12 @@ use qcoin_crypto::{
     default_registry, InMemoryRegistry, PqSchemeRegistry, PqSignatureScheme, PrivateKey, PublicKey,
     SignatureSchemeId,
 };
-use qcoin_ledger::ChainState;
-use qcoin_script::DeterministicScriptEngine;
-use qcoin_types::{consensus_codec, Block, Hash256, Transaction};
+use qcoin_ledger::{ChainState, MEDIAN_TIME_SPAN};
+use qcoin_script::{DeterministicScriptEngine, VmConfig};
+use qcoin_types::{
+    consensus_codec, validator_id, validator_set_hash, Block, BlockHeader, Hash256, Transaction,
+    BLOCK_HEADER_VERSION, BLOCK_HEADER_VERSION_LEGACY,
+};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -22,6 +25,40 @@ pub enum ConsensusError {
     Other(String),
 }
 
+/// Rules every validator must share to agree on block validity. Headers commit to the
+/// hash of the rules in effect at their height, so a node running different rules
+/// rejects the block instead of diverging, while rule changes scheduled for later heights
+/// leave the commitments of earlier blocks valid.
+#[derive(Clone, Debug)]
+pub struct ConsensusParams {
+    pub chain_id: u32,
+    pub median_time_span: usize,
+    /// VM configuration blocks are validated with.
+    pub vm: VmConfig,
+}
+
+impl ConsensusParams {
+    pub fn for_chain(chain_id: u32) -> Self {
+        Self {
+            chain_id,
+            median_time_span: MEDIAN_TIME_SPAN,
+            vm: VmConfig::for_chain(chain_id),
+        }
+    }
+
+    /// Hash of the rules in effect at `height`, committed to by a header at that height.
+    pub fn hash_at(&self, height: u64) -> Hash256 {
+        let mut hasher = Hasher::new();
+        hasher.update(b"QCOIN_CONSENSUS_PARAMS_V1");
+        hasher.update(&self.chain_id.to_le_bytes());
+        hasher.update(&(self.median_time_span as u64).to_le_bytes());
+        hasher.update(&qcoin_script::consensus_codec::encode_vm_config_at(
+            &self.vm, height,
+        ));
+        *hasher.finalize().as_bytes()
+    }
+}
+
 pub trait ValidatorIdentity {
     fn public_key(&self) -> &PublicKey;
 }
@@ -174,7 +211,14 @@ where
         return Err(ConsensusError::InvalidBlock);
     }
 
-    let expected_state_root = compute_state_root(chain, &block.transactions, block.header.height)?;
+    validate_header_commitments(chain, block, validators)?;
+
+    let expected_state_root = compute_state_root(
+        chain,
+        &block.transactions,
+        block.header.height,
+        block.header.version,
+    )?;
     if block.header.state_root != expected_state_root {
         return Err(ConsensusError::InvalidBlock);
     }
@@ -192,6 +236,59 @@ where
     Ok(())
 }
 
+/// Checks the header fields that depend on its version: the witness root, and for
+/// versioned headers the proposer, validator set and consensus parameters.
+fn validate_header_commitments(
+    chain: &ChainState,
+    block: &Block,
+    validators: &[PublicKey],
+) -> Result<(), ConsensusError> {
+    let header = &block.header;
+    if header.version < chain.header_version {
+        return Err(ConsensusError::InvalidBlock);
+    }
+
+    match header.version {
+        BLOCK_HEADER_VERSION_LEGACY => {
+            // These fields are not in the legacy encoding, so they are not signed.
+            if header.proposer_id != Hash256::default()
+                || header.validator_set_hash != Hash256::default()
+                || header.consensus_params_hash != Hash256::default()
+            {
+                return Err(ConsensusError::InvalidBlock);
+            }
+            // Accepted without a witness root until the chain has seen one.
+            if !header.commits_witnesses() && chain.witness_commitments {
+                return Err(ConsensusError::InvalidBlock);
+            }
+        }
+        // Version 2 only adds supply counters to the state root.
+        1..=BLOCK_HEADER_VERSION => {
+            if !header.commits_witnesses()
+                || header.proposer_id != validator_id(&block.proposer_public_key)
+                || header.consensus_params_hash
+                    != ConsensusParams::for_chain(chain.chain_id).hash_at(header.height)
+            {
+                return Err(ConsensusError::InvalidBlock);
+            }
+            // Replays without a configured set can only check the proposer's own key.
+            if !validators.is_empty() && header.validator_set_hash != validator_set_hash(validators)
+            {
+                return Err(ConsensusError::InvalidBlock);
+            }
+        }
+        _ => return Err(ConsensusError::InvalidBlock),
+    }
+
+    if header.commits_witnesses()
+        && header.witness_root != compute_witness_root(&block.transactions)
+    {
+        return Err(ConsensusError::InvalidBlock);
+    }
+
+    Ok(())
+}
+
 fn compute_tx_root(txs: &[Transaction]) -> Hash256 {
     let mut hasher = Hasher::new();
 
@@ -203,21 +300,34 @@ fn compute_tx_root(txs: &[Transaction]) -> Hash256 {
     *hasher.finalize().as_bytes()
 }
 
+fn compute_witness_root(txs: &[Transaction]) -> Hash256 {
+    let mut hasher = Hasher::new();
+    hasher.update(b"QCOIN_WITNESS_ROOT_V1");
+
+    for tx in txs {
+        hasher.update(&tx.witness_tx_id());
+    }
+
+    *hasher.finalize().as_bytes()
+}
+
 fn compute_state_root(
     chain: &ChainState,
     txs: &[Transaction],
     height: u64,
+    header_version: u32,
 ) -> Result<Hash256, ConsensusError> {
     let mut ledger = chain.ledger.clone();
-    let script_engine = DeterministicScriptEngine::default();
+    let script_engine = DeterministicScriptEngine::for_chain(chain.chain_id);
+    let median_time_past = chain.median_time_past();
 
     for tx in txs {
         ledger
-            .apply_transaction(tx, &script_engine, height, chain.chain_id)
+            .apply_transaction(tx, &script_engine, height, median_time_past, chain.chain_id)
             .map_err(|err| ConsensusError::LedgerError(err.to_string()))?;
     }
 
-    Ok(ledger.state_root())
+    Ok(ledger.state_root_at(header_version))
 }
 
 fn current_unix_timestamp() -> Result<u64, ConsensusError> {
@@ -240,16 +350,22 @@ impl ConsensusEngine for DummyConsensusEngine {
             return Err(ConsensusError::InvalidBlock);
         }
 
-        let state_root = compute_state_root(chain, &txs, next_height)?;
+        let state_root = compute_state_root(chain, &txs, next_height, BLOCK_HEADER_VERSION)?;
         let tx_root = compute_tx_root(&txs);
+        let witness_root = compute_witness_root(&txs);
         let timestamp = current_unix_timestamp()?;
 
-        let header = qcoin_types::BlockHeader {
+        let header = BlockHeader {
+            version: BLOCK_HEADER_VERSION,
             parent_hash: chain.tip_hash,
             state_root,
             tx_root,
             height: next_height,
             timestamp,
+            witness_root,
+            proposer_id: validator_id(&self.public_key),
+            validator_set_hash: validator_set_hash(&self.validators),
+            consensus_params_hash: ConsensusParams::for_chain(chain.chain_id).hash_at(next_height),
         };
 
         let header_bytes = consensus_codec::encode_block_header(&header);
@@ -277,6 +393,7 @@ impl ConsensusEngine for DummyConsensusEngine {
 mod tests {
     use super::*;
     use qcoin_crypto::SignatureSchemeId;
+    use qcoin_script::gas::{GasSchedule, GasScheduleTable};
     use qcoin_types::TransactionKind;
 
     #[test]
@@ -289,6 +406,8 @@ mod tests {
                 kind: TransactionKind::Transfer,
                 inputs: Vec::new(),
                 outputs: Vec::new(),
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: qcoin_types::TransactionWitness::default(),
         };
@@ -307,6 +426,140 @@ mod tests {
         assert!(matches!(result, Err(ConsensusError::InvalidBlock)));
     }
 
+    fn sign_header(engine: &DummyConsensusEngine, block: &mut Block) {
+        let header_bytes = consensus_codec::encode_block_header(&block.header);
+        block.signature = engine
+            .scheme(&engine.signing_scheme)
+            .expect("scheme should exist")
+            .sign(&engine.signing_key, &header_bytes)
+            .expect("signing should succeed");
+    }
+
+    #[test]
+    fn validate_block_rejects_swapped_witnesses() {
+        let engine = DummyConsensusEngine::default();
+        let chain = ChainState::default();
+
+        let tx = Transaction {
+            core: qcoin_types::TransactionCore {
+                kind: TransactionKind::Transfer,
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: qcoin_types::TransactionWitness {
+                inputs: vec![vec![1, 2, 3]],
+            },
+        };
+
+        let block = engine
+            .propose_block(&chain, vec![tx])
+            .expect("block should be proposed");
+        assert!(block.header.commits_witnesses());
+
+        // The tx root still matches, but the signed witness root does not.
+        let mut swapped = block.clone();
+        swapped.transactions[0].witness.inputs[0] = vec![9, 9, 9];
+        assert!(matches!(
+            engine.validate_block(&chain, &swapped),
+            Err(ConsensusError::InvalidBlock)
+        ));
+    }
+
+    /// Rewrites a proposed block's header into the pre-versioning format.
+    fn legacy_block(engine: &DummyConsensusEngine, chain: &ChainState) -> Block {
+        let mut block = engine
+            .propose_block(chain, Vec::new())
+            .expect("block should be proposed");
+        block.header = BlockHeader {
+            version: BLOCK_HEADER_VERSION_LEGACY,
+            witness_root: qcoin_types::LEGACY_WITNESS_ROOT,
+            proposer_id: Hash256::default(),
+            validator_set_hash: Hash256::default(),
+            consensus_params_hash: Hash256::default(),
+            ..block.header
+        };
+        sign_header(engine, &mut block);
+        block
+    }
+
+    #[test]
+    fn legacy_headers_are_accepted_until_witnesses_are_committed() {
+        let engine = DummyConsensusEngine::default();
+        let mut chain = ChainState::default();
+
+        let legacy = legacy_block(&engine, &chain);
+        engine
+            .validate_block(&chain, &legacy)
+            .expect("legacy header should validate on a legacy chain");
+
+        chain.witness_commitments = true;
+        assert!(matches!(
+            engine.validate_block(&chain, &legacy),
+            Err(ConsensusError::InvalidBlock)
+        ));
+    }
+
+    #[test]
+    fn versioned_headers_commit_to_proposer_validators_and_params() {
+        let engine = DummyConsensusEngine::default();
+        let mut chain = ChainState::default();
+
+        let block = engine
+            .propose_block(&chain, Vec::new())
+            .expect("block should be proposed");
+        assert_eq!(block.header.version, BLOCK_HEADER_VERSION);
+        assert_eq!(block.header.proposer_id, validator_id(&engine.public_key));
+        assert_eq!(
+            block.header.validator_set_hash,
+            validator_set_hash(&engine.validators)
+        );
+
+        let tampered: [fn(&mut BlockHeader); 4] = [
+            |header| header.proposer_id = [1u8; 32],
+            |header| header.validator_set_hash = [2u8; 32],
+            |header| header.consensus_params_hash = [3u8; 32],
+            |header| header.version = BLOCK_HEADER_VERSION + 1,
+        ];
+        for tamper in tampered {
+            let mut modified = block.clone();
+            tamper(&mut modified.header);
+            sign_header(&engine, &mut modified);
+            assert!(matches!(
+                engine.validate_block(&chain, &modified),
+                Err(ConsensusError::InvalidBlock)
+            ));
+        }
+
+        // A legacy header may not carry fields its encoding leaves unsigned.
+        let mut legacy = legacy_block(&engine, &chain);
+        legacy.header.proposer_id = validator_id(&engine.public_key);
+        sign_header(&engine, &mut legacy);
+        assert!(matches!(
+            engine.validate_block(&chain, &legacy),
+            Err(ConsensusError::InvalidBlock)
+        ));
+
+        // Other chains and other VM rules produce other parameter hashes.
+        let params = ConsensusParams::for_chain(chain.chain_id);
+        assert_ne!(params.hash_at(1), ConsensusParams::for_chain(1).hash_at(1));
+        let mut repriced = params.clone();
+        repriced.vm.max_gas += 1;
+        assert_ne!(params.hash_at(1), repriced.hash_at(1));
+
+        // Once a versioned header is applied, legacy headers are no longer accepted.
+        chain
+            .apply_block(&block, &DeterministicScriptEngine::default())
+            .expect("block applies");
+        assert_eq!(chain.header_version, BLOCK_HEADER_VERSION);
+        let legacy = legacy_block(&engine, &chain);
+        assert!(matches!(
+            engine.validate_block(&chain, &legacy),
+            Err(ConsensusError::InvalidBlock)
+        ));
+    }
+
     #[test]
     fn validate_block_rejects_wrong_parent_hash() {
         let engine = DummyConsensusEngine::default();
@@ -333,6 +586,8 @@ mod tests {
                 kind: TransactionKind::Transfer,
                 inputs: Vec::new(),
                 outputs: Vec::new(),
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: qcoin_types::TransactionWitness::default(),
         };
@@ -443,4 +698,39 @@ mod tests {
         );
         assert!(matches!(result, Err(ConsensusError::InvalidBlock)));
     }
+
+    #[test]
+    fn history_replays_after_a_later_gas_schedule_is_added() {
+        let engine = DummyConsensusEngine::default();
+        let mut chain = ChainState::default();
+
+        // The rules blocks were produced under, and the same rules with a schedule
+        // added from height 50.
+        let mut produced_under = ConsensusParams::for_chain(chain.chain_id);
+        produced_under.vm.gas_schedules = GasScheduleTable::new(GasSchedule::legacy());
+        let mut repriced = GasSchedule::legacy();
+        repriced.sig_base = 1;
+        let mut extended = produced_under.clone();
+        extended.vm.gas_schedules = extended.vm.gas_schedules.with_activation(50, repriced);
+        for height in [1, 49] {
+            assert_eq!(produced_under.hash_at(height), extended.hash_at(height));
+        }
+        assert_ne!(produced_under.hash_at(50), extended.hash_at(50));
+
+        // Stored blocks committed to the single legacy schedule; the current rules,
+        // which schedule repricing at a later height, still replay them.
+        for _ in 0..2 {
+            let mut block = engine
+                .propose_block(&chain, Vec::new())
+                .expect("block should be proposed");
+            block.header.timestamp = block.header.timestamp.max(chain.last_timestamp + 1);
+            block.header.consensus_params_hash = produced_under.hash_at(block.header.height);
+            sign_header(&engine, &mut block);
+            validate_replayed_block(&default_registry(), &chain, &block, &[])
+                .expect("history from before the new schedule replays");
+            chain
+                .apply_block(&block, &DeterministicScriptEngine::default())
+                .expect("block applies");
+        }
+    }
 }
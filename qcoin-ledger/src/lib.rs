@@ -1,11 +1,17 @@
 use std::collections::{HashMap, HashSet};
 
+use bincode::Options;
 use qcoin_script::{
-    consensus_codec as script_codec, ResolvedInput, Script, ScriptContext, ScriptEngine, ScriptHost,
+    tree as script_tree, DeterministicScriptEngine, ResolvedInput, Script, ScriptContext,
+    ScriptEngine, ScriptEnvelope, ScriptError, ScriptHost, ScriptTrace,
 };
 use qcoin_types::{
-    consensus_codec, derive_asset_id, is_qcoin_asset_id, AssetAmount, AssetDefinition, AssetId,
-    Block, Hash256, Output, Transaction, TransactionKind,
+    consensus_codec, derive_asset_id, derive_collection_id, derive_collection_item_id,
+    derive_sub_asset_id, is_qcoin_asset_id, AssetAmount, AssetDefinition, AssetId, AssetKind,
+    Block, ChainActivations, CollectionDefinition, CollectionId, Hash256, InputWitness,
+    IssuerControls, Output, RoyaltyAmount, RoyaltyRule, SalePrice, Transaction, TransactionCore,
+    TransactionInput, TransactionKind, TransferMode, BLOCK_HEADER_VERSION,
+    BLOCK_HEADER_VERSION_SUPPLY, MAX_ROYALTY_BASIS_POINTS,
 };
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
@@ -20,14 +26,85 @@ pub struct UtxoKey {
 pub struct TrackedOutput {
     pub output: Output,
     pub created_height: u64,
+    /// Median-time-past the creating block was validated at. Not hashed into the state
+    /// root: it is fixed by `created_height` and the header timestamps below it.
+    #[serde(default)]
+    pub created_time: u64,
 }
 
 pub type UtxoSet = HashMap<UtxoKey, TrackedOutput>;
 
+/// A sub-identifier minted under a `SemiFungible` class.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubAsset {
+    pub class_id: AssetId,
+    pub sub_id: u64,
+}
+
+/// Membership of an asset created by `CreateCollectionItem`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionItem {
+    pub collection_id: CollectionId,
+    pub serial: u64,
+}
+
+/// An unspent output frozen by the issuer of `asset_id` through `FreezeOutputs`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FrozenOutput {
+    pub key: UtxoKey,
+    pub asset_id: AssetId,
+}
+
+/// A metadata root set by `UpdateAssetMetadata`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetadataUpdate {
+    pub metadata_root: Hash256,
+    pub height: u64,
+    pub tx_id: Hash256,
+}
+
+/// Units of an asset minted at creation and burned by holders since.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssetSupply {
+    pub minted: u128,
+    pub burned: u128,
+}
+
+impl AssetSupply {
+    /// Units still held in outputs, or `None` if more were burned than minted, which
+    /// the ledger never allows.
+    pub fn circulating(&self) -> Option<u128> {
+        self.minted.checked_sub(self.burned)
+    }
+}
+
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct LedgerState {
     pub utxos: UtxoSet,
     pub assets: HashMap<AssetId, AssetDefinition>,
+    /// Sub-asset ids, keyed by [`derive_sub_asset_id`], and the class they belong to.
+    #[serde(default)]
+    pub sub_assets: HashMap<AssetId, SubAsset>,
+    /// `NonFungible` assets created by `CreateAsset` below the chain's
+    /// [`ChainActivations::asset_kind_rules`] height. They keep moving as any number of
+    /// units.
+    #[serde(default)]
+    pub legacy_non_fungible: HashSet<AssetId>,
+    #[serde(default)]
+    pub collections: HashMap<CollectionId, CollectionDefinition>,
+    /// Collection items, keyed by [`derive_collection_item_id`].
+    #[serde(default)]
+    pub collection_items: HashMap<AssetId, CollectionItem>,
+    /// Outputs that cannot be spent until their asset's issuer unfreezes or claws them.
+    #[serde(default)]
+    pub frozen_outputs: HashSet<FrozenOutput>,
+    /// Metadata updates per asset, oldest first. The last one is the current root.
+    #[serde(default)]
+    pub metadata_updates: HashMap<AssetId, Vec<MetadataUpdate>>,
+    /// Supply counters per minted id: the asset itself, or each sub-asset of a
+    /// semi-fungible class.
+    #[serde(default)]
+    pub asset_supply: HashMap<AssetId, AssetSupply>,
 }
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
@@ -38,8 +115,21 @@ pub struct ChainState {
     pub state_root: Hash256,
     pub last_timestamp: u64,
     pub chain_id: u32,
+    /// Timestamps of the last [`MEDIAN_TIME_SPAN`] blocks, oldest first.
+    #[serde(default)]
+    pub recent_timestamps: Vec<u64>,
+    /// Set once a block with a witness root is applied. From then on every block must
+    /// commit to its witnesses.
+    #[serde(default)]
+    pub witness_commitments: bool,
+    /// Highest header version applied. Blocks with an older header version are rejected.
+    #[serde(default)]
+    pub header_version: u32,
 }
 
+/// Number of recent block timestamps whose median is the chain's median-time-past.
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
 #[derive(Debug, Error)]
 pub enum LedgerError {
     #[error("input not found in UTXO set")]
@@ -50,12 +140,17 @@ pub enum LedgerError {
     DoubleSpend,
     #[error("owner script hash does not match provided script")]
     ScriptHashMismatch,
+    #[error("script tree path does not lead to owner script hash")]
+    ScriptPathMismatch,
     #[error("output metadata hash does not match provided metadata")]
     MetadataHashMismatch,
     #[error("failed to decode witness data")]
     InvalidWitness,
-    #[error("script execution failed")]
-    ScriptFailed,
+    #[error("script execution failed for input {input_index}: {source}")]
+    ScriptFailed {
+        input_index: usize,
+        source: ScriptError,
+    },
     #[error("asset conservation violated")]
     AssetConservationViolation,
     #[error("asset already exists")]
@@ -66,16 +161,109 @@ pub enum LedgerError {
     MissingIssuerAuthorization,
     #[error("asset supply exceeds declared maximum")]
     MaxSupplyExceeded,
+    #[error("non-fungible asset must have a supply of exactly 1 and no decimals")]
+    InvalidNonFungibleSupply,
+    #[error("non-fungible asset must move as a single unit")]
+    NonFungibleSplit,
+    #[error("sub-ids must be ascending, each minted, and only for semi-fungible assets")]
+    InvalidSubAssets,
+    #[error("collection already exists")]
+    CollectionAlreadyExists,
+    #[error("collection not found")]
+    UnknownCollection,
+    #[error("collection item issuer does not match the collection issuer")]
+    CollectionIssuerMismatch,
+    #[error("soulbound asset cannot move to another owner")]
+    AssetNotTransferable,
+    #[error("asset transfer requires an input owned by the issuer")]
+    TransferNotApproved,
+    #[error("asset not found")]
+    UnknownAsset,
+    #[error("asset does not enable this issuer control")]
+    IssuerControlDisabled,
+    #[error("issuer control target is not an eligible output of the asset")]
+    InvalidIssuerControlTarget,
+    #[error("assets held alongside clawed-back units must be returned to their holder")]
+    ClawbackNotReturned,
+    #[error("input is frozen by its asset issuer")]
+    OutputFrozen,
+    #[error("royalty must be between 1 and {MAX_ROYALTY_BASIS_POINTS} basis points or a positive fixed amount")]
+    InvalidRoyalty,
+    #[error("royalty of {required} not paid to its recipient (paid {paid})")]
+    RoyaltyUnpaid { required: u128, paid: u128 },
+    #[error("asset sold under a percentage royalty needs a declared sale price")]
+    MissingSalePrice,
+    #[error("declared sale price {declared} is below the {received} its sellers received")]
+    SalePriceUnderstated { declared: u128, received: u128 },
+    #[error("sale prices must be listed once each, by asset id, for assets sold under a percentage royalty")]
+    InvalidSalePrices,
+    #[error("metadata update does not change the current metadata root")]
+    MetadataUnchanged,
+    #[error("asset supply counters do not match the units minted and burned")]
+    SupplyInvariantViolated,
+    #[error("transaction is not valid before height {valid_from_height} (block height {height})")]
+    TransactionNotYetValid { valid_from_height: u64, height: u64 },
+    #[error("transaction expired after height {valid_until_height} (block height {height})")]
+    TransactionExpired {
+        valid_until_height: u64,
+        height: u64,
+    },
     #[error("other ledger error: {0}")]
     Other(String),
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct InputWitness {
+/// Bincode witness layout used before the canonical [`InputWitness`] encoding.
+#[derive(Deserialize)]
+struct LegacyInputWitness {
     script: Script,
     metadata: Option<Vec<u8>>,
 }
 
+/// Decodes an input witness: the canonical encoding, or the bincode layout written
+/// before it. Both must consume every byte, so no other bytes decode to the same
+/// witness in either form. The canonical magic cannot start a legacy witness that fits
+/// in a transaction.
+pub fn decode_input_witness(bytes: &[u8]) -> Result<InputWitness, LedgerError> {
+    if let Ok(witness) = InputWitness::decode(bytes) {
+        return Ok(witness);
+    }
+
+    let legacy: LegacyInputWitness = bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(bytes)
+        .map_err(|_| LedgerError::InvalidWitness)?;
+    Ok(InputWitness {
+        metadata: legacy.metadata,
+        ..ScriptEnvelope::legacy(&legacy.script).witness(Vec::new())
+    })
+}
+
+/// Script trace for one input, as produced by [`LedgerState::trace_transaction`].
+#[derive(Debug)]
+pub struct InputTrace {
+    pub input_index: usize,
+    pub result: Result<(), LedgerError>,
+    pub trace: ScriptTrace,
+}
+
+struct PreparedInput {
+    referenced_output: TrackedOutput,
+    script: ScriptEnvelope,
+    ctx: ScriptContext,
+}
+
+/// Scripts revealed by the input witnesses of `tx`, in input order. They are not checked
+/// against the outputs being spent; this is for relay policy, which inspects scripts
+/// before a transaction is validated.
+pub fn input_scripts(tx: &Transaction) -> Result<Vec<ScriptEnvelope>, LedgerError> {
+    tx.witness
+        .inputs
+        .iter()
+        .map(|bytes| decode_input_witness(bytes).map(|witness| ScriptEnvelope::from(&witness)))
+        .collect()
+}
+
 fn hash_bytes(data: &[u8]) -> Hash256 {
     *blake3::hash(data).as_bytes()
 }
@@ -83,13 +271,15 @@ fn hash_bytes(data: &[u8]) -> Hash256 {
 struct LedgerScriptHost<'a> {
     utxos: &'a UtxoSet,
     current_height: u64,
+    current_time: u64,
 }
 
 impl<'a> LedgerScriptHost<'a> {
-    fn new(utxos: &'a UtxoSet, current_height: u64) -> Self {
+    fn new(utxos: &'a UtxoSet, current_height: u64, current_time: u64) -> Self {
         Self {
             utxos,
             current_height,
+            current_time,
         }
     }
 }
@@ -99,6 +289,10 @@ impl ScriptHost for LedgerScriptHost<'_> {
         Some(self.current_height)
     }
 
+    fn current_time(&self) -> Option<u64> {
+        Some(self.current_time)
+    }
+
     fn input_utxo(&self, input: &qcoin_types::TransactionInput) -> Option<ResolvedInput> {
         let key = UtxoKey {
             tx_id: input.tx_id,
@@ -108,12 +302,21 @@ impl ScriptHost for LedgerScriptHost<'_> {
         self.utxos.get(&key).map(|tracked| ResolvedInput {
             output: tracked.output.clone(),
             created_height: Some(tracked.created_height),
+            created_time: Some(tracked.created_time),
         })
     }
 }
 
 impl LedgerState {
+    /// State root committed by headers of the current [`BLOCK_HEADER_VERSION`].
     pub fn state_root(&self) -> Hash256 {
+        self.state_root_at(BLOCK_HEADER_VERSION)
+    }
+
+    /// State root committed by a header of `header_version`. Supply counters and the
+    /// pre-rules non-fungible assets are only committed from
+    /// [`BLOCK_HEADER_VERSION_SUPPLY`], so older headers keep their roots.
+    pub fn state_root_at(&self, header_version: u32) -> Hash256 {
         let mut entries: Vec<_> = self.utxos.iter().collect();
         entries.sort_by(|(a, _), (b, _)| a.tx_id.cmp(&b.tx_id).then_with(|| a.index.cmp(&b.index)));
 
@@ -139,38 +342,516 @@ impl LedgerState {
             hasher.update(&encoded);
         }
 
+        let mut sub_assets: Vec<_> = self.sub_assets.iter().collect();
+        sub_assets.sort_by_key(|(asset_id, _)| asset_id.0);
+
+        for (asset_id, sub_asset) in sub_assets {
+            let mut encoded = Vec::new();
+            encoded.extend_from_slice(&asset_id.0);
+            encoded.extend_from_slice(&sub_asset.class_id.0);
+            encoded.extend_from_slice(&sub_asset.sub_id.to_le_bytes());
+            hasher.update(&encoded);
+        }
+
+        let mut collections: Vec<_> = self.collections.iter().collect();
+        collections.sort_by_key(|(collection_id, _)| collection_id.0);
+
+        for (collection_id, collection) in collections {
+            let mut encoded = Vec::new();
+            encoded.extend_from_slice(&collection_id.0);
+            encoded.extend(consensus_codec::encode_collection_definition(collection));
+            hasher.update(&encoded);
+        }
+
+        let mut collection_items: Vec<_> = self.collection_items.iter().collect();
+        collection_items.sort_by_key(|(asset_id, _)| asset_id.0);
+
+        for (asset_id, item) in collection_items {
+            let mut encoded = Vec::new();
+            encoded.extend_from_slice(&asset_id.0);
+            encoded.extend_from_slice(&item.collection_id.0);
+            encoded.extend_from_slice(&item.serial.to_le_bytes());
+            hasher.update(&encoded);
+        }
+
+        let mut frozen_outputs: Vec<_> = self.frozen_outputs.iter().collect();
+        frozen_outputs
+            .sort_by_key(|frozen| (frozen.key.tx_id, frozen.key.index, frozen.asset_id.0));
+
+        for frozen in frozen_outputs {
+            let mut encoded = Vec::new();
+            encoded.extend_from_slice(&frozen.key.tx_id);
+            encoded.extend_from_slice(&frozen.key.index.to_le_bytes());
+            encoded.extend_from_slice(&frozen.asset_id.0);
+            hasher.update(&encoded);
+        }
+
+        let mut metadata_updates: Vec<_> = self.metadata_updates.iter().collect();
+        metadata_updates.sort_by_key(|(asset_id, _)| asset_id.0);
+
+        for (asset_id, updates) in metadata_updates {
+            for update in updates {
+                let mut encoded = Vec::new();
+                encoded.extend_from_slice(&asset_id.0);
+                encoded.extend_from_slice(&update.metadata_root);
+                encoded.extend_from_slice(&update.height.to_le_bytes());
+                encoded.extend_from_slice(&update.tx_id);
+                hasher.update(&encoded);
+            }
+        }
+
+        if header_version >= BLOCK_HEADER_VERSION_SUPPLY {
+            let mut supply: Vec<_> = self.asset_supply.iter().collect();
+            supply.sort_by_key(|(asset_id, _)| asset_id.0);
+
+            for (asset_id, counters) in supply {
+                let mut encoded = Vec::new();
+                encoded.extend_from_slice(&asset_id.0);
+                encoded.extend_from_slice(&counters.minted.to_le_bytes());
+                encoded.extend_from_slice(&counters.burned.to_le_bytes());
+                hasher.update(&encoded);
+            }
+
+            let mut legacy_non_fungible: Vec<_> = self.legacy_non_fungible.iter().collect();
+            legacy_non_fungible.sort_by_key(|asset_id| asset_id.0);
+
+            for asset_id in legacy_non_fungible {
+                hasher.update(&asset_id.0);
+            }
+        }
+
         *hasher.finalize().as_bytes()
     }
 
+    /// Supply of `asset_id`. A semi-fungible class reports the sum over its sub-assets.
+    pub fn asset_supply(&self, asset_id: &AssetId) -> Option<AssetSupply> {
+        if self.sub_assets.contains_key(asset_id) {
+            return Some(self.supply_counters(asset_id));
+        }
+        self.assets.get(asset_id)?;
+        let sub_ids = self
+            .sub_assets
+            .iter()
+            .filter(|(_, sub_asset)| sub_asset.class_id == *asset_id)
+            .map(|(sub_id, _)| sub_id);
+        Some(
+            std::iter::once(asset_id)
+                .chain(sub_ids)
+                .map(|id| self.supply_counters(id))
+                .fold(AssetSupply::default(), |total, supply| AssetSupply {
+                    minted: total.minted.saturating_add(supply.minted),
+                    burned: total.burned.saturating_add(supply.burned),
+                }),
+        )
+    }
+
+    /// Counters for a single id. Ids created before counters were kept are seeded
+    /// from the units they still hold, with nothing burned yet.
+    fn supply_counters(&self, asset_id: &AssetId) -> AssetSupply {
+        if let Some(supply) = self.asset_supply.get(asset_id) {
+            return supply.clone();
+        }
+        let held = self
+            .utxos
+            .values()
+            .flat_map(|tracked| tracked.output.assets.iter())
+            .filter(|asset| asset.asset_id == *asset_id)
+            .fold(0u128, |total, asset| total.saturating_add(asset.amount));
+        AssetSupply {
+            minted: held,
+            burned: 0,
+        }
+    }
+
+    /// Number of distinct owners holding units of `asset_id`, or of any sub-asset of
+    /// it when it is a semi-fungible class.
+    pub fn holder_count(&self, asset_id: &AssetId) -> usize {
+        let holds = |held: &AssetId| {
+            held == asset_id
+                || self
+                    .sub_assets
+                    .get(held)
+                    .is_some_and(|sub_asset| sub_asset.class_id == *asset_id)
+        };
+        self.utxos
+            .values()
+            .filter(|tracked| {
+                tracked
+                    .output
+                    .assets
+                    .iter()
+                    .any(|asset| asset.amount > 0 && holds(&asset.asset_id))
+            })
+            .map(|tracked| tracked.output.owner_script_hash)
+            .collect::<HashSet<_>>()
+            .len()
+    }
+
+    /// The definition governing `asset_id`: its own, or its class's for a sub-asset.
+    pub fn asset_definition(&self, asset_id: &AssetId) -> Option<&AssetDefinition> {
+        match self.sub_assets.get(asset_id) {
+            Some(sub_asset) => self.assets.get(&sub_asset.class_id),
+            None => self.assets.get(asset_id),
+        }
+    }
+
+    /// The metadata root `asset_id` currently points to: its latest update, or the root
+    /// in its definition. A sub-asset shares its class's root.
+    pub fn current_metadata_root(&self, asset_id: &AssetId) -> Option<Hash256> {
+        let class_id = match self.sub_assets.get(asset_id) {
+            Some(sub_asset) => &sub_asset.class_id,
+            None => asset_id,
+        };
+        let definition = self.assets.get(class_id)?;
+        Some(
+            self.metadata_history(class_id)
+                .last()
+                .map_or(definition.metadata_root, |update| update.metadata_root),
+        )
+    }
+
+    /// Metadata updates applied to `asset_id`, oldest first.
+    pub fn metadata_history(&self, asset_id: &AssetId) -> &[MetadataUpdate] {
+        self.metadata_updates
+            .get(asset_id)
+            .map_or(&[], |updates| updates.as_slice())
+    }
+
+    /// Whether `key` is frozen for any asset it holds.
+    pub fn is_frozen(&self, key: &UtxoKey) -> bool {
+        self.utxos.get(key).is_some_and(|tracked| {
+            tracked.output.assets.iter().any(|asset| {
+                self.frozen_outputs.contains(&FrozenOutput {
+                    key: key.clone(),
+                    asset_id: asset.asset_id.clone(),
+                })
+            })
+        })
+    }
+
+    /// The definition of `asset_id`, if its issuer enabled the control `enabled` picks.
+    fn issuer_controlled_asset(
+        &self,
+        asset_id: &AssetId,
+        enabled: impl Fn(&IssuerControls) -> bool,
+    ) -> Result<&AssetDefinition, LedgerError> {
+        let definition = self
+            .asset_definition(asset_id)
+            .ok_or(LedgerError::UnknownAsset)?;
+        if !enabled(&definition.issuer_controls) {
+            return Err(LedgerError::IssuerControlDisabled);
+        }
+        Ok(definition)
+    }
+
+    /// Items of `collection_id` as `(serial, asset_id)`, ordered by serial.
+    pub fn collection_items(&self, collection_id: &CollectionId) -> Vec<(u64, AssetId)> {
+        let mut items: Vec<_> = self
+            .collection_items
+            .iter()
+            .filter(|(_, item)| item.collection_id == *collection_id)
+            .map(|(asset_id, item)| (item.serial, asset_id.clone()))
+            .collect();
+        items.sort_by_key(|(serial, _)| *serial);
+        items
+    }
+
+    /// Resolves an input, decodes its witness, and checks the witness commitments
+    /// (script hash and metadata) that must hold before the script is evaluated.
+    fn prepare_input(
+        &self,
+        tx: &Transaction,
+        input_index: usize,
+        current_height: u64,
+        chain_id: u32,
+    ) -> Result<PreparedInput, LedgerError> {
+        let input = &tx.core.inputs[input_index];
+        let key = UtxoKey {
+            tx_id: input.tx_id,
+            index: input.index,
+        };
+
+        let referenced_output = self
+            .utxos
+            .get(&key)
+            .cloned()
+            .ok_or(LedgerError::MissingInput)?;
+
+        let witness_bytes = tx
+            .witness
+            .inputs
+            .get(input_index)
+            .ok_or(LedgerError::MissingWitness)?;
+
+        let witness = decode_input_witness(witness_bytes)?;
+        let script = ScriptEnvelope::from(&witness);
+
+        // The executed script's own commitment: its plain hash, or its leaf hash when it
+        // is revealed as one branch of a script tree.
+        let script_hash = match witness.merkle_path.as_deref() {
+            None => {
+                let script_hash = script.script_hash();
+                if script_hash != referenced_output.output.owner_script_hash {
+                    return Err(LedgerError::ScriptHashMismatch);
+                }
+                script_hash
+            }
+            Some(path) => {
+                if path.len() > script_tree::MAX_PATH_LEN {
+                    return Err(LedgerError::InvalidWitness);
+                }
+                let leaf = script_tree::envelope_leaf_hash(&script);
+                if script_tree::root_from_path(leaf, path)
+                    != referenced_output.output.owner_script_hash
+                {
+                    return Err(LedgerError::ScriptPathMismatch);
+                }
+                leaf
+            }
+        };
+
+        match (
+            referenced_output.output.metadata_hash.as_ref(),
+            witness.metadata.as_ref(),
+        ) {
+            (Some(expected), Some(bytes)) if *expected == hash_bytes(bytes) => {}
+            (None, None) => {}
+            _ => return Err(LedgerError::MetadataHashMismatch),
+        }
+
+        let ctx = ScriptContext {
+            tx: tx.clone(),
+            input_index,
+            current_height: Some(current_height),
+            chain_id,
+            script_hash,
+            witness: witness.stack,
+        };
+
+        Ok(PreparedInput {
+            referenced_output,
+            script,
+            ctx,
+        })
+    }
+
+    /// Replays the input scripts of `tx` against this UTXO set without applying it,
+    /// recording an execution trace per input. Inputs that fail before evaluation
+    /// (missing UTXO, bad witness) are reported with an empty trace.
+    pub fn trace_transaction(
+        &self,
+        tx: &Transaction,
+        engine: &DeterministicScriptEngine,
+        current_height: u64,
+        current_time: u64,
+        chain_id: u32,
+    ) -> Vec<InputTrace> {
+        let host = LedgerScriptHost::new(&self.utxos, current_height, current_time);
+
+        (0..tx.core.inputs.len())
+            .map(|input_index| {
+                let prepared = match self.prepare_input(tx, input_index, current_height, chain_id) {
+                    Ok(prepared) => prepared,
+                    Err(err) => {
+                        return InputTrace {
+                            input_index,
+                            result: Err(err),
+                            trace: ScriptTrace::default(),
+                        }
+                    }
+                };
+
+                let (result, trace) =
+                    engine.eval_envelope_traced(&prepared.script, &prepared.ctx, &host);
+                InputTrace {
+                    input_index,
+                    result: result
+                        .map(|_| ())
+                        .map_err(|source| LedgerError::ScriptFailed {
+                            input_index,
+                            source,
+                        }),
+                    trace,
+                }
+            })
+            .collect()
+    }
+
+    /// Validates and applies `tx` in a block at `current_height`, whose time-based locks
+    /// are checked against `current_time` (the parent chain's median-time-past).
     pub fn apply_transaction<E: ScriptEngine>(
         &mut self,
         tx: &Transaction,
         engine: &E,
         current_height: u64,
+        current_time: u64,
         chain_id: u32,
     ) -> Result<(), LedgerError> {
         let mut seen_inputs = HashSet::new();
         let mut consumed_utxos = Vec::new();
         let mut input_totals: HashMap<Hash256, u128> = HashMap::new();
         let mut output_totals: HashMap<Hash256, u128> = HashMap::new();
-        let host = LedgerScriptHost::new(&self.utxos, current_height);
+        // Supply changes, applied once the transaction is valid.
+        let mut minted: Vec<(AssetId, u128)> = Vec::new();
+        let mut burned: Vec<(AssetId, u128)> = Vec::new();
+        // Per (asset id, owner script hash), for transfer policies.
+        let mut owner_inputs: HashMap<(Hash256, Hash256), u128> = HashMap::new();
+        let mut owner_outputs: HashMap<(Hash256, Hash256), u128> = HashMap::new();
+        let mut input_owners = HashSet::new();
+        let host = LedgerScriptHost::new(&self.utxos, current_height, current_time);
         let mut issuer_authorized = false;
         let mut created_asset: Option<(AssetId, AssetDefinition, u128)> = None;
+        let mut created_sub_assets: Vec<(AssetId, SubAsset)> = Vec::new();
+        let mut created_legacy_non_fungible = false;
+        let mut created_collection: Option<(CollectionId, CollectionDefinition)> = None;
+        let mut created_item: Option<(AssetId, CollectionItem)> = None;
+        // Issuer whose input must authorize an issuer control or metadata update.
+        let mut controlling_issuer: Option<Hash256> = None;
+        let mut frozen_changes: Vec<FrozenOutput> = Vec::new();
+        let mut freezing = false;
+        let mut clawed_back: Vec<UtxoKey> = Vec::new();
+        // Per (asset id, holder), what a clawback must hand back to the targets' holders.
+        let mut returned_to_holders: HashMap<(Hash256, Hash256), u128> = HashMap::new();
+        let mut metadata_update: Option<(AssetId, Hash256)> = None;
+
+        check_validity_window(&tx.core, current_height)?;
+
+        match &tx.core.kind {
+            TransactionKind::Transfer | TransactionKind::Sale { .. } => {}
+            TransactionKind::CreateAsset {
+                definition,
+                initial_supply,
+                sub_ids,
+            } => {
+                let asset_id = derive_asset_id(definition, chain_id);
+                if is_qcoin_asset_id(&asset_id) {
+                    return Err(LedgerError::ReservedNativeAssetId);
+                }
+                if self.assets.contains_key(&asset_id) {
+                    return Err(LedgerError::AssetAlreadyExists);
+                }
+                // Sub-ids are newer than the kind rules, so a mint using them is always
+                // checked. Assets created below the chain's activation were never held to
+                // the rules, so history that created or split them still replays.
+                let rules_height = ChainActivations::for_chain(chain_id).asset_kind_rules;
+                if current_height >= rules_height || !sub_ids.is_empty() {
+                    check_asset_kind(definition, *initial_supply, sub_ids)?;
+                } else {
+                    created_legacy_non_fungible = definition.kind == AssetKind::NonFungible;
+                }
+                check_royalty(definition)?;
 
-        if let TransactionKind::CreateAsset {
-            definition,
-            initial_supply,
-        } = &tx.core.kind
-        {
-            let asset_id = derive_asset_id(definition, chain_id);
-            if is_qcoin_asset_id(&asset_id) {
-                return Err(LedgerError::ReservedNativeAssetId);
+                created_sub_assets = sub_ids
+                    .iter()
+                    .map(|sub_id| {
+                        let sub_asset = SubAsset {
+                            class_id: asset_id.clone(),
+                            sub_id: *sub_id,
+                        };
+                        (derive_sub_asset_id(&asset_id, *sub_id), sub_asset)
+                    })
+                    .collect();
+                created_asset = Some((asset_id, definition.clone(), *initial_supply));
             }
-            if self.assets.contains_key(&asset_id) {
-                return Err(LedgerError::AssetAlreadyExists);
+            TransactionKind::CreateCollection { collection } => {
+                let collection_id = derive_collection_id(collection, chain_id);
+                if self.collections.contains_key(&collection_id) {
+                    return Err(LedgerError::CollectionAlreadyExists);
+                }
+                created_collection = Some((collection_id, collection.clone()));
+            }
+            TransactionKind::CreateCollectionItem {
+                collection_id,
+                serial,
+                definition,
+                initial_supply,
+            } => {
+                let collection = self
+                    .collections
+                    .get(collection_id)
+                    .ok_or(LedgerError::UnknownCollection)?;
+                if definition.issuer_script_hash != collection.issuer_script_hash {
+                    return Err(LedgerError::CollectionIssuerMismatch);
+                }
+                let asset_id = derive_collection_item_id(collection_id, *serial);
+                if self.assets.contains_key(&asset_id) {
+                    return Err(LedgerError::AssetAlreadyExists);
+                }
+                check_asset_kind(definition, *initial_supply, &[])?;
+                check_royalty(definition)?;
+
+                let item = CollectionItem {
+                    collection_id: collection_id.clone(),
+                    serial: *serial,
+                };
+                created_item = Some((asset_id.clone(), item));
+                created_asset = Some((asset_id, definition.clone(), *initial_supply));
+            }
+            TransactionKind::FreezeOutputs { asset_id, targets }
+            | TransactionKind::UnfreezeOutputs { asset_id, targets } => {
+                freezing = matches!(tx.core.kind, TransactionKind::FreezeOutputs { .. });
+                let definition = self.issuer_controlled_asset(asset_id, |c| c.freeze)?;
+                controlling_issuer = Some(definition.issuer_script_hash);
+
+                for key in control_target_keys(targets, &tx.core.inputs)? {
+                    let holds_asset = self.utxos.get(&key).is_some_and(|tracked| {
+                        tracked
+                            .output
+                            .assets
+                            .iter()
+                            .any(|asset| asset.asset_id == *asset_id)
+                    });
+                    let frozen = FrozenOutput {
+                        key,
+                        asset_id: asset_id.clone(),
+                    };
+                    if !holds_asset || self.frozen_outputs.contains(&frozen) == freezing {
+                        return Err(LedgerError::InvalidIssuerControlTarget);
+                    }
+                    frozen_changes.push(frozen);
+                }
+            }
+            TransactionKind::Clawback { asset_id, targets } => {
+                let definition = self.issuer_controlled_asset(asset_id, |c| c.clawback)?;
+                let issuer = definition.issuer_script_hash;
+                controlling_issuer = Some(issuer);
+
+                for key in control_target_keys(targets, &tx.core.inputs)? {
+                    let tracked = self
+                        .utxos
+                        .get(&key)
+                        .ok_or(LedgerError::InvalidIssuerControlTarget)?;
+                    let assets = &tracked.output.assets;
+                    if !assets.iter().any(|asset| asset.asset_id == *asset_id) {
+                        return Err(LedgerError::InvalidIssuerControlTarget);
+                    }
+                    // Reclaimed units count as held by the issuer, so returning them to
+                    // the issuer is not a move under the asset's transfer policy. Any
+                    // other asset in the output stays with its holder.
+                    let holder = tracked.output.owner_script_hash;
+                    for asset in assets {
+                        accumulate_asset(&mut input_totals, asset);
+                        if asset.asset_id == *asset_id {
+                            accumulate_owned_asset(&mut owner_inputs, issuer, asset);
+                        } else {
+                            accumulate_owned_asset(&mut owner_inputs, holder, asset);
+                            accumulate_owned_asset(&mut returned_to_holders, holder, asset);
+                        }
+                    }
+                    clawed_back.push(key);
+                }
+            }
+            TransactionKind::UpdateAssetMetadata {
+                asset_id,
+                metadata_root,
+            } => {
+                let definition = self.assets.get(asset_id).ok_or(LedgerError::UnknownAsset)?;
+                controlling_issuer = Some(definition.issuer_script_hash);
+                if self.current_metadata_root(asset_id) == Some(*metadata_root) {
+                    return Err(LedgerError::MetadataUnchanged);
+                }
+                metadata_update = Some((asset_id.clone(), *metadata_root));
             }
-
-            created_asset = Some((asset_id, definition.clone(), *initial_supply));
         }
 
         for (input_index, input) in tx.core.inputs.iter().enumerate() {
@@ -183,34 +864,20 @@ impl LedgerState {
                 return Err(LedgerError::DoubleSpend);
             }
 
-            let referenced_output = self
-                .utxos
-                .get(&key)
-                .cloned()
-                .ok_or(LedgerError::MissingInput)?;
-
-            let ctx = ScriptContext {
-                tx: tx.clone(),
-                input_index,
-                current_height: Some(current_height),
-                chain_id,
-                script_hash: referenced_output.output.owner_script_hash,
-            };
-
-            let witness_bytes = tx
-                .witness
-                .inputs
-                .get(input_index)
-                .ok_or(LedgerError::MissingWitness)?;
+            let PreparedInput {
+                referenced_output,
+                script,
+                ctx,
+            } = self.prepare_input(tx, input_index, current_height, chain_id)?;
 
-            let witness: InputWitness =
-                bincode::deserialize(witness_bytes).map_err(|_| LedgerError::InvalidWitness)?;
-
-            let script_bytes = script_codec::encode_script(&witness.script);
-            let script_hash = hash_bytes(&script_bytes);
+            if self.is_frozen(&key) {
+                return Err(LedgerError::OutputFrozen);
+            }
 
-            if script_hash != referenced_output.output.owner_script_hash {
-                return Err(LedgerError::ScriptHashMismatch);
+            if let Some((_, collection)) = &created_collection {
+                if referenced_output.output.owner_script_hash == collection.issuer_script_hash {
+                    issuer_authorized = true;
+                }
             }
 
             if let Some((asset_id, definition, _)) = &created_asset {
@@ -228,35 +895,68 @@ impl LedgerState {
                 }
             }
 
-            match (
-                referenced_output.output.metadata_hash.as_ref(),
-                witness.metadata.as_ref(),
-            ) {
-                (Some(expected), Some(bytes)) if *expected == hash_bytes(bytes) => {}
-                (None, None) => {}
-                _ => return Err(LedgerError::MetadataHashMismatch),
-            }
-
             engine
-                .eval(&witness.script, &ctx, &host)
-                .map_err(|_| LedgerError::ScriptFailed)?;
+                .eval_envelope(&script, &ctx, &host)
+                .map_err(|source| LedgerError::ScriptFailed {
+                    input_index,
+                    source,
+                })?;
 
             consumed_utxos.push(key);
+            let owner = referenced_output.output.owner_script_hash;
+            input_owners.insert(owner);
             for asset in referenced_output.output.assets {
                 accumulate_asset(&mut input_totals, &asset);
+                accumulate_owned_asset(&mut owner_inputs, owner, &asset);
             }
         }
 
         for output in &tx.core.outputs {
             for asset in &output.assets {
                 accumulate_asset(&mut output_totals, asset);
+                accumulate_owned_asset(&mut owner_outputs, output.owner_script_hash, asset);
+
+                let (definition, legacy) = match &created_asset {
+                    Some((asset_id, definition, _)) if *asset_id == asset.asset_id => {
+                        (Some(definition), created_legacy_non_fungible)
+                    }
+                    _ => (
+                        self.asset_definition(&asset.asset_id),
+                        self.legacy_non_fungible.contains(&asset.asset_id),
+                    ),
+                };
+                if matches!(definition, Some(definition) if definition.kind == AssetKind::NonFungible)
+                    && !legacy
+                    && asset.amount != 1
+                {
+                    return Err(LedgerError::NonFungibleSplit);
+                }
             }
         }
 
-        if let Some((asset_id, definition, initial_supply)) = created_asset {
-            let minted_amount = output_totals.get(&asset_id.0).copied().unwrap_or_default();
+        if let Some((asset_id, definition, initial_supply)) = &created_asset {
+            // A semi-fungible class is minted only as its sub-assets, each of which must
+            // receive units; units of the class id itself fail conservation below.
+            let minted_ids = if created_sub_assets.is_empty() {
+                vec![asset_id.clone()]
+            } else {
+                created_sub_assets
+                    .iter()
+                    .map(|(id, _)| id.clone())
+                    .collect()
+            };
+
+            let mut minted_amount = 0u128;
+            for minted_id in &minted_ids {
+                let amount = output_totals.get(&minted_id.0).copied().unwrap_or_default();
+                if amount == 0 && !created_sub_assets.is_empty() {
+                    return Err(LedgerError::InvalidSubAssets);
+                }
+                minted_amount = minted_amount.saturating_add(amount);
+                minted.push((minted_id.clone(), amount));
+            }
 
-            if minted_amount != initial_supply {
+            if minted_amount != *initial_supply {
                 return Err(LedgerError::AssetConservationViolation);
             }
 
@@ -266,7 +966,10 @@ impl LedgerState {
                 }
             }
 
-            if input_totals.get(&asset_id.0).copied().unwrap_or_default() != 0 {
+            if minted_ids
+                .iter()
+                .any(|id| input_totals.get(&id.0).copied().unwrap_or_default() != 0)
+            {
                 return Err(LedgerError::AssetConservationViolation);
             }
 
@@ -274,17 +977,23 @@ impl LedgerState {
                 return Err(LedgerError::MissingIssuerAuthorization);
             }
 
-            output_totals.remove(&asset_id.0);
-            input_totals.remove(&asset_id.0);
-
-            self.assets.insert(asset_id, definition);
+            for minted_id in &minted_ids {
+                output_totals.remove(&minted_id.0);
+                input_totals.remove(&minted_id.0);
+            }
         }
 
         for (asset_id, input_amount) in input_totals.iter() {
             let output_amount = output_totals.get(asset_id).copied().unwrap_or_default();
-            if *input_amount != output_amount {
+            let burnable = self
+                .asset_definition(&AssetId(*asset_id))
+                .is_some_and(|definition| definition.transfer_policy.burnable_by_holder);
+            if *input_amount != output_amount && !(burnable && output_amount < *input_amount) {
                 return Err(LedgerError::AssetConservationViolation);
             }
+            if output_amount < *input_amount {
+                burned.push((AssetId(*asset_id), *input_amount - output_amount));
+            }
         }
 
         for (asset_id, output_amount) in output_totals.iter() {
@@ -293,11 +1002,195 @@ impl LedgerState {
             }
         }
 
-        for key in consumed_utxos {
+        for ((asset_id, holder), amount) in &returned_to_holders {
+            let returned = owner_outputs
+                .get(&(*asset_id, *holder))
+                .copied()
+                .unwrap_or_default();
+            if returned < *amount {
+                return Err(LedgerError::ClawbackNotReturned);
+            }
+        }
+
+        // Assets created here have no definition in `self` yet, so their minted units
+        // are not treated as moves.
+        let mut royalty_assets: HashMap<Hash256, &RoyaltyRule> = HashMap::new();
+        for ((asset_id, owner), output_amount) in &owner_outputs {
+            let Some(definition) = self.asset_definition(&AssetId(*asset_id)) else {
+                continue;
+            };
+            let input_amount = owner_inputs
+                .get(&(*asset_id, *owner))
+                .copied()
+                .unwrap_or_default();
+            if *output_amount <= input_amount {
+                continue;
+            }
+            match definition.transfer_policy.mode {
+                TransferMode::Transferable => {}
+                TransferMode::Soulbound => return Err(LedgerError::AssetNotTransferable),
+                TransferMode::IssuerApproved => {
+                    if !input_owners.contains(&definition.issuer_script_hash) {
+                        return Err(LedgerError::TransferNotApproved);
+                    }
+                }
+            }
+            if let Some(royalty) = &definition.royalty {
+                royalty_assets.insert(*asset_id, royalty);
+            }
+        }
+
+        // What an asset's sellers, the owners whose holdings of it shrank, received is
+        // their gain in the royalty's payment asset. A percentage is charged on the
+        // price declared by the `Sale`, which must cover what the sellers received
+        // here. Royalties are paid as the recipient's own gain in that asset.
+        let owner_gain = |asset_id: Hash256, owner: Hash256| {
+            let input = owner_inputs
+                .get(&(asset_id, owner))
+                .copied()
+                .unwrap_or_default();
+            let output = owner_outputs
+                .get(&(asset_id, owner))
+                .copied()
+                .unwrap_or_default();
+            output.saturating_sub(input)
+        };
+        let declared_prices: &[SalePrice] = match &tx.core.kind {
+            TransactionKind::Sale { prices } => prices,
+            _ => &[],
+        };
+        let percentage_royalty = |asset_id: &AssetId| {
+            royalty_assets
+                .get(&asset_id.0)
+                .is_some_and(|royalty| matches!(royalty.amount, RoyaltyAmount::BasisPoints(_)))
+        };
+        if matches!(tx.core.kind, TransactionKind::Sale { .. })
+            && (declared_prices.is_empty()
+                || !declared_prices
+                    .windows(2)
+                    .all(|pair| pair[0].asset_id.0 < pair[1].asset_id.0)
+                || !declared_prices
+                    .iter()
+                    .all(|sale_price| percentage_royalty(&sale_price.asset_id)))
+        {
+            return Err(LedgerError::InvalidSalePrices);
+        }
+        let mut royalties_due: HashMap<(Hash256, Hash256), u128> = HashMap::new();
+        for (asset_id, royalty) in &royalty_assets {
+            let received = owner_inputs
+                .iter()
+                .filter(|((input_asset, seller), input_amount)| {
+                    input_asset == asset_id
+                        && owner_outputs
+                            .get(&(*input_asset, *seller))
+                            .copied()
+                            .unwrap_or_default()
+                            < **input_amount
+                })
+                .map(|((_, seller), _)| owner_gain(royalty.payment_asset_id.0, *seller))
+                .fold(0u128, u128::saturating_add);
+            let sale_price = match royalty.amount {
+                RoyaltyAmount::Fixed(_) => received,
+                RoyaltyAmount::BasisPoints(_) => {
+                    let declared = declared_prices
+                        .iter()
+                        .find(|sale_price| sale_price.asset_id.0 == *asset_id)
+                        .ok_or(LedgerError::MissingSalePrice)?
+                        .price;
+                    if declared < received {
+                        return Err(LedgerError::SalePriceUnderstated { declared, received });
+                    }
+                    declared
+                }
+            };
+            let due = royalties_due
+                .entry((royalty.recipient_script_hash, royalty.payment_asset_id.0))
+                .or_default();
+            *due = due.saturating_add(royalty.required_payment(sale_price));
+        }
+        for ((recipient, payment_asset_id), required) in royalties_due {
+            let paid = owner_gain(payment_asset_id, recipient);
+            if paid < required {
+                return Err(LedgerError::RoyaltyUnpaid { required, paid });
+            }
+        }
+
+        if created_collection.is_some() && !issuer_authorized {
+            return Err(LedgerError::MissingIssuerAuthorization);
+        }
+
+        if let Some(issuer) = controlling_issuer {
+            if !input_owners.contains(&issuer) {
+                return Err(LedgerError::MissingIssuerAuthorization);
+            }
+        }
+
+        // Units are only minted when an id is created, and never burned beyond what
+        // was minted.
+        let mut supply_updates: Vec<(AssetId, AssetSupply)> = minted
+            .iter()
+            .map(|(asset_id, amount)| {
+                let supply = AssetSupply {
+                    minted: *amount,
+                    burned: 0,
+                };
+                (asset_id.clone(), supply)
+            })
+            .collect();
+        for (asset_id, amount) in &burned {
+            let supply = self.supply_counters(asset_id);
+            let supply = AssetSupply {
+                burned: supply
+                    .burned
+                    .checked_add(*amount)
+                    .ok_or(LedgerError::SupplyInvariantViolated)?,
+                ..supply
+            };
+            if supply.circulating().is_none() {
+                return Err(LedgerError::SupplyInvariantViolated);
+            }
+            supply_updates.push((asset_id.clone(), supply));
+        }
+
+        if let Some((asset_id, definition, _)) = created_asset {
+            if created_legacy_non_fungible {
+                self.legacy_non_fungible.insert(asset_id.clone());
+            }
+            self.assets.insert(asset_id, definition);
+            self.sub_assets.extend(created_sub_assets);
+            self.collection_items.extend(created_item);
+        }
+        self.collections.extend(created_collection);
+
+        self.asset_supply.extend(supply_updates);
+
+        for frozen in frozen_changes {
+            if freezing {
+                self.frozen_outputs.insert(frozen);
+            } else {
+                self.frozen_outputs.remove(&frozen);
+            }
+        }
+        if !clawed_back.is_empty() {
+            self.frozen_outputs
+                .retain(|frozen| !clawed_back.contains(&frozen.key));
+        }
+
+        for key in consumed_utxos.into_iter().chain(clawed_back) {
             self.utxos.remove(&key);
         }
 
         let tx_id = tx.tx_id();
+        if let Some((asset_id, metadata_root)) = metadata_update {
+            self.metadata_updates
+                .entry(asset_id)
+                .or_default()
+                .push(MetadataUpdate {
+                    metadata_root,
+                    height: current_height,
+                    tx_id,
+                });
+        }
         for (index, output) in tx.core.outputs.iter().cloned().enumerate() {
             let key = UtxoKey {
                 tx_id,
@@ -308,6 +1201,7 @@ impl LedgerState {
                 TrackedOutput {
                     output,
                     created_height: current_height,
+                    created_time: current_time,
                 },
             );
         }
@@ -316,28 +1210,149 @@ impl LedgerState {
     }
 }
 
+/// Rejects `core` in a block at `height` outside its `valid_from_height` and
+/// `valid_until_height` bounds, both inclusive.
+pub fn check_validity_window(core: &TransactionCore, height: u64) -> Result<(), LedgerError> {
+    if let Some(valid_from_height) = core.valid_from_height {
+        if height < valid_from_height {
+            return Err(LedgerError::TransactionNotYetValid {
+                valid_from_height,
+                height,
+            });
+        }
+    }
+    if let Some(valid_until_height) = core.valid_until_height {
+        if height > valid_until_height {
+            return Err(LedgerError::TransactionExpired {
+                valid_until_height,
+                height,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Kind-specific rules for a new asset. A `NonFungible` asset is a single indivisible
+/// unit; only a `SemiFungible` class mints sub-ids, and it mints at least one.
+fn check_asset_kind(
+    definition: &AssetDefinition,
+    initial_supply: u128,
+    sub_ids: &[u64],
+) -> Result<(), LedgerError> {
+    match definition.kind {
+        AssetKind::NonFungible => {
+            if initial_supply != 1
+                || definition.decimals != 0
+                || !matches!(definition.max_supply, None | Some(1))
+            {
+                return Err(LedgerError::InvalidNonFungibleSupply);
+            }
+        }
+        AssetKind::SemiFungible => {
+            if sub_ids.is_empty() || sub_ids.windows(2).any(|pair| pair[0] >= pair[1]) {
+                return Err(LedgerError::InvalidSubAssets);
+            }
+            return Ok(());
+        }
+        AssetKind::Fungible => {}
+    }
+    if !sub_ids.is_empty() {
+        return Err(LedgerError::InvalidSubAssets);
+    }
+    Ok(())
+}
+
+/// A royalty rule must charge something, and at most the whole sale price.
+fn check_royalty(definition: &AssetDefinition) -> Result<(), LedgerError> {
+    match definition.royalty.as_ref().map(|royalty| &royalty.amount) {
+        Some(RoyaltyAmount::BasisPoints(basis_points))
+            if *basis_points == 0 || *basis_points > MAX_ROYALTY_BASIS_POINTS =>
+        {
+            Err(LedgerError::InvalidRoyalty)
+        }
+        Some(RoyaltyAmount::Fixed(0)) => Err(LedgerError::InvalidRoyalty),
+        _ => Ok(()),
+    }
+}
+
 fn accumulate_asset(totals: &mut HashMap<Hash256, u128>, asset: &AssetAmount) {
     let entry = totals.entry(asset.asset_id.0).or_insert(0);
     *entry += asset.amount;
 }
 
+/// Keys of an issuer control's `targets`: non-empty, distinct, and not also spent as
+/// regular inputs of the transaction.
+fn control_target_keys(
+    targets: &[TransactionInput],
+    inputs: &[TransactionInput],
+) -> Result<Vec<UtxoKey>, LedgerError> {
+    if targets.is_empty() {
+        return Err(LedgerError::InvalidIssuerControlTarget);
+    }
+    let mut keys = Vec::with_capacity(targets.len());
+    for target in targets {
+        if keys
+            .iter()
+            .any(|key: &UtxoKey| key.tx_id == target.tx_id && key.index == target.index)
+            || inputs.contains(target)
+        {
+            return Err(LedgerError::InvalidIssuerControlTarget);
+        }
+        keys.push(UtxoKey {
+            tx_id: target.tx_id,
+            index: target.index,
+        });
+    }
+    Ok(keys)
+}
+
+fn accumulate_owned_asset(
+    totals: &mut HashMap<(Hash256, Hash256), u128>,
+    owner: Hash256,
+    asset: &AssetAmount,
+) {
+    let entry = totals.entry((asset.asset_id.0, owner)).or_insert(0);
+    *entry += asset.amount;
+}
+
 impl ChainState {
+    /// Median of the last [`MEDIAN_TIME_SPAN`] block timestamps, or 0 before the first
+    /// block. Time-based script locks in the next block are checked against this value,
+    /// which a single proposer cannot move far by choosing its own timestamp.
+    pub fn median_time_past(&self) -> u64 {
+        let mut timestamps = self.recent_timestamps.clone();
+        timestamps.sort_unstable();
+        timestamps.get(timestamps.len() / 2).copied().unwrap_or(0)
+    }
+
     pub fn apply_block<E: ScriptEngine>(
         &mut self,
         block: &Block,
         engine: &E,
     ) -> Result<(), LedgerError> {
+        let median_time_past = self.median_time_past();
         for tx in &block.transactions {
-            self.ledger
-                .apply_transaction(tx, engine, block.header.height, self.chain_id)?;
+            self.ledger.apply_transaction(
+                tx,
+                engine,
+                block.header.height,
+                median_time_past,
+                self.chain_id,
+            )?;
         }
 
         self.height = block.header.height;
         let serialized = consensus_codec::encode_block_header(&block.header);
         let hash = blake3::hash(&serialized);
         self.tip_hash = *hash.as_bytes();
-        self.state_root = self.ledger.state_root();
+        self.state_root = self.ledger.state_root_at(block.header.version);
         self.last_timestamp = block.header.timestamp;
+        self.recent_timestamps.push(block.header.timestamp);
+        if self.recent_timestamps.len() > MEDIAN_TIME_SPAN {
+            self.recent_timestamps.remove(0);
+        }
+        self.witness_commitments |= block.header.commits_witnesses();
+        self.header_version = self.header_version.max(block.header.version);
 
         Ok(())
     }
@@ -346,29 +1361,63 @@ impl ChainState {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use qcoin_crypto::{PublicKey, Signature, SignatureSchemeId};
-    use qcoin_script::{DeterministicScriptEngine, OpCode, Script};
+    use qcoin_crypto::{
+        default_registry, PqSchemeRegistry, PublicKey, Signature, SignatureSchemeId,
+    };
+    use qcoin_script::{
+        templates, DeterministicScriptEngine, HashAlgorithm, OpCode, Script, UnknownVersionPolicy,
+        VmConfig, SCRIPT_VERSION_LEGACY,
+    };
     use qcoin_types::{
         create_asset_transaction, derive_asset_id, AssetId, AssetKind, Block, BlockHeader,
-        TransactionCore, TransactionInput, TransactionKind, TransactionWitness,
+        IssuerControls, SighashFlags, TransactionCore, TransactionInput, TransactionKind,
+        TransactionWitness, TransferPolicy, BLOCK_HEADER_VERSION_LEGACY, LEGACY_WITNESS_ROOT,
+        QCOIN_ASSET_ID,
     };
 
     const TEST_CHAIN_ID: u32 = 0;
 
+    fn kind_rules_height() -> u64 {
+        ChainActivations::for_chain(TEST_CHAIN_ID).asset_kind_rules
+    }
+
     fn simple_script() -> Script {
         Script(vec![OpCode::Nop])
     }
 
     fn script_hash(script: &Script) -> Hash256 {
-        hash_bytes(&script_codec::encode_script(script))
+        script.script_hash()
     }
 
     fn build_witness(script: &Script, metadata: Option<Vec<u8>>) -> Vec<u8> {
-        bincode::serialize(&InputWitness {
-            script: script.clone(),
+        build_witness_with_stack(script, metadata, Vec::new())
+    }
+
+    fn build_witness_with_stack(
+        script: &Script,
+        metadata: Option<Vec<u8>>,
+        stack: Vec<Vec<u8>>,
+    ) -> Vec<u8> {
+        InputWitness {
             metadata,
-        })
-        .expect("witness serialization should succeed")
+            ..ScriptEnvelope::legacy(script).witness(stack)
+        }
+        .encode()
+    }
+
+    fn legacy_header() -> BlockHeader {
+        BlockHeader {
+            version: BLOCK_HEADER_VERSION_LEGACY,
+            parent_hash: Hash256::default(),
+            state_root: Hash256::default(),
+            tx_root: Hash256::default(),
+            height: 0,
+            timestamp: 0,
+            witness_root: LEGACY_WITNESS_ROOT,
+            proposer_id: Hash256::default(),
+            validator_set_hash: Hash256::default(),
+            consensus_params_hash: Hash256::default(),
+        }
     }
 
     fn simple_asset_id() -> AssetId {
@@ -383,6 +1432,7 @@ mod tests {
         TrackedOutput {
             output,
             created_height: 0,
+            created_time: 0,
         }
     }
 
@@ -411,6 +1461,7 @@ mod tests {
                     metadata_hash: None,
                 },
                 created_height: 0,
+                created_time: 0,
             },
         );
     }
@@ -475,7 +1526,7 @@ mod tests {
 
         let engine = DeterministicScriptEngine::default();
         ledger
-            .apply_transaction(&create_tx, &engine, 0, chain_id)
+            .apply_transaction(&create_tx, &engine, 0, 0, chain_id)
             .expect("asset creation should succeed");
 
         let minted_utxo_key = UtxoKey {
@@ -508,6 +1559,8 @@ mod tests {
                     assets: minted_output.output.assets.clone(),
                     metadata_hash: None,
                 }],
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: TransactionWitness {
                 inputs: vec![build_witness(&asset_script, None)],
@@ -515,7 +1568,7 @@ mod tests {
         };
 
         ledger
-            .apply_transaction(&spend_tx, &engine, 1, chain_id)
+            .apply_transaction(&spend_tx, &engine, 1, 0, chain_id)
             .expect("spend transaction should succeed");
 
         assert!(!ledger.utxos.contains_key(&minted_utxo_key));
@@ -552,7 +1605,7 @@ mod tests {
         create_tx.core.outputs[0].assets[0].asset_id = AssetId([9u8; 32]);
 
         let engine = DeterministicScriptEngine::default();
-        let result = ledger.apply_transaction(&create_tx, &engine, 0, TEST_CHAIN_ID);
+        let result = ledger.apply_transaction(&create_tx, &engine, 0, 0, TEST_CHAIN_ID);
 
         assert!(matches!(
             result,
@@ -589,7 +1642,7 @@ mod tests {
         create_tx.core.outputs[0].assets[0].amount = 5;
 
         let engine = DeterministicScriptEngine::default();
-        let result = ledger.apply_transaction(&create_tx, &engine, 0, TEST_CHAIN_ID);
+        let result = ledger.apply_transaction(&create_tx, &engine, 0, 0, TEST_CHAIN_ID);
 
         assert!(matches!(
             result,
@@ -630,7 +1683,7 @@ mod tests {
         });
 
         let engine = DeterministicScriptEngine::default();
-        let result = ledger.apply_transaction(&create_tx, &engine, 0, TEST_CHAIN_ID);
+        let result = ledger.apply_transaction(&create_tx, &engine, 0, 0, TEST_CHAIN_ID);
 
         assert!(matches!(
             result,
@@ -663,14 +1716,14 @@ mod tests {
 
         let engine = DeterministicScriptEngine::default();
         ledger
-            .apply_transaction(&create_tx, &engine, 0, TEST_CHAIN_ID)
+            .apply_transaction(&create_tx, &engine, 0, 0, TEST_CHAIN_ID)
             .expect("first asset creation should succeed");
 
         let mut second_tx = create_tx.clone();
         second_tx.core.inputs.clear();
         second_tx.witness.inputs.clear();
 
-        let result = ledger.apply_transaction(&second_tx, &engine, 0, TEST_CHAIN_ID);
+        let result = ledger.apply_transaction(&second_tx, &engine, 0, 0, TEST_CHAIN_ID);
 
         assert!(matches!(result, Err(LedgerError::AssetAlreadyExists)));
         assert!(ledger
@@ -702,17 +1755,457 @@ mod tests {
         );
 
         let engine = DeterministicScriptEngine::default();
-        let result = ledger.apply_transaction(&create_tx, &engine, 0, TEST_CHAIN_ID);
+        let result = ledger.apply_transaction(&create_tx, &engine, 0, 0, TEST_CHAIN_ID);
 
         assert!(matches!(result, Err(LedgerError::MaxSupplyExceeded)));
     }
 
-    #[test]
-    fn create_asset_requires_issuer_authorization() {
-        let mut ledger = LedgerState::default();
-        let destination_script_hash = script_hash(&simple_script());
-        let metadata_root = [12u8; 32];
-        let issuer_script_hash = [13u8; 32];
+    /// A `kind` asset authorized by a fresh issuer UTXO, minting `mints` units to
+    /// `simple_script`; a `Some` sub-id mints that sub-asset instead of the asset itself.
+    fn create_kind_transaction(
+        ledger: &mut LedgerState,
+        kind: AssetKind,
+        decimals: u8,
+        initial_supply: u128,
+        sub_ids: Vec<u64>,
+        mints: &[(Option<u64>, u128)],
+    ) -> (AssetId, Transaction) {
+        let issuer_script = simple_script();
+        let issuer_key = UtxoKey {
+            tx_id: [40u8; 32],
+            index: 0,
+        };
+        insert_issuer_utxo(ledger, &issuer_script, issuer_key.clone());
+
+        let (definition, mut tx) = build_create_asset_transaction(
+            &issuer_script,
+            script_hash(&simple_script()),
+            [41u8; 32],
+            initial_supply,
+            None,
+            decimals,
+            TEST_CHAIN_ID,
+            issuer_key,
+        );
+        let definition = AssetDefinition { kind, ..definition };
+        let asset_id = derive_asset_id(&definition, TEST_CHAIN_ID);
+        tx.core.kind = TransactionKind::CreateAsset {
+            definition,
+            initial_supply,
+            sub_ids,
+        };
+        tx.core.outputs = mints
+            .iter()
+            .map(|(sub_id, amount)| Output {
+                owner_script_hash: script_hash(&simple_script()),
+                assets: vec![AssetAmount {
+                    asset_id: match sub_id {
+                        Some(sub_id) => derive_sub_asset_id(&asset_id, *sub_id),
+                        None => asset_id.clone(),
+                    },
+                    amount: *amount,
+                }],
+                metadata_hash: None,
+            })
+            .collect();
+        (asset_id, tx)
+    }
+
+    fn spend_all_outputs(tx: &Transaction, assets: Vec<Vec<AssetAmount>>) -> Transaction {
+        let tx_id = tx.tx_id();
+        Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::Transfer,
+                inputs: (0..tx.core.outputs.len() as u32)
+                    .map(|index| TransactionInput { tx_id, index })
+                    .collect(),
+                outputs: assets
+                    .into_iter()
+                    .map(|assets| Output {
+                        owner_script_hash: script_hash(&simple_script()),
+                        assets,
+                        metadata_hash: None,
+                    })
+                    .collect(),
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![build_witness(&simple_script(), None); tx.core.outputs.len()],
+            },
+        }
+    }
+
+    #[test]
+    fn non_fungible_assets_are_single_indivisible_units() {
+        let engine = DeterministicScriptEngine::default();
+        let rules = kind_rules_height();
+        for (decimals, supply, mints) in [
+            (0, 1_000, vec![(None, 1_000)]),
+            (2, 1, vec![(None, 1)]),
+            (0, 2, vec![(None, 1), (None, 1)]),
+        ] {
+            let mut ledger = LedgerState::default();
+            let (_, tx) = create_kind_transaction(
+                &mut ledger,
+                AssetKind::NonFungible,
+                decimals,
+                supply,
+                Vec::new(),
+                &mints,
+            );
+            assert!(matches!(
+                ledger.apply_transaction(&tx, &engine, rules, 0, TEST_CHAIN_ID),
+                Err(LedgerError::InvalidNonFungibleSupply)
+            ));
+        }
+
+        let mut ledger = LedgerState::default();
+        let (asset_id, create) = create_kind_transaction(
+            &mut ledger,
+            AssetKind::NonFungible,
+            0,
+            1,
+            Vec::new(),
+            &[(None, 1)],
+        );
+        ledger
+            .apply_transaction(&create, &engine, rules, 0, TEST_CHAIN_ID)
+            .expect("a single unit should mint");
+
+        let unit = |amount| AssetAmount {
+            asset_id: asset_id.clone(),
+            amount,
+        };
+        let split = spend_all_outputs(&create, vec![vec![unit(1)], vec![unit(0)]]);
+        assert!(matches!(
+            ledger
+                .clone()
+                .apply_transaction(&split, &engine, rules + 1, 0, TEST_CHAIN_ID),
+            Err(LedgerError::NonFungibleSplit)
+        ));
+        let moved = spend_all_outputs(&create, vec![vec![unit(1)]]);
+        ledger
+            .apply_transaction(&moved, &engine, rules + 1, 0, TEST_CHAIN_ID)
+            .expect("the whole unit should move");
+    }
+
+    #[test]
+    fn non_fungible_assets_from_before_the_rules_still_replay() {
+        let engine = DeterministicScriptEngine::default();
+        let block = |height, transactions: Vec<Transaction>| Block {
+            header: BlockHeader {
+                height,
+                ..legacy_header()
+            },
+            transactions,
+            proposer_public_key: PublicKey {
+                scheme: SignatureSchemeId::Dilithium2,
+                bytes: Vec::new(),
+            },
+            signature: Signature {
+                scheme: SignatureSchemeId::Dilithium2,
+                bytes: Vec::new(),
+            },
+        };
+
+        // History from before the rules: five units of a non-fungible asset, later split.
+        let mut chain = ChainState::default();
+        let (asset_id, create) = create_kind_transaction(
+            &mut chain.ledger,
+            AssetKind::NonFungible,
+            0,
+            5,
+            Vec::new(),
+            &[(None, 3), (None, 2)],
+        );
+        let unit = |amount| AssetAmount {
+            asset_id: asset_id.clone(),
+            amount,
+        };
+        let split = spend_all_outputs(&create, vec![vec![unit(4)], vec![unit(1)]]);
+
+        chain
+            .apply_block(&block(1, vec![create]), &engine)
+            .expect("pre-rules supply should replay");
+        chain
+            .apply_block(&block(kind_rules_height(), vec![split]), &engine)
+            .expect("a pre-rules asset keeps splitting after activation");
+        assert!(chain.ledger.legacy_non_fungible.contains(&asset_id));
+
+        // The exemption is committed by headers that commit to supply.
+        let mut without_exemption = chain.ledger.clone();
+        without_exemption.legacy_non_fungible.clear();
+        assert_ne!(chain.ledger.state_root(), without_exemption.state_root());
+        assert_eq!(
+            chain.ledger.state_root_at(1),
+            without_exemption.state_root_at(1)
+        );
+
+        // The same mint is rejected once the rules are active.
+        let mut ledger = LedgerState::default();
+        let (_, create) = create_kind_transaction(
+            &mut ledger,
+            AssetKind::NonFungible,
+            0,
+            5,
+            Vec::new(),
+            &[(None, 3), (None, 2)],
+        );
+        assert!(matches!(
+            ledger.apply_transaction(&create, &engine, kind_rules_height(), 0, TEST_CHAIN_ID),
+            Err(LedgerError::InvalidNonFungibleSupply)
+        ));
+    }
+
+    #[test]
+    fn semi_fungible_classes_mint_distinct_sub_ids() {
+        let engine = DeterministicScriptEngine::default();
+        for (kind, sub_ids, mints, expected) in [
+            (
+                AssetKind::SemiFungible,
+                vec![2, 1],
+                vec![(Some(1), 4), (Some(2), 4)],
+                "unsorted",
+            ),
+            (
+                AssetKind::SemiFungible,
+                vec![],
+                vec![(None, 8)],
+                "no sub-ids",
+            ),
+            (AssetKind::Fungible, vec![1], vec![(Some(1), 8)], "fungible"),
+            (
+                AssetKind::SemiFungible,
+                vec![1, 2],
+                vec![(Some(1), 8)],
+                "unminted sub-id",
+            ),
+        ] {
+            let mut ledger = LedgerState::default();
+            let (_, tx) = create_kind_transaction(&mut ledger, kind, 0, 8, sub_ids, &mints);
+            assert!(
+                matches!(
+                    ledger.apply_transaction(&tx, &engine, kind_rules_height(), 0, TEST_CHAIN_ID),
+                    Err(LedgerError::InvalidSubAssets)
+                ),
+                "{expected}"
+            );
+        }
+
+        // Units of the class id itself are not part of the mint.
+        let mut ledger = LedgerState::default();
+        let (_, tx) = create_kind_transaction(
+            &mut ledger,
+            AssetKind::SemiFungible,
+            0,
+            8,
+            vec![1],
+            &[(Some(1), 8), (None, 1)],
+        );
+        assert!(matches!(
+            ledger.apply_transaction(&tx, &engine, 0, 0, TEST_CHAIN_ID),
+            Err(LedgerError::AssetConservationViolation)
+        ));
+
+        let mut ledger = LedgerState::default();
+        let (class_id, create) = create_kind_transaction(
+            &mut ledger,
+            AssetKind::SemiFungible,
+            0,
+            8,
+            vec![1, 2],
+            &[(Some(1), 5), (Some(2), 3)],
+        );
+        let root_before = ledger.state_root();
+        ledger
+            .apply_transaction(&create, &engine, 0, 0, TEST_CHAIN_ID)
+            .expect("sub-ids should mint");
+        assert_ne!(ledger.state_root(), root_before);
+
+        let sword = derive_sub_asset_id(&class_id, 1);
+        let shield = derive_sub_asset_id(&class_id, 2);
+        assert_eq!(
+            ledger.sub_assets.get(&sword),
+            Some(&SubAsset {
+                class_id: class_id.clone(),
+                sub_id: 1
+            })
+        );
+        assert_eq!(
+            ledger.asset_definition(&shield),
+            ledger.assets.get(&class_id)
+        );
+
+        // Sub-ids of one class are not interchangeable.
+        let amount = |asset_id: &AssetId, amount| AssetAmount {
+            asset_id: asset_id.clone(),
+            amount,
+        };
+        let swap = spend_all_outputs(
+            &create,
+            vec![vec![amount(&sword, 3)], vec![amount(&shield, 5)]],
+        );
+        assert!(matches!(
+            ledger
+                .clone()
+                .apply_transaction(&swap, &engine, 1, 0, TEST_CHAIN_ID),
+            Err(LedgerError::AssetConservationViolation)
+        ));
+        let split = spend_all_outputs(
+            &create,
+            vec![
+                vec![amount(&sword, 2), amount(&shield, 3)],
+                vec![amount(&sword, 3)],
+            ],
+        );
+        ledger
+            .apply_transaction(&split, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("sub-asset units split like fungible units");
+    }
+
+    #[test]
+    fn collection_items_share_the_collection_issuer() {
+        let engine = DeterministicScriptEngine::default();
+        let issuer_script = simple_script();
+        let issuer_key = UtxoKey {
+            tx_id: [50u8; 32],
+            index: 0,
+        };
+        let mut ledger = LedgerState::default();
+        insert_issuer_utxo(&mut ledger, &issuer_script, issuer_key.clone());
+
+        let build = |kind, input: &UtxoKey, assets| Transaction {
+            core: TransactionCore {
+                kind,
+                inputs: vec![TransactionInput {
+                    tx_id: input.tx_id,
+                    index: input.index,
+                }],
+                outputs: vec![Output {
+                    owner_script_hash: script_hash(&issuer_script),
+                    assets,
+                    metadata_hash: None,
+                }],
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![build_witness(&issuer_script, None)],
+            },
+        };
+
+        let foreign = CollectionDefinition {
+            issuer_script_hash: [51u8; 32],
+            metadata_root: [52u8; 32],
+        };
+        let unauthorized = build(
+            TransactionKind::CreateCollection {
+                collection: foreign,
+            },
+            &issuer_key,
+            Vec::new(),
+        );
+        assert!(matches!(
+            ledger
+                .clone()
+                .apply_transaction(&unauthorized, &engine, 0, 0, TEST_CHAIN_ID),
+            Err(LedgerError::MissingIssuerAuthorization)
+        ));
+
+        let collection = CollectionDefinition {
+            issuer_script_hash: script_hash(&issuer_script),
+            metadata_root: [53u8; 32],
+        };
+        let collection_id = derive_collection_id(&collection, TEST_CHAIN_ID);
+        let create_collection = build(
+            TransactionKind::CreateCollection {
+                collection: collection.clone(),
+            },
+            &issuer_key,
+            Vec::new(),
+        );
+        ledger
+            .apply_transaction(&create_collection, &engine, 0, 0, TEST_CHAIN_ID)
+            .expect("issuer should create the collection");
+        assert_eq!(ledger.collections.get(&collection_id), Some(&collection));
+
+        let collection_output = UtxoKey {
+            tx_id: create_collection.tx_id(),
+            index: 0,
+        };
+        let item_id = derive_collection_item_id(&collection_id, 3);
+        let item = |collection_id: &CollectionId, issuer_script_hash| {
+            build(
+                TransactionKind::CreateCollectionItem {
+                    collection_id: collection_id.clone(),
+                    serial: 3,
+                    definition: AssetDefinition {
+                        issuer_script_hash,
+                        metadata_root: [54u8; 32],
+                        max_supply: None,
+                        decimals: 0,
+                        kind: AssetKind::NonFungible,
+                        transfer_policy: TransferPolicy::default(),
+                        issuer_controls: IssuerControls::default(),
+                        royalty: None,
+                    },
+                    initial_supply: 1,
+                },
+                &collection_output,
+                vec![AssetAmount {
+                    asset_id: item_id.clone(),
+                    amount: 1,
+                }],
+            )
+        };
+
+        assert!(matches!(
+            ledger.clone().apply_transaction(
+                &item(&CollectionId([55u8; 32]), script_hash(&issuer_script)),
+                &engine,
+                1,
+                0,
+                TEST_CHAIN_ID
+            ),
+            Err(LedgerError::UnknownCollection)
+        ));
+        assert!(matches!(
+            ledger.clone().apply_transaction(
+                &item(&collection_id, [51u8; 32]),
+                &engine,
+                1,
+                0,
+                TEST_CHAIN_ID
+            ),
+            Err(LedgerError::CollectionIssuerMismatch)
+        ));
+
+        let create_item = item(&collection_id, script_hash(&issuer_script));
+        let root_before = ledger.state_root();
+        ledger
+            .apply_transaction(&create_item, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("issuer should create the item");
+        assert_ne!(ledger.state_root(), root_before);
+        assert_eq!(
+            ledger.collection_items(&collection_id),
+            vec![(3, item_id.clone())]
+        );
+        assert!(ledger.asset_definition(&item_id).is_some());
+
+        // Each serial is created once.
+        assert!(matches!(
+            ledger.apply_transaction(&create_item, &engine, 2, 0, TEST_CHAIN_ID),
+            Err(LedgerError::AssetAlreadyExists)
+        ));
+    }
+
+    #[test]
+    fn create_asset_requires_issuer_authorization() {
+        let mut ledger = LedgerState::default();
+        let destination_script_hash = script_hash(&simple_script());
+        let metadata_root = [12u8; 32];
+        let issuer_script_hash = [13u8; 32];
 
         let (definition, create_tx) = create_asset_transaction(
             issuer_script_hash,
@@ -726,7 +2219,7 @@ mod tests {
         );
 
         let engine = DeterministicScriptEngine::default();
-        let result = ledger.apply_transaction(&create_tx, &engine, 0, TEST_CHAIN_ID);
+        let result = ledger.apply_transaction(&create_tx, &engine, 0, 0, TEST_CHAIN_ID);
 
         assert!(matches!(
             result,
@@ -748,12 +2241,14 @@ mod tests {
                     index: 0,
                 }],
                 outputs: vec![],
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: TransactionWitness::default(),
         };
 
         let engine = DeterministicScriptEngine::default();
-        let result = ledger.apply_transaction(&tx, &engine, 0, TEST_CHAIN_ID);
+        let result = ledger.apply_transaction(&tx, &engine, 0, 0, TEST_CHAIN_ID);
 
         assert!(matches!(result, Err(LedgerError::MissingInput)));
     }
@@ -783,6 +2278,8 @@ mod tests {
                     }],
                     metadata_hash: None,
                 }],
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: TransactionWitness {
                 inputs: vec![build_witness(&simple_script(), None)],
@@ -790,7 +2287,7 @@ mod tests {
         };
 
         let engine = DeterministicScriptEngine::default();
-        let result = ledger.apply_transaction(&spending_tx, &engine, 0, TEST_CHAIN_ID);
+        let result = ledger.apply_transaction(&spending_tx, &engine, 0, 0, TEST_CHAIN_ID);
 
         assert!(matches!(
             result,
@@ -822,6 +2319,8 @@ mod tests {
                     },
                 ],
                 outputs: vec![simple_output()],
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: TransactionWitness {
                 inputs: vec![
@@ -832,7 +2331,7 @@ mod tests {
         };
 
         let engine = DeterministicScriptEngine::default();
-        let result = ledger.apply_transaction(&tx, &engine, 0, TEST_CHAIN_ID);
+        let result = ledger.apply_transaction(&tx, &engine, 0, 0, TEST_CHAIN_ID);
 
         assert!(matches!(result, Err(LedgerError::DoubleSpend)));
         assert!(ledger.utxos.contains_key(&utxo_key));
@@ -858,6 +2357,8 @@ mod tests {
                     index: 0,
                 }],
                 outputs: vec![simple_output()],
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: TransactionWitness {
                 inputs: vec![build_witness(&incorrect_script, None)],
@@ -865,7 +2366,7 @@ mod tests {
         };
 
         let engine = DeterministicScriptEngine::default();
-        let result = ledger.apply_transaction(&tx, &engine, 0, TEST_CHAIN_ID);
+        let result = ledger.apply_transaction(&tx, &engine, 0, 0, TEST_CHAIN_ID);
 
         assert!(matches!(result, Err(LedgerError::ScriptHashMismatch)));
         assert!(ledger.utxos.contains_key(&utxo_key));
@@ -893,6 +2394,8 @@ mod tests {
                     index: 0,
                 }],
                 outputs: vec![simple_output()],
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: TransactionWitness {
                 inputs: vec![build_witness(&simple_script(), Some(b"different".to_vec()))],
@@ -900,7 +2403,7 @@ mod tests {
         };
 
         let engine = DeterministicScriptEngine::default();
-        let result = ledger.apply_transaction(&tx, &engine, 0, TEST_CHAIN_ID);
+        let result = ledger.apply_transaction(&tx, &engine, 0, 0, TEST_CHAIN_ID);
 
         assert!(matches!(result, Err(LedgerError::MetadataHashMismatch)));
         assert!(ledger.utxos.contains_key(&utxo_key));
@@ -928,6 +2431,8 @@ mod tests {
                     index: 0,
                 }],
                 outputs: vec![simple_output()],
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: TransactionWitness {
                 inputs: vec![build_witness(&simple_script(), Some(metadata))],
@@ -936,71 +2441,1691 @@ mod tests {
 
         let engine = DeterministicScriptEngine::default();
         ledger
-            .apply_transaction(&tx, &engine, 0, TEST_CHAIN_ID)
+            .apply_transaction(&tx, &engine, 0, 0, TEST_CHAIN_ID)
             .expect("transaction should succeed");
 
         assert!(!ledger.utxos.contains_key(&utxo_key));
     }
 
     #[test]
-    fn chain_state_apply_block_updates_height_and_tip_hash() {
-        let mut chain = ChainState::default();
-        let previous_tx_id = [11u8; 32];
+    fn validity_window_bounds_inclusion_height() {
         let utxo_key = UtxoKey {
-            tx_id: previous_tx_id,
+            tx_id: [16u8; 32],
             index: 0,
         };
-        chain.ledger.utxos.insert(utxo_key.clone(), simple_utxo());
+        let mut ledger = LedgerState::default();
+        ledger.utxos.insert(utxo_key.clone(), simple_utxo());
 
-        let spend_tx = Transaction {
+        let tx = Transaction {
             core: TransactionCore {
                 kind: TransactionKind::Transfer,
                 inputs: vec![TransactionInput {
-                    tx_id: previous_tx_id,
-                    index: 0,
+                    tx_id: utxo_key.tx_id,
+                    index: utxo_key.index,
                 }],
                 outputs: vec![simple_output()],
+                valid_from_height: Some(5),
+                valid_until_height: Some(7),
             },
             witness: TransactionWitness {
                 inputs: vec![build_witness(&simple_script(), None)],
             },
         };
 
-        let tx_id = spend_tx.tx_id();
-        let tx_root = spend_tx.tx_id();
-        let block = Block {
-            header: BlockHeader {
-                parent_hash: chain.tip_hash,
-                state_root: Hash256::default(),
-                tx_root,
-                height: 1,
-                timestamp: 42,
-            },
-            transactions: vec![spend_tx.clone()],
-            proposer_public_key: PublicKey {
-                scheme: SignatureSchemeId::Dilithium2,
-                bytes: Vec::new(),
-            },
-            signature: Signature {
-                scheme: SignatureSchemeId::Dilithium2,
-                bytes: Vec::new(),
-            },
+        let engine = DeterministicScriptEngine::default();
+        let mut attempt = ledger.clone();
+        assert!(matches!(
+            attempt.apply_transaction(&tx, &engine, 4, 0, TEST_CHAIN_ID),
+            Err(LedgerError::TransactionNotYetValid {
+                valid_from_height: 5,
+                height: 4
+            })
+        ));
+        assert!(matches!(
+            attempt.apply_transaction(&tx, &engine, 8, 0, TEST_CHAIN_ID),
+            Err(LedgerError::TransactionExpired {
+                valid_until_height: 7,
+                height: 8
+            })
+        ));
+        assert_eq!(attempt.utxos, ledger.utxos);
+
+        for height in [5, 7] {
+            let mut ledger = ledger.clone();
+            ledger
+                .apply_transaction(&tx, &engine, height, 0, TEST_CHAIN_ID)
+                .expect("transaction inside its window should apply");
+            assert!(!ledger.utxos.contains_key(&utxo_key));
+        }
+    }
+
+    #[test]
+    fn script_failure_reports_input_and_trace() {
+        let mut ledger = LedgerState::default();
+        let locked_script = Script(vec![
+            OpCode::PushBytes(10u64.to_le_bytes().to_vec()),
+            OpCode::CheckTimeLock,
+        ]);
+        let open_key = UtxoKey {
+            tx_id: [16u8; 32],
+            index: 0,
+        };
+        let locked_key = UtxoKey {
+            tx_id: [17u8; 32],
+            index: 0,
         };
+        ledger.utxos.insert(open_key.clone(), simple_utxo());
+        insert_issuer_utxo(&mut ledger, &locked_script, locked_key.clone());
 
-        let expected_tip_hash = {
-            let serialized = consensus_codec::encode_block_header(&block.header);
-            *blake3::hash(&serialized).as_bytes()
+        let tx = Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::Transfer,
+                inputs: vec![
+                    TransactionInput {
+                        tx_id: open_key.tx_id,
+                        index: 0,
+                    },
+                    TransactionInput {
+                        tx_id: locked_key.tx_id,
+                        index: 0,
+                    },
+                ],
+                outputs: vec![simple_output()],
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![
+                    build_witness(&simple_script(), None),
+                    build_witness(&locked_script, None),
+                ],
+            },
         };
 
         let engine = DeterministicScriptEngine::default();
-        chain
-            .apply_block(&block, &engine)
-            .expect("block application should succeed");
+        let result = ledger.apply_transaction(&tx, &engine, 5, 0, TEST_CHAIN_ID);
+        assert!(matches!(
+            result,
+            Err(LedgerError::ScriptFailed {
+                input_index: 1,
+                source: ScriptError::Evaluation(_),
+            })
+        ));
+        assert!(ledger.utxos.contains_key(&open_key));
 
-        assert_eq!(chain.height, 1);
-        assert_eq!(chain.tip_hash, expected_tip_hash);
-        assert!(!chain.ledger.utxos.contains_key(&utxo_key));
-        let new_utxo = UtxoKey { tx_id, index: 0 };
-        assert!(chain.ledger.utxos.contains_key(&new_utxo));
+        let traces = ledger.trace_transaction(&tx, &engine, 5, 0, TEST_CHAIN_ID);
+        assert_eq!(traces.len(), 2);
+        assert!(traces[0].result.is_ok());
+        assert_eq!(traces[0].trace.steps.len(), 1);
+        assert!(matches!(
+            traces[1].result,
+            Err(LedgerError::ScriptFailed { input_index: 1, .. })
+        ));
+        let failed = traces[1].trace.steps.last().expect("failing step");
+        assert_eq!(failed.op, OpCode::CheckTimeLock);
+        assert!(failed.error.is_some());
+
+        let traces = ledger.trace_transaction(&tx, &engine, 10, 0, TEST_CHAIN_ID);
+        assert!(traces.iter().all(|trace| trace.result.is_ok()));
+    }
+
+    #[test]
+    fn single_key_template_spends_with_witness_signature() {
+        let registry = default_registry();
+        let scheme = registry
+            .get(&SignatureSchemeId::Dilithium2)
+            .expect("scheme should exist");
+        let (pk, sk) = scheme.keygen().expect("keygen should work");
+        let (script, owner_script_hash) = templates::single_key(&pk).expect("template builds");
+
+        let mut ledger = LedgerState::default();
+        let utxo_key = UtxoKey {
+            tx_id: [18u8; 32],
+            index: 0,
+        };
+        let prev_output = Output {
+            owner_script_hash,
+            ..simple_output()
+        };
+        ledger
+            .utxos
+            .insert(utxo_key.clone(), tracked(prev_output.clone()));
+
+        let mut tx = Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::Transfer,
+                inputs: vec![TransactionInput {
+                    tx_id: utxo_key.tx_id,
+                    index: 0,
+                }],
+                outputs: vec![simple_output()],
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness::default(),
+        };
+        let sighash = tx.sighash(
+            0,
+            &prev_output,
+            owner_script_hash,
+            TEST_CHAIN_ID,
+            SighashFlags::default(),
+        );
+        let signature = scheme
+            .sign(&sk, &sighash)
+            .expect("signing should work")
+            .to_bytes()
+            .expect("signature encodes");
+
+        let engine = DeterministicScriptEngine::default();
+        tx.witness.inputs = vec![build_witness(&script, None)];
+        let result = ledger.apply_transaction(&tx, &engine, 1, 0, TEST_CHAIN_ID);
+        assert!(matches!(
+            result,
+            Err(LedgerError::ScriptFailed { input_index: 0, .. })
+        ));
+
+        tx.witness.inputs = vec![build_witness_with_stack(&script, None, vec![signature])];
+        ledger
+            .apply_transaction(&tx, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("signed spend should succeed");
+        assert!(!ledger.utxos.contains_key(&utxo_key));
+    }
+
+    #[test]
+    fn htlc_leaf_spends_with_merkle_path() {
+        let registry = default_registry();
+        let scheme = registry
+            .get(&SignatureSchemeId::Dilithium2)
+            .expect("scheme should exist");
+        let (recipient_pk, recipient_sk) = scheme.keygen().expect("keygen should work");
+        let (refund_pk, _) = scheme.keygen().expect("keygen should work");
+        let preimage = b"htlc secret".to_vec();
+        let tree = templates::htlc(
+            HashAlgorithm::Blake3,
+            hash_bytes(&preimage),
+            &recipient_pk,
+            templates::Timelock::Absolute(100),
+            &refund_pk,
+        )
+        .expect("htlc builds");
+        let claim = tree.leaves()[0].clone();
+        let claim_path = tree.path(0).expect("claim leaf exists");
+        let refund_path = tree.path(1).expect("refund leaf exists");
+
+        let mut ledger = LedgerState::default();
+        let utxo_key = UtxoKey {
+            tx_id: [19u8; 32],
+            index: 0,
+        };
+        let prev_output = Output {
+            owner_script_hash: tree.root(),
+            ..simple_output()
+        };
+        ledger
+            .utxos
+            .insert(utxo_key.clone(), tracked(prev_output.clone()));
+
+        let mut tx = Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::Transfer,
+                inputs: vec![TransactionInput {
+                    tx_id: utxo_key.tx_id,
+                    index: 0,
+                }],
+                outputs: vec![simple_output()],
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness::default(),
+        };
+        // Tree spends sign the leaf hash, not the tree root.
+        let sighash = tx.sighash(
+            0,
+            &prev_output,
+            script_tree::leaf_hash(&claim),
+            TEST_CHAIN_ID,
+            SighashFlags::default(),
+        );
+        let signature = scheme
+            .sign(&recipient_sk, &sighash)
+            .expect("signing should work")
+            .to_bytes()
+            .expect("signature encodes");
+        let witness = |merkle_path: Option<Vec<Hash256>>| {
+            InputWitness {
+                merkle_path,
+                ..ScriptEnvelope::legacy(&claim).witness(vec![preimage.clone(), signature.clone()])
+            }
+            .encode()
+        };
+
+        let engine = DeterministicScriptEngine::default();
+        tx.witness.inputs = vec![witness(None)];
+        assert!(matches!(
+            ledger.apply_transaction(&tx, &engine, 1, 0, TEST_CHAIN_ID),
+            Err(LedgerError::ScriptHashMismatch)
+        ));
+
+        tx.witness.inputs = vec![witness(Some(refund_path))];
+        assert!(matches!(
+            ledger.apply_transaction(&tx, &engine, 1, 0, TEST_CHAIN_ID),
+            Err(LedgerError::ScriptPathMismatch)
+        ));
+
+        tx.witness.inputs = vec![witness(Some(vec![
+            [0u8; 32];
+            script_tree::MAX_PATH_LEN + 1
+        ]))];
+        assert!(matches!(
+            ledger.apply_transaction(&tx, &engine, 1, 0, TEST_CHAIN_ID),
+            Err(LedgerError::InvalidWitness)
+        ));
+
+        tx.witness.inputs = vec![witness(Some(claim_path))];
+        ledger
+            .apply_transaction(&tx, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("claim leaf should spend");
+        assert!(!ledger.utxos.contains_key(&utxo_key));
+    }
+
+    #[test]
+    fn only_canonical_and_baseline_witnesses_decode() {
+        #[derive(Serialize)]
+        struct Legacy {
+            script: Script,
+            metadata: Option<Vec<u8>>,
+        }
+
+        let bytes = bincode::serialize(&Legacy {
+            script: simple_script(),
+            metadata: Some(b"meta".to_vec()),
+        })
+        .unwrap();
+        let witness = decode_input_witness(&bytes).expect("legacy witness decodes");
+        assert_eq!(
+            ScriptEnvelope::from(&witness),
+            ScriptEnvelope::legacy(&simple_script())
+        );
+        assert_eq!(witness.metadata, Some(b"meta".to_vec()));
+        assert!(witness.stack.is_empty());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            decode_input_witness(&trailing),
+            Err(LedgerError::InvalidWitness)
+        ));
+
+        // Bincode layouts with stacks, paths or envelopes never shipped; only the
+        // canonical encoding carries those fields.
+        #[derive(Serialize)]
+        struct WithStack {
+            script: Script,
+            metadata: Option<Vec<u8>>,
+            stack: Vec<Vec<u8>>,
+        }
+        #[derive(Serialize)]
+        struct Envelope {
+            script: ScriptEnvelope,
+            metadata: Option<Vec<u8>>,
+            stack: Vec<Vec<u8>>,
+            merkle_path: Option<Vec<Hash256>>,
+        }
+
+        let envelope = ScriptEnvelope {
+            version: 3,
+            body: vec![1, 2],
+        };
+        for bytes in [
+            bincode::serialize(&WithStack {
+                script: simple_script(),
+                metadata: None,
+                stack: vec![vec![7u8; 3]],
+            })
+            .unwrap(),
+            bincode::serialize(&Envelope {
+                script: envelope.clone(),
+                metadata: Some(b"meta".to_vec()),
+                stack: vec![vec![7u8; 3]],
+                merkle_path: None,
+            })
+            .unwrap(),
+        ] {
+            assert!(matches!(
+                decode_input_witness(&bytes),
+                Err(LedgerError::InvalidWitness)
+            ));
+        }
+
+        let canonical = InputWitness {
+            metadata: Some(b"meta".to_vec()),
+            ..envelope.witness(vec![vec![7u8; 3]])
+        };
+        assert_eq!(
+            decode_input_witness(&canonical.encode()).unwrap(),
+            canonical
+        );
+        let mut trailing = canonical.encode();
+        trailing.push(0);
+        assert!(matches!(
+            decode_input_witness(&trailing),
+            Err(LedgerError::InvalidWitness)
+        ));
+
+        assert!(matches!(
+            decode_input_witness(&[1, 2, 3]),
+            Err(LedgerError::InvalidWitness)
+        ));
+    }
+
+    #[test]
+    fn unknown_script_version_is_anyone_can_spend_unless_rejected_by_policy() {
+        let envelope = ScriptEnvelope {
+            version: qcoin_script::MAX_KNOWN_SCRIPT_VERSION + 1,
+            body: vec![0xee; 4],
+        };
+        let previous_tx_id = [13u8; 32];
+        let mut ledger = LedgerState::default();
+        ledger.utxos.insert(
+            UtxoKey {
+                tx_id: previous_tx_id,
+                index: 0,
+            },
+            tracked(Output {
+                owner_script_hash: envelope.script_hash(),
+                ..simple_output()
+            }),
+        );
+        let witness = |script: ScriptEnvelope| script.witness(Vec::new()).encode();
+        let mut tx = Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::Transfer,
+                inputs: vec![TransactionInput {
+                    tx_id: previous_tx_id,
+                    index: 0,
+                }],
+                outputs: vec![simple_output()],
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![witness(ScriptEnvelope {
+                    version: SCRIPT_VERSION_LEGACY,
+                    ..envelope.clone()
+                })],
+            },
+        };
+
+        // The version is committed: the same body under another version does not match.
+        let engine = DeterministicScriptEngine::default();
+        assert!(matches!(
+            ledger
+                .clone()
+                .apply_transaction(&tx, &engine, 1, 0, TEST_CHAIN_ID),
+            Err(LedgerError::ScriptHashMismatch)
+        ));
+
+        tx.witness.inputs = vec![witness(envelope)];
+        let policy = DeterministicScriptEngine::with_config(VmConfig {
+            unknown_versions: UnknownVersionPolicy::Reject,
+            ..VmConfig::default()
+        });
+        assert!(matches!(
+            ledger
+                .clone()
+                .apply_transaction(&tx, &policy, 1, 0, TEST_CHAIN_ID),
+            Err(LedgerError::ScriptFailed {
+                source: ScriptError::UnknownVersion(_),
+                ..
+            })
+        ));
+        ledger
+            .apply_transaction(&tx, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("consensus accepts unknown script versions");
+    }
+
+    #[test]
+    fn chain_state_apply_block_updates_height_and_tip_hash() {
+        let mut chain = ChainState::default();
+        let previous_tx_id = [11u8; 32];
+        let utxo_key = UtxoKey {
+            tx_id: previous_tx_id,
+            index: 0,
+        };
+        chain.ledger.utxos.insert(utxo_key.clone(), simple_utxo());
+
+        let spend_tx = Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::Transfer,
+                inputs: vec![TransactionInput {
+                    tx_id: previous_tx_id,
+                    index: 0,
+                }],
+                outputs: vec![simple_output()],
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![build_witness(&simple_script(), None)],
+            },
+        };
+
+        let tx_id = spend_tx.tx_id();
+        let tx_root = spend_tx.tx_id();
+        let block = Block {
+            header: BlockHeader {
+                parent_hash: chain.tip_hash,
+                state_root: Hash256::default(),
+                tx_root,
+                height: 1,
+                timestamp: 42,
+                witness_root: spend_tx.witness_tx_id(),
+                ..legacy_header()
+            },
+            transactions: vec![spend_tx.clone()],
+            proposer_public_key: PublicKey {
+                scheme: SignatureSchemeId::Dilithium2,
+                bytes: Vec::new(),
+            },
+            signature: Signature {
+                scheme: SignatureSchemeId::Dilithium2,
+                bytes: Vec::new(),
+            },
+        };
+
+        let expected_tip_hash = {
+            let serialized = consensus_codec::encode_block_header(&block.header);
+            *blake3::hash(&serialized).as_bytes()
+        };
+
+        let engine = DeterministicScriptEngine::default();
+        chain
+            .apply_block(&block, &engine)
+            .expect("block application should succeed");
+
+        assert_eq!(chain.height, 1);
+        assert_eq!(chain.tip_hash, expected_tip_hash);
+        assert!(!chain.ledger.utxos.contains_key(&utxo_key));
+        let new_utxo = UtxoKey { tx_id, index: 0 };
+        assert!(chain.ledger.utxos.contains_key(&new_utxo));
+        assert!(chain.witness_commitments);
+    }
+
+    #[test]
+    fn median_time_past_covers_recent_blocks() {
+        let mut chain = ChainState::default();
+        assert_eq!(chain.median_time_past(), 0);
+
+        let engine = DeterministicScriptEngine::default();
+        // A late proposer timestamp moves the median by at most one position.
+        let timestamps = [10, 20, 30, 40, 50, 60, 70, 80, 90, 100, 110, 10_000];
+        for (index, timestamp) in timestamps.into_iter().enumerate() {
+            let block = Block {
+                header: BlockHeader {
+                    parent_hash: chain.tip_hash,
+                    state_root: Hash256::default(),
+                    tx_root: Hash256::default(),
+                    height: index as u64 + 1,
+                    timestamp,
+                    ..legacy_header()
+                },
+                transactions: Vec::new(),
+                proposer_public_key: PublicKey {
+                    scheme: SignatureSchemeId::Dilithium2,
+                    bytes: Vec::new(),
+                },
+                signature: Signature {
+                    scheme: SignatureSchemeId::Dilithium2,
+                    bytes: Vec::new(),
+                },
+            };
+            chain
+                .apply_block(&block, &engine)
+                .expect("empty block applies");
+        }
+
+        assert_eq!(chain.recent_timestamps.len(), MEDIAN_TIME_SPAN);
+        assert_eq!(chain.recent_timestamps[0], 20);
+        assert_eq!(chain.median_time_past(), 70);
+        assert!(!chain.witness_commitments);
+    }
+
+    #[test]
+    fn relative_timestamp_lock_uses_output_creation_time() {
+        let script = Script(vec![
+            OpCode::PushBytes(600u64.to_le_bytes().to_vec()),
+            OpCode::CheckRelativeTimestampLock,
+        ]);
+        let previous_tx_id = [12u8; 32];
+        let mut ledger = LedgerState::default();
+        ledger.utxos.insert(
+            UtxoKey {
+                tx_id: previous_tx_id,
+                index: 0,
+            },
+            TrackedOutput {
+                output: Output {
+                    owner_script_hash: script_hash(&script),
+                    ..simple_output()
+                },
+                created_height: 1,
+                created_time: 1_000,
+            },
+        );
+        let tx = Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::Transfer,
+                inputs: vec![TransactionInput {
+                    tx_id: previous_tx_id,
+                    index: 0,
+                }],
+                outputs: vec![simple_output()],
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![build_witness(&script, None)],
+            },
+        };
+
+        let engine = DeterministicScriptEngine::default();
+        assert!(matches!(
+            ledger.apply_transaction(&tx, &engine, 50, 1_599, TEST_CHAIN_ID),
+            Err(LedgerError::ScriptFailed { .. })
+        ));
+        ledger
+            .apply_transaction(&tx, &engine, 50, 1_600, TEST_CHAIN_ID)
+            .expect("lock satisfied at creation time plus 600s");
+
+        let created = ledger
+            .utxos
+            .get(&UtxoKey {
+                tx_id: tx.tx_id(),
+                index: 0,
+            })
+            .expect("output created");
+        assert_eq!(created.created_time, 1_600);
+    }
+
+    #[test]
+    fn transfer_policies_restrict_moves_between_owners() {
+        let engine = DeterministicScriptEngine::default();
+        let issuer_script = simple_script();
+        let holder_script = Script(vec![OpCode::Nop, OpCode::Nop]);
+        let other_owner = [60u8; 32];
+        let asset_id = AssetId([61u8; 32]);
+        let holder_key = UtxoKey {
+            tx_id: [62u8; 32],
+            index: 0,
+        };
+        let issuer_key = UtxoKey {
+            tx_id: [63u8; 32],
+            index: 0,
+        };
+
+        let ledger_with = |mode, burnable_by_holder| {
+            let mut ledger = LedgerState::default();
+            ledger.assets.insert(
+                asset_id.clone(),
+                AssetDefinition {
+                    issuer_script_hash: script_hash(&issuer_script),
+                    metadata_root: [64u8; 32],
+                    max_supply: None,
+                    decimals: 0,
+                    kind: AssetKind::Fungible,
+                    transfer_policy: TransferPolicy {
+                        mode,
+                        burnable_by_holder,
+                    },
+                    issuer_controls: IssuerControls::default(),
+                    royalty: None,
+                },
+            );
+            ledger.utxos.insert(
+                holder_key.clone(),
+                TrackedOutput {
+                    output: Output {
+                        owner_script_hash: script_hash(&holder_script),
+                        assets: vec![AssetAmount {
+                            asset_id: asset_id.clone(),
+                            amount: 10,
+                        }],
+                        metadata_hash: None,
+                    },
+                    created_height: 0,
+                    created_time: 0,
+                },
+            );
+            insert_issuer_utxo(&mut ledger, &issuer_script, issuer_key.clone());
+            ledger
+        };
+        let spend = |outputs: &[(Hash256, u128)], with_issuer: bool| {
+            let mut inputs = vec![TransactionInput {
+                tx_id: holder_key.tx_id,
+                index: holder_key.index,
+            }];
+            let mut witnesses = vec![build_witness(&holder_script, None)];
+            if with_issuer {
+                inputs.push(TransactionInput {
+                    tx_id: issuer_key.tx_id,
+                    index: issuer_key.index,
+                });
+                witnesses.push(build_witness(&issuer_script, None));
+            }
+            Transaction {
+                core: TransactionCore {
+                    kind: TransactionKind::Transfer,
+                    inputs,
+                    outputs: outputs
+                        .iter()
+                        .map(|(owner_script_hash, amount)| Output {
+                            owner_script_hash: *owner_script_hash,
+                            assets: vec![AssetAmount {
+                                asset_id: asset_id.clone(),
+                                amount: *amount,
+                            }],
+                            metadata_hash: None,
+                        })
+                        .collect(),
+                    valid_from_height: None,
+                    valid_until_height: None,
+                },
+                witness: TransactionWitness { inputs: witnesses },
+            }
+        };
+        let apply = |ledger: &LedgerState, tx: &Transaction| {
+            ledger
+                .clone()
+                .apply_transaction(tx, &engine, 1, 0, TEST_CHAIN_ID)
+        };
+        let holder = script_hash(&holder_script);
+
+        let soulbound = ledger_with(TransferMode::Soulbound, false);
+        assert!(matches!(
+            apply(&soulbound, &spend(&[(other_owner, 10)], false)),
+            Err(LedgerError::AssetNotTransferable)
+        ));
+        assert!(matches!(
+            apply(&soulbound, &spend(&[(holder, 4), (other_owner, 6)], true)),
+            Err(LedgerError::AssetNotTransferable)
+        ));
+        apply(&soulbound, &spend(&[(holder, 4), (holder, 6)], false))
+            .expect("the holder may split units among its own outputs");
+        assert!(matches!(
+            apply(&soulbound, &spend(&[(holder, 7)], false)),
+            Err(LedgerError::AssetConservationViolation)
+        ));
+
+        let burnable = ledger_with(TransferMode::Soulbound, true);
+        apply(&burnable, &spend(&[(holder, 7)], false)).expect("the holder may burn units");
+        apply(&burnable, &spend(&[], false)).expect("the holder may burn every unit");
+        assert!(matches!(
+            apply(&burnable, &spend(&[(holder, 11)], false)),
+            Err(LedgerError::AssetConservationViolation)
+        ));
+
+        let approved = ledger_with(TransferMode::IssuerApproved, false);
+        assert!(matches!(
+            apply(&approved, &spend(&[(other_owner, 10)], false)),
+            Err(LedgerError::TransferNotApproved)
+        ));
+        apply(&approved, &spend(&[(other_owner, 10)], true))
+            .expect("an issuer input approves the transfer");
+
+        let transferable = ledger_with(TransferMode::Transferable, false);
+        apply(&transferable, &spend(&[(other_owner, 10)], false))
+            .expect("transferable assets move freely");
+    }
+
+    #[test]
+    fn issuer_controls_freeze_and_claw_back_outputs() {
+        let engine = DeterministicScriptEngine::default();
+        let issuer_script = simple_script();
+        let holder_script = Script(vec![OpCode::Nop, OpCode::Nop]);
+        let asset_id = AssetId([70u8; 32]);
+        let held = UtxoKey {
+            tx_id: [71u8; 32],
+            index: 0,
+        };
+        let mut ledger = LedgerState::default();
+        ledger.assets.insert(
+            asset_id.clone(),
+            AssetDefinition {
+                issuer_script_hash: script_hash(&issuer_script),
+                metadata_root: [72u8; 32],
+                max_supply: None,
+                decimals: 0,
+                kind: AssetKind::Fungible,
+                transfer_policy: TransferPolicy {
+                    mode: TransferMode::Soulbound,
+                    burnable_by_holder: false,
+                },
+                issuer_controls: IssuerControls {
+                    freeze: true,
+                    clawback: true,
+                },
+                royalty: None,
+            },
+        );
+        ledger.utxos.insert(
+            held.clone(),
+            TrackedOutput {
+                output: Output {
+                    owner_script_hash: script_hash(&holder_script),
+                    assets: vec![AssetAmount {
+                        asset_id: asset_id.clone(),
+                        amount: 10,
+                    }],
+                    metadata_hash: None,
+                },
+                created_height: 0,
+                created_time: 0,
+            },
+        );
+        for tx_id in 73u8..78 {
+            insert_issuer_utxo(
+                &mut ledger,
+                &issuer_script,
+                UtxoKey {
+                    tx_id: [tx_id; 32],
+                    index: 0,
+                },
+            );
+        }
+        insert_issuer_utxo(
+            &mut ledger,
+            &holder_script,
+            UtxoKey {
+                tx_id: [78u8; 32],
+                index: 0,
+            },
+        );
+
+        let target = TransactionInput {
+            tx_id: held.tx_id,
+            index: held.index,
+        };
+        let build = |kind, input: (u8, &Script), outputs: Vec<Output>| Transaction {
+            core: TransactionCore {
+                kind,
+                inputs: vec![TransactionInput {
+                    tx_id: [input.0; 32],
+                    index: 0,
+                }],
+                outputs,
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![build_witness(input.1, None)],
+            },
+        };
+        let freeze = |input| {
+            build(
+                TransactionKind::FreezeOutputs {
+                    asset_id: asset_id.clone(),
+                    targets: vec![target.clone()],
+                },
+                input,
+                Vec::new(),
+            )
+        };
+
+        assert!(matches!(
+            ledger.clone().apply_transaction(
+                &freeze((78, &holder_script)),
+                &engine,
+                1,
+                0,
+                TEST_CHAIN_ID
+            ),
+            Err(LedgerError::MissingIssuerAuthorization)
+        ));
+        let unfrozen_root = ledger.state_root();
+        ledger
+            .apply_transaction(&freeze((73, &issuer_script)), &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("issuer should freeze the output");
+        assert!(ledger.is_frozen(&held));
+        assert_ne!(ledger.state_root(), unfrozen_root);
+        assert!(matches!(
+            ledger.clone().apply_transaction(
+                &freeze((74, &issuer_script)),
+                &engine,
+                1,
+                0,
+                TEST_CHAIN_ID
+            ),
+            Err(LedgerError::InvalidIssuerControlTarget)
+        ));
+
+        let holder_spend = Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::Transfer,
+                inputs: vec![target.clone()],
+                outputs: vec![Output {
+                    owner_script_hash: script_hash(&holder_script),
+                    assets: vec![AssetAmount {
+                        asset_id: asset_id.clone(),
+                        amount: 10,
+                    }],
+                    metadata_hash: None,
+                }],
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![build_witness(&holder_script, None)],
+            },
+        };
+        assert!(matches!(
+            ledger
+                .clone()
+                .apply_transaction(&holder_spend, &engine, 1, 0, TEST_CHAIN_ID),
+            Err(LedgerError::OutputFrozen)
+        ));
+
+        let mut unfrozen = ledger.clone();
+        unfrozen
+            .apply_transaction(
+                &build(
+                    TransactionKind::UnfreezeOutputs {
+                        asset_id: asset_id.clone(),
+                        targets: vec![target.clone()],
+                    },
+                    (74, &issuer_script),
+                    Vec::new(),
+                ),
+                &engine,
+                1,
+                0,
+                TEST_CHAIN_ID,
+            )
+            .expect("issuer should unfreeze the output");
+        assert!(!unfrozen.is_frozen(&held));
+        unfrozen
+            .apply_transaction(&holder_spend, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("unfrozen output is spendable");
+
+        let reclaimed = vec![Output {
+            owner_script_hash: script_hash(&issuer_script),
+            assets: vec![AssetAmount {
+                asset_id: asset_id.clone(),
+                amount: 10,
+            }],
+            metadata_hash: None,
+        }];
+        let clawback = build(
+            TransactionKind::Clawback {
+                asset_id: asset_id.clone(),
+                targets: vec![target.clone()],
+            },
+            (75, &issuer_script),
+            reclaimed,
+        );
+        ledger
+            .apply_transaction(&clawback, &engine, 2, 0, TEST_CHAIN_ID)
+            .expect("issuer should claw back the soulbound units");
+        assert!(!ledger.utxos.contains_key(&held));
+        assert!(ledger.frozen_outputs.is_empty());
+        let reclaimed_key = UtxoKey {
+            tx_id: clawback.tx_id(),
+            index: 0,
+        };
+        assert_eq!(ledger.utxos[&reclaimed_key].output.assets[0].amount, 10);
+
+        ledger
+            .assets
+            .get_mut(&asset_id)
+            .unwrap()
+            .issuer_controls
+            .clawback = false;
+        let target = TransactionInput {
+            tx_id: reclaimed_key.tx_id,
+            index: reclaimed_key.index,
+        };
+        assert!(matches!(
+            ledger.apply_transaction(
+                &build(
+                    TransactionKind::Clawback {
+                        asset_id: asset_id.clone(),
+                        targets: vec![target],
+                    },
+                    (76, &issuer_script),
+                    Vec::new(),
+                ),
+                &engine,
+                2,
+                0,
+                TEST_CHAIN_ID
+            ),
+            Err(LedgerError::IssuerControlDisabled)
+        ));
+    }
+
+    #[test]
+    fn clawback_returns_other_assets_to_their_holder() {
+        let engine = DeterministicScriptEngine::default();
+        let issuer_script = simple_script();
+        let issuer = script_hash(&issuer_script);
+        let holder = script_hash(&Script(vec![OpCode::Nop, OpCode::Nop]));
+        let asset_id = AssetId([90u8; 32]);
+        let other_asset = AssetId([91u8; 32]);
+        let mut ledger = LedgerState::default();
+        ledger.assets.insert(
+            asset_id.clone(),
+            AssetDefinition {
+                issuer_script_hash: issuer,
+                metadata_root: [92u8; 32],
+                max_supply: None,
+                decimals: 0,
+                kind: AssetKind::Fungible,
+                transfer_policy: TransferPolicy::default(),
+                issuer_controls: IssuerControls {
+                    freeze: false,
+                    clawback: true,
+                },
+                royalty: None,
+            },
+        );
+        let amount = |asset_id: &AssetId, amount| AssetAmount {
+            asset_id: asset_id.clone(),
+            amount,
+        };
+        let output = |owner_script_hash, assets| Output {
+            owner_script_hash,
+            assets,
+            metadata_hash: None,
+        };
+        let mixed = UtxoKey {
+            tx_id: [93u8; 32],
+            index: 0,
+        };
+        let unrelated = UtxoKey {
+            tx_id: [94u8; 32],
+            index: 0,
+        };
+        for (key, assets) in [
+            (&mixed, vec![amount(&asset_id, 10), amount(&other_asset, 4)]),
+            (&unrelated, vec![amount(&other_asset, 2)]),
+        ] {
+            ledger.utxos.insert(
+                key.clone(),
+                TrackedOutput {
+                    output: output(holder, assets),
+                    created_height: 0,
+                    created_time: 0,
+                },
+            );
+        }
+        let issuer_key = UtxoKey {
+            tx_id: [95u8; 32],
+            index: 0,
+        };
+        insert_issuer_utxo(&mut ledger, &issuer_script, issuer_key.clone());
+
+        let clawback = |target: &UtxoKey, outputs| Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::Clawback {
+                    asset_id: asset_id.clone(),
+                    targets: vec![TransactionInput {
+                        tx_id: target.tx_id,
+                        index: target.index,
+                    }],
+                },
+                inputs: vec![TransactionInput {
+                    tx_id: issuer_key.tx_id,
+                    index: issuer_key.index,
+                }],
+                outputs,
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![build_witness(&issuer_script, None)],
+            },
+        };
+
+        // The issuer cannot take the other asset, in whole or in part.
+        for outputs in [
+            vec![output(
+                issuer,
+                vec![amount(&asset_id, 10), amount(&other_asset, 4)],
+            )],
+            vec![
+                output(issuer, vec![amount(&asset_id, 10), amount(&other_asset, 1)]),
+                output(holder, vec![amount(&other_asset, 3)]),
+            ],
+        ] {
+            assert!(matches!(
+                ledger.clone().apply_transaction(
+                    &clawback(&mixed, outputs),
+                    &engine,
+                    1,
+                    0,
+                    TEST_CHAIN_ID
+                ),
+                Err(LedgerError::ClawbackNotReturned)
+            ));
+        }
+        // An output without the clawed asset is not a target.
+        assert!(matches!(
+            ledger.clone().apply_transaction(
+                &clawback(
+                    &unrelated,
+                    vec![output(holder, vec![amount(&other_asset, 2)])]
+                ),
+                &engine,
+                1,
+                0,
+                TEST_CHAIN_ID
+            ),
+            Err(LedgerError::InvalidIssuerControlTarget)
+        ));
+
+        let tx = clawback(
+            &mixed,
+            vec![
+                output(issuer, vec![amount(&asset_id, 10)]),
+                output(holder, vec![amount(&other_asset, 4)]),
+            ],
+        );
+        ledger
+            .apply_transaction(&tx, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("the clawed asset moves and the rest returns to the holder");
+        assert!(!ledger.utxos.contains_key(&mixed));
+        let returned = &ledger.utxos[&UtxoKey {
+            tx_id: tx.tx_id(),
+            index: 1,
+        }];
+        assert_eq!(returned.output.owner_script_hash, holder);
+        assert_eq!(returned.output.assets, vec![amount(&other_asset, 4)]);
+    }
+
+    #[test]
+    fn royalties_are_owed_when_assets_change_owner() {
+        let engine = DeterministicScriptEngine::default();
+        let seller_script = Script(vec![OpCode::Nop, OpCode::Nop]);
+        let buyer_script = simple_script();
+        let seller = script_hash(&seller_script);
+        let buyer = script_hash(&buyer_script);
+        let recipient = [80u8; 32];
+        let asset_id = AssetId([81u8; 32]);
+        let item_key = UtxoKey {
+            tx_id: [82u8; 32],
+            index: 0,
+        };
+        let payment_key = UtxoKey {
+            tx_id: [83u8; 32],
+            index: 0,
+        };
+        let amount = |asset_id: &AssetId, amount| AssetAmount {
+            asset_id: asset_id.clone(),
+            amount,
+        };
+        let tracked = |owner_script_hash, assets| TrackedOutput {
+            output: Output {
+                owner_script_hash,
+                assets,
+                metadata_hash: None,
+            },
+            created_height: 0,
+            created_time: 0,
+        };
+
+        let other_asset = AssetId([86u8; 32]);
+        let ledger_with = |royalty_amount| {
+            let mut ledger = LedgerState::default();
+            ledger.assets.insert(
+                asset_id.clone(),
+                AssetDefinition {
+                    issuer_script_hash: [84u8; 32],
+                    metadata_root: [85u8; 32],
+                    max_supply: None,
+                    decimals: 0,
+                    kind: AssetKind::NonFungible,
+                    transfer_policy: TransferPolicy::default(),
+                    issuer_controls: IssuerControls::default(),
+                    royalty: Some(RoyaltyRule {
+                        recipient_script_hash: recipient,
+                        payment_asset_id: QCOIN_ASSET_ID,
+                        amount: royalty_amount,
+                    }),
+                },
+            );
+            ledger.utxos.insert(
+                item_key.clone(),
+                tracked(seller, vec![amount(&asset_id, 1)]),
+            );
+            ledger.utxos.insert(
+                payment_key.clone(),
+                tracked(
+                    buyer,
+                    vec![amount(&QCOIN_ASSET_ID, 1_000), amount(&other_asset, 1_000)],
+                ),
+            );
+            ledger
+        };
+        // Moves the item to the buyer and splits the buyer's 1,000 QCOIN and 1,000 units
+        // of another asset.
+        let sale = |kind: TransactionKind, payments: &[(Hash256, &AssetId, u128)]| {
+            let mut outputs = vec![Output {
+                owner_script_hash: buyer,
+                assets: vec![amount(&asset_id, 1)],
+                metadata_hash: None,
+            }];
+            outputs.extend(payments.iter().map(|(owner, asset_id, paid)| Output {
+                owner_script_hash: *owner,
+                assets: vec![amount(asset_id, *paid)],
+                metadata_hash: None,
+            }));
+            Transaction {
+                core: TransactionCore {
+                    kind,
+                    inputs: [&item_key, &payment_key]
+                        .into_iter()
+                        .map(|key| TransactionInput {
+                            tx_id: key.tx_id,
+                            index: key.index,
+                        })
+                        .collect(),
+                    outputs,
+                    valid_from_height: None,
+                    valid_until_height: None,
+                },
+                witness: TransactionWitness {
+                    inputs: vec![
+                        build_witness(&seller_script, None),
+                        build_witness(&buyer_script, None),
+                    ],
+                },
+            }
+        };
+        let declared = |price| TransactionKind::Sale {
+            prices: vec![SalePrice {
+                asset_id: asset_id.clone(),
+                price,
+            }],
+        };
+        let apply = |ledger: &LedgerState, tx: &Transaction| {
+            ledger
+                .clone()
+                .apply_transaction(tx, &engine, 1, 0, TEST_CHAIN_ID)
+        };
+        let qcoin = &QCOIN_ASSET_ID;
+        let other = &other_asset;
+
+        let percentage = ledger_with(RoyaltyAmount::BasisPoints(500));
+        let rule = percentage.assets[&asset_id].royalty.clone().unwrap();
+        let royalty_output = rule.payment_output(950).unwrap();
+        assert_eq!(royalty_output.assets[0].amount, 48);
+        apply(
+            &percentage,
+            &sale(
+                declared(950),
+                &[
+                    (seller, qcoin, 950),
+                    (recipient, qcoin, 48),
+                    (buyer, qcoin, 2),
+                    (buyer, other, 1_000),
+                ],
+            ),
+        )
+        .expect("sale paying the royalty on its declared price is valid");
+        assert!(matches!(
+            apply(
+                &percentage,
+                &sale(
+                    declared(960),
+                    &[
+                        (seller, qcoin, 960),
+                        (recipient, qcoin, 40),
+                        (buyer, other, 1_000)
+                    ]
+                )
+            ),
+            Err(LedgerError::RoyaltyUnpaid {
+                required: 48,
+                paid: 40
+            })
+        ));
+        assert!(matches!(
+            apply(
+                &percentage,
+                &sale(
+                    declared(950),
+                    &[(seller, qcoin, 1_000), (buyer, other, 1_000)]
+                )
+            ),
+            Err(LedgerError::SalePriceUnderstated {
+                declared: 950,
+                received: 1_000
+            })
+        ));
+
+        // Paying the seller in another asset, under another script, or in a separate
+        // transaction leaves nothing for the ledger to price, so the sale must declare
+        // its price and the royalty follows the declaration.
+        let seller_alias = [87u8; 32];
+        for payments in [
+            [(seller, other, 1_000), (buyer, qcoin, 1_000)],
+            [(seller_alias, qcoin, 1_000), (buyer, other, 1_000)],
+            [(buyer, qcoin, 1_000), (buyer, other, 1_000)],
+        ] {
+            assert!(matches!(
+                apply(&percentage, &sale(TransactionKind::Transfer, &payments)),
+                Err(LedgerError::MissingSalePrice)
+            ));
+            assert!(matches!(
+                apply(&percentage, &sale(declared(1_000), &payments)),
+                Err(LedgerError::RoyaltyUnpaid {
+                    required: 50,
+                    paid: 0
+                })
+            ));
+        }
+        apply(
+            &percentage,
+            &sale(declared(0), &[(buyer, qcoin, 1_000), (buyer, other, 1_000)]),
+        )
+        .expect("a gift declares a price of zero");
+
+        // Every change of owner is a sale, gifts and moves to another script of the
+        // same person included. Re-locking the item under the same script is not.
+        let move_item = |kind: TransactionKind, owner_script_hash| Transaction {
+            core: TransactionCore {
+                kind,
+                inputs: vec![TransactionInput {
+                    tx_id: item_key.tx_id,
+                    index: item_key.index,
+                }],
+                outputs: vec![Output {
+                    owner_script_hash,
+                    assets: vec![amount(&asset_id, 1)],
+                    metadata_hash: None,
+                }],
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![build_witness(&seller_script, None)],
+            },
+        };
+        for owner in [buyer, seller_alias] {
+            assert!(matches!(
+                apply(&percentage, &move_item(TransactionKind::Transfer, owner)),
+                Err(LedgerError::MissingSalePrice)
+            ));
+            apply(&percentage, &move_item(declared(0), owner))
+                .expect("a move declared at zero owes no royalty");
+        }
+        apply(&percentage, &move_item(TransactionKind::Transfer, seller))
+            .expect("the same owner needs no declaration");
+        assert!(matches!(
+            apply(
+                &percentage,
+                &sale(
+                    TransactionKind::Sale {
+                        prices: vec![SalePrice {
+                            asset_id: other_asset.clone(),
+                            price: 0,
+                        }],
+                    },
+                    &[(buyer, qcoin, 1_000), (buyer, other, 1_000)]
+                )
+            ),
+            Err(LedgerError::InvalidSalePrices)
+        ));
+
+        let fixed = ledger_with(RoyaltyAmount::Fixed(10));
+        let unpaid = [(buyer, qcoin, 1_000), (buyer, other, 1_000)];
+        assert!(matches!(
+            apply(&fixed, &sale(TransactionKind::Transfer, &unpaid)),
+            Err(LedgerError::RoyaltyUnpaid {
+                required: 10,
+                paid: 0
+            })
+        ));
+        apply(
+            &fixed,
+            &sale(
+                TransactionKind::Transfer,
+                &[
+                    (recipient, qcoin, 10),
+                    (buyer, qcoin, 990),
+                    (buyer, other, 1_000),
+                ],
+            ),
+        )
+        .expect("fixed royalty is owed on every change of owner");
+        assert!(matches!(
+            apply(&fixed, &sale(declared(0), &unpaid)),
+            Err(LedgerError::InvalidSalePrices)
+        ));
+
+        let mut ledger = LedgerState::default();
+        let (_, mut create) = create_kind_transaction(
+            &mut ledger,
+            AssetKind::Fungible,
+            0,
+            5,
+            Vec::new(),
+            &[(None, 5)],
+        );
+        if let TransactionKind::CreateAsset { definition, .. } = &mut create.core.kind {
+            definition.royalty = Some(RoyaltyRule {
+                amount: RoyaltyAmount::BasisPoints(MAX_ROYALTY_BASIS_POINTS + 1),
+                ..rule
+            });
+        }
+        assert!(matches!(
+            ledger.apply_transaction(&create, &engine, 1, 0, TEST_CHAIN_ID),
+            Err(LedgerError::InvalidRoyalty)
+        ));
+    }
+
+    #[test]
+    fn issuer_updates_metadata_root_and_history_is_kept() {
+        let engine = DeterministicScriptEngine::default();
+        let mut ledger = LedgerState::default();
+        let (asset_id, create) = create_kind_transaction(
+            &mut ledger,
+            AssetKind::Fungible,
+            0,
+            5,
+            Vec::new(),
+            &[(None, 5)],
+        );
+        ledger
+            .apply_transaction(&create, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("asset should be created");
+        let original_root = ledger.assets[&asset_id].metadata_root;
+        assert_eq!(ledger.current_metadata_root(&asset_id), Some(original_root));
+
+        let issuer_script = simple_script();
+        let stranger_script = Script(vec![OpCode::Nop, OpCode::Nop]);
+        insert_issuer_utxo(
+            &mut ledger,
+            &issuer_script,
+            UtxoKey {
+                tx_id: [90u8; 32],
+                index: 0,
+            },
+        );
+        insert_issuer_utxo(
+            &mut ledger,
+            &issuer_script,
+            UtxoKey {
+                tx_id: [91u8; 32],
+                index: 0,
+            },
+        );
+        insert_issuer_utxo(
+            &mut ledger,
+            &stranger_script,
+            UtxoKey {
+                tx_id: [92u8; 32],
+                index: 0,
+            },
+        );
+        let update = |asset_id: &AssetId, metadata_root, input: (u8, &Script)| Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::UpdateAssetMetadata {
+                    asset_id: asset_id.clone(),
+                    metadata_root,
+                },
+                inputs: vec![TransactionInput {
+                    tx_id: [input.0; 32],
+                    index: 0,
+                }],
+                outputs: Vec::new(),
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![build_witness(input.1, None)],
+            },
+        };
+
+        assert!(matches!(
+            ledger.clone().apply_transaction(
+                &update(&asset_id, [93u8; 32], (92, &stranger_script)),
+                &engine,
+                2,
+                0,
+                TEST_CHAIN_ID
+            ),
+            Err(LedgerError::MissingIssuerAuthorization)
+        ));
+        assert!(matches!(
+            ledger.clone().apply_transaction(
+                &update(&AssetId([94u8; 32]), [93u8; 32], (90, &issuer_script)),
+                &engine,
+                2,
+                0,
+                TEST_CHAIN_ID
+            ),
+            Err(LedgerError::UnknownAsset)
+        ));
+        assert!(matches!(
+            ledger.clone().apply_transaction(
+                &update(&asset_id, original_root, (90, &issuer_script)),
+                &engine,
+                2,
+                0,
+                TEST_CHAIN_ID
+            ),
+            Err(LedgerError::MetadataUnchanged)
+        ));
+
+        let root_before = ledger.state_root();
+        let first = update(&asset_id, [93u8; 32], (90, &issuer_script));
+        ledger
+            .apply_transaction(&first, &engine, 2, 0, TEST_CHAIN_ID)
+            .expect("issuer should update the metadata root");
+        let second = update(&asset_id, [95u8; 32], (91, &issuer_script));
+        ledger
+            .apply_transaction(&second, &engine, 3, 0, TEST_CHAIN_ID)
+            .expect("issuer should update the metadata root again");
+
+        assert_ne!(ledger.state_root(), root_before);
+        assert_eq!(ledger.assets[&asset_id].metadata_root, original_root);
+        assert_eq!(ledger.current_metadata_root(&asset_id), Some([95u8; 32]));
+        assert_eq!(
+            ledger.metadata_history(&asset_id),
+            [
+                MetadataUpdate {
+                    metadata_root: [93u8; 32],
+                    height: 2,
+                    tx_id: first.tx_id(),
+                },
+                MetadataUpdate {
+                    metadata_root: [95u8; 32],
+                    height: 3,
+                    tx_id: second.tx_id(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn supply_counters_track_mints_and_burns() {
+        let engine = DeterministicScriptEngine::default();
+        let mut ledger = LedgerState::default();
+        let (asset_id, create) = create_kind_transaction(
+            &mut ledger,
+            AssetKind::Fungible,
+            0,
+            5,
+            Vec::new(),
+            &[(None, 5)],
+        );
+        assert_eq!(ledger.asset_supply(&asset_id), None);
+        ledger
+            .apply_transaction(&create, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("asset should be created");
+        ledger
+            .assets
+            .get_mut(&asset_id)
+            .expect("asset is defined")
+            .transfer_policy
+            .burnable_by_holder = true;
+
+        let burn = spend_all_outputs(
+            &create,
+            vec![vec![AssetAmount {
+                asset_id: asset_id.clone(),
+                amount: 3,
+            }]],
+        );
+        ledger
+            .apply_transaction(&burn, &engine, 2, 0, TEST_CHAIN_ID)
+            .expect("the holder may burn units");
+
+        let supply = ledger.asset_supply(&asset_id).expect("supply is tracked");
+        assert_eq!(
+            supply,
+            AssetSupply {
+                minted: 5,
+                burned: 2,
+            }
+        );
+        assert_eq!(supply.circulating(), Some(3));
+        assert_eq!(ledger.holder_count(&asset_id), 1);
+
+        // Only headers from the supply version commit to the counters.
+        let mut without_supply = ledger.clone();
+        without_supply.asset_supply.clear();
+        assert_eq!(
+            ledger.state_root_at(BLOCK_HEADER_VERSION_SUPPLY - 1),
+            without_supply.state_root_at(BLOCK_HEADER_VERSION_SUPPLY)
+        );
+        assert_ne!(
+            ledger.state_root_at(BLOCK_HEADER_VERSION_SUPPLY),
+            without_supply.state_root_at(BLOCK_HEADER_VERSION_SUPPLY)
+        );
+
+        let mut ledger = LedgerState::default();
+        let (class_id, create) = create_kind_transaction(
+            &mut ledger,
+            AssetKind::SemiFungible,
+            0,
+            5,
+            vec![1, 2],
+            &[(Some(1), 2), (Some(2), 3)],
+        );
+        ledger
+            .apply_transaction(&create, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("class should be created");
+        assert_eq!(
+            ledger.asset_supply(&derive_sub_asset_id(&class_id, 2)),
+            Some(AssetSupply {
+                minted: 3,
+                burned: 0,
+            })
+        );
+        assert_eq!(
+            ledger.asset_supply(&class_id),
+            Some(AssetSupply {
+                minted: 5,
+                burned: 0,
+            })
+        );
+        assert_eq!(ledger.holder_count(&class_id), 1);
+    }
+
+    #[test]
+    fn assets_without_counters_are_seeded_from_held_units() {
+        let engine = DeterministicScriptEngine::default();
+        let mut ledger = LedgerState::default();
+        let (asset_id, create) = create_kind_transaction(
+            &mut ledger,
+            AssetKind::Fungible,
+            0,
+            5,
+            Vec::new(),
+            &[(None, 5)],
+        );
+        ledger
+            .apply_transaction(&create, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("asset should be created");
+        ledger
+            .assets
+            .get_mut(&asset_id)
+            .expect("asset is defined")
+            .transfer_policy
+            .burnable_by_holder = true;
+
+        // An asset created before counters were kept has none recorded.
+        ledger.asset_supply.clear();
+        assert_eq!(
+            ledger.asset_supply(&asset_id),
+            Some(AssetSupply {
+                minted: 5,
+                burned: 0,
+            })
+        );
+        assert_eq!(ledger.asset_supply(&AssetId([87u8; 32])), None);
+
+        let burn = spend_all_outputs(
+            &create,
+            vec![vec![AssetAmount {
+                asset_id: asset_id.clone(),
+                amount: 3,
+            }]],
+        );
+        // Counters that already account for every minted unit cannot burn more.
+        let mut exhausted = ledger.clone();
+        exhausted.asset_supply.insert(
+            asset_id.clone(),
+            AssetSupply {
+                minted: 5,
+                burned: 5,
+            },
+        );
+        assert!(matches!(
+            exhausted.apply_transaction(&burn, &engine, 2, 0, TEST_CHAIN_ID),
+            Err(LedgerError::SupplyInvariantViolated)
+        ));
+        assert_eq!(exhausted.asset_supply[&asset_id].burned, 5);
+
+        ledger
+            .apply_transaction(&burn, &engine, 2, 0, TEST_CHAIN_ID)
+            .expect("the holder may burn units of a seeded asset");
+        assert_eq!(
+            ledger.asset_supply(&asset_id),
+            Some(AssetSupply {
+                minted: 5,
+                burned: 2,
+            })
+        );
+        assert_eq!(ledger.asset_supply[&asset_id].circulating(), Some(3));
+    }
+
+    #[test]
+    fn new_chains_apply_the_kind_rules_from_genesis() {
+        let engine = DeterministicScriptEngine::default();
+        let new_chain = TEST_CHAIN_ID + 7;
+        assert_eq!(ChainActivations::for_chain(new_chain).asset_kind_rules, 0);
+
+        let mut ledger = LedgerState::default();
+        let (_, create) = create_kind_transaction(
+            &mut ledger,
+            AssetKind::NonFungible,
+            0,
+            5,
+            Vec::new(),
+            &[(None, 3), (None, 2)],
+        );
+        assert!(matches!(
+            ledger
+                .clone()
+                .apply_transaction(&create, &engine, 1, 0, new_chain),
+            Err(LedgerError::InvalidNonFungibleSupply)
+        ));
+        ledger
+            .apply_transaction(&create, &engine, 1, 0, TEST_CHAIN_ID)
+            .expect("the chain with history accepts it below its activation");
+        assert_eq!(ledger.legacy_non_fungible.len(), 1);
     }
 }
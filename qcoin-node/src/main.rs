@@ -1,15 +1,27 @@
 mod node;
+mod policy;
 mod wire;
 
 use clap::{ArgAction, Parser, Subcommand, ValueEnum};
+use policy::StandardnessPolicy;
 use qcoin_consensus::{validate_replayed_block, ConsensusEngine, DummyConsensusEngine};
 use qcoin_crypto::{default_registry, PqSchemeRegistry, PrivateKey, PublicKey, SignatureSchemeId};
-use qcoin_ledger::{ChainState, LedgerState, TrackedOutput, UtxoKey};
-use qcoin_script::DeterministicScriptEngine;
-use qcoin_types::{AssetDefinition, AssetId, Block, Hash256, Transaction};
+use qcoin_ledger::{
+    AssetSupply, ChainState, CollectionItem, FrozenOutput, InputTrace, LedgerError, LedgerState,
+    MetadataUpdate, SubAsset, TrackedOutput, UtxoKey,
+};
+use qcoin_script::{
+    asm, consensus_codec as script_codec,
+    tree::{self as script_tree, ScriptTree},
+    DeterministicScriptEngine, OpCode, Script, UnknownVersionPolicy, VmConfig,
+};
+use qcoin_types::{
+    address, consensus_codec, AssetDefinition, AssetId, Block, CollectionDefinition, CollectionId,
+    Hash256, Transaction,
+};
 use serde::{Deserialize, Serialize};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ffi::OsString,
     fs::{self, File},
     io::{Read, Write},
@@ -76,12 +88,47 @@ enum Commands {
         target: String,
         #[arg(long, default_value_t = 3)]
         timeout_seconds: u64,
+        /// Chain whose addresses the transaction JSON may use for script hashes
+        #[arg(long, default_value_t = DEFAULT_CHAIN_ID)]
+        chain_id: u32,
     },
     /// Generate a new PQ keypair using the dummy scheme
     Keygen {
         #[arg(long, value_enum, default_value_t = SchemeArg::Dilithium2)]
         scheme: SchemeArg,
     },
+    /// Replay a transaction against the local UTXO set and print each input's script trace
+    TraceTx {
+        #[arg(long)]
+        tx_json: PathBuf,
+        #[arg(long, default_value = "data/qcoin-chain-state.json")]
+        state_path: PathBuf,
+    },
+    /// Assemble, disassemble, or hash scripts in the qcoin-script text format
+    Script {
+        /// Chain whose address prefix is used when printing addresses
+        #[arg(long, default_value_t = DEFAULT_CHAIN_ID)]
+        chain_id: u32,
+        #[command(subcommand)]
+        command: ScriptCommand,
+    },
+}
+
+#[derive(Subcommand)]
+enum ScriptCommand {
+    /// Assemble script text (path or `-` for stdin) into hex consensus bytes
+    Asm { source: PathBuf },
+    /// Disassemble hex consensus bytes into script text
+    Disasm { script_hex: String },
+    /// Print the owner_script_hash for script text (path or `-` for stdin)
+    Hash { source: PathBuf },
+    /// Build a script tree from leaf script files and print its root and leaf paths
+    Tree {
+        #[arg(required = true)]
+        sources: Vec<PathBuf>,
+    },
+    /// Convert an owner_script_hash (hex or address) into its address for the chain
+    Address { script_hash: String },
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum, Serialize, Deserialize)]
@@ -106,6 +153,34 @@ struct KeypairOutput {
     private_key_hex: String,
 }
 
+#[derive(Serialize, Deserialize)]
+struct AssembledScriptOutput {
+    script_hex: String,
+    owner_script_hash_hex: String,
+    address: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScriptAddressOutput {
+    owner_script_hash_hex: String,
+    address: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScriptTreeOutput {
+    owner_script_hash_hex: String,
+    address: String,
+    leaves: Vec<ScriptTreeLeafOutput>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScriptTreeLeafOutput {
+    source: String,
+    script_hex: String,
+    leaf_hash_hex: String,
+    merkle_path_hex: Vec<String>,
+}
+
 #[derive(Default, Serialize, Deserialize)]
 struct NetworkConfig {
     #[serde(default)]
@@ -118,6 +193,8 @@ struct NetworkConfig {
     multicast_v4: Vec<MulticastV4Config>,
     #[serde(default)]
     multicast_v6: Vec<MulticastV6Config>,
+    #[serde(default)]
+    standardness: StandardnessPolicy,
 }
 
 #[derive(Default, Serialize, Deserialize)]
@@ -144,6 +221,46 @@ struct TipResponse {
     last_timestamp: u64,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CollectionMemberResponse {
+    serial: u64,
+    asset_id_hex: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CollectionResponse {
+    collection_id_hex: String,
+    issuer_script_hash_hex: String,
+    metadata_root_hex: String,
+    members: Vec<CollectionMemberResponse>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct MetadataUpdateResponse {
+    metadata_root_hex: String,
+    height: u64,
+    tx_id_hex: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct AssetMetadataResponse {
+    asset_id_hex: String,
+    original_metadata_root_hex: String,
+    current_metadata_root_hex: String,
+    updates: Vec<MetadataUpdateResponse>,
+}
+
+/// Supply amounts are decimal strings: JSON numbers cannot carry every `u128`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct AssetResponse {
+    asset_id_hex: String,
+    definition: AssetDefinition,
+    minted: String,
+    burned: String,
+    circulating: String,
+    holder_count: usize,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 struct SubmitBlockResponse {
     accepted: bool,
@@ -186,6 +303,9 @@ struct NodeRuntime {
     pending_transactions: Vec<Transaction>,
     consensus: DummyConsensusEngine,
     script_engine: DeterministicScriptEngine,
+    /// Consensus rules plus relay policy, used only to admit new mempool transactions.
+    policy_script_engine: DeterministicScriptEngine,
+    standardness: StandardnessPolicy,
     state_path: PathBuf,
     blocks_path: PathBuf,
     node_public_key_hex: String,
@@ -205,10 +325,54 @@ struct PersistedAssetEntry {
     definition: AssetDefinition,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PersistedSubAssetEntry {
+    asset_id: AssetId,
+    sub_asset: SubAsset,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PersistedCollectionEntry {
+    collection_id: CollectionId,
+    collection: CollectionDefinition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PersistedCollectionItemEntry {
+    asset_id: AssetId,
+    item: CollectionItem,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PersistedMetadataHistoryEntry {
+    asset_id: AssetId,
+    updates: Vec<MetadataUpdate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct PersistedAssetSupplyEntry {
+    asset_id: AssetId,
+    supply: AssetSupply,
+}
+
 #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
 struct PersistedLedgerState {
     utxos: Vec<PersistedUtxoEntry>,
     assets: Vec<PersistedAssetEntry>,
+    #[serde(default)]
+    sub_assets: Vec<PersistedSubAssetEntry>,
+    #[serde(default)]
+    legacy_non_fungible: Vec<AssetId>,
+    #[serde(default)]
+    collections: Vec<PersistedCollectionEntry>,
+    #[serde(default)]
+    collection_items: Vec<PersistedCollectionItemEntry>,
+    #[serde(default)]
+    frozen_outputs: Vec<FrozenOutput>,
+    #[serde(default)]
+    metadata_updates: Vec<PersistedMetadataHistoryEntry>,
+    #[serde(default)]
+    asset_supply: Vec<PersistedAssetSupplyEntry>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -219,6 +383,12 @@ struct PersistedChainState {
     state_root: Hash256,
     last_timestamp: u64,
     chain_id: u32,
+    #[serde(default)]
+    recent_timestamps: Vec<u64>,
+    #[serde(default)]
+    witness_commitments: bool,
+    #[serde(default)]
+    header_version: u32,
 }
 
 enum TransactionAcceptStatus {
@@ -265,8 +435,18 @@ fn main() {
             tx_json,
             target,
             timeout_seconds,
-        } => submit_transaction_via_udp(tx_json, target, timeout_seconds),
+            chain_id,
+        } => submit_transaction_via_udp(tx_json, target, timeout_seconds, chain_id),
         Commands::Keygen { scheme } => generate_keypair(scheme),
+        Commands::TraceTx {
+            tx_json,
+            state_path,
+        } => trace_transaction(tx_json, state_path),
+        Commands::Script { chain_id, command } => {
+            if let Err(err) = run_script_command(command, chain_id) {
+                eprintln!("{err}");
+            }
+        }
     }
 }
 
@@ -423,12 +603,18 @@ fn run_node(
     println!("Node state path: {}", state_path.display());
     println!("Node blocks path: {}", blocks_path.display());
 
+    let chain_id = chain.chain_id;
     let runtime = Arc::new(Mutex::new(NodeRuntime {
         chain,
         blocks,
         pending_transactions: Vec::new(),
         consensus,
-        script_engine: DeterministicScriptEngine::default(),
+        script_engine: DeterministicScriptEngine::for_chain(chain_id),
+        policy_script_engine: policy_script_engine(chain_id),
+        standardness: network_config
+            .as_ref()
+            .map(|config| config.standardness.clone())
+            .unwrap_or_default(),
         state_path,
         blocks_path,
         node_public_key_hex,
@@ -526,8 +712,13 @@ fn run_node(
     let _ = server_thread.join();
 }
 
-fn submit_transaction_via_udp(tx_json: PathBuf, target: String, timeout_seconds: u64) {
-    let transaction = match load_transaction_json(&tx_json) {
+fn submit_transaction_via_udp(
+    tx_json: PathBuf,
+    target: String,
+    timeout_seconds: u64,
+    chain_id: u32,
+) {
+    let transaction = match load_transaction_json(&tx_json, chain_id) {
         Ok(transaction) => transaction,
         Err(err) => {
             eprintln!("{err}");
@@ -608,16 +799,173 @@ fn submit_transaction_via_udp(tx_json: PathBuf, target: String, timeout_seconds:
     }
 }
 
-fn load_transaction_json(path: &Path) -> Result<Transaction, String> {
+fn trace_transaction(tx_json: PathBuf, state_path: PathBuf) {
+    let chain = match load_chain_state(&state_path) {
+        Ok(Some(chain)) => chain,
+        Ok(None) => {
+            eprintln!("No chain state found at {}", state_path.display());
+            return;
+        }
+        Err(err) => {
+            eprintln!("{err}");
+            return;
+        }
+    };
+    let transaction = match load_transaction_json(&tx_json, chain.chain_id) {
+        Ok(transaction) => transaction,
+        Err(err) => {
+            eprintln!("{err}");
+            return;
+        }
+    };
+
+    // Trace as if the transaction were included in the next block.
+    let height = chain.height + 1;
+    println!(
+        "Tracing tx {} at height {} on chain {}",
+        to_hex(&transaction.tx_id()),
+        height,
+        chain.chain_id
+    );
+    let engine = DeterministicScriptEngine::for_chain(chain.chain_id);
+    for input in chain.ledger.trace_transaction(
+        &transaction,
+        &engine,
+        height,
+        chain.median_time_past(),
+        chain.chain_id,
+    ) {
+        print!("{}", format_input_trace(&input));
+    }
+}
+
+fn format_input_trace(input: &InputTrace) -> String {
+    let mut out = match &input.result {
+        Ok(()) => format!("input {}: ok\n", input.input_index),
+        Err(err) => format!("input {}: failed: {err}\n", input.input_index),
+    };
+    for step in &input.trace.steps {
+        out.push_str(&format!(
+            "  [{}] {}  gas +{} = {}\n",
+            step.position,
+            match &step.op {
+                OpCode::PushBytes(data) => format!("PUSH {}", format_trace_item(data)),
+                op => op.to_string(),
+            },
+            step.gas_cost,
+            step.gas_used
+        ));
+        out.push_str(&format!(
+            "      before: {}\n",
+            format_trace_stack(&step.stack_before)
+        ));
+        out.push_str(&format!(
+            "      after:  {}\n",
+            format_trace_stack(&step.stack_after)
+        ));
+        if let Some(error) = &step.error {
+            out.push_str(&format!("      error:  {error}\n"));
+        }
+    }
+    out
+}
+
+fn format_trace_stack(items: &[Vec<u8>]) -> String {
+    let items: Vec<String> = items.iter().map(|item| format_trace_item(item)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// Hex with a `0x` prefix, shortened for large items such as keys and signatures.
+fn format_trace_item(item: &[u8]) -> String {
+    const MAX_SHOWN: usize = 32;
+    if item.len() <= MAX_SHOWN {
+        format!("0x{}", to_hex(item))
+    } else {
+        format!("0x{}..({} bytes)", to_hex(&item[..MAX_SHOWN]), item.len())
+    }
+}
+
+fn load_transaction_json(path: &Path, chain_id: u32) -> Result<Transaction, String> {
     let text = fs::read_to_string(path)
         .map_err(|err| format!("Failed to read transaction JSON {}: {err}", path.display()))?;
-    serde_json::from_str(&text)
+    parse_transaction_json(&text, chain_id)
         .map_err(|err| format!("Failed to parse transaction JSON {}: {err}", path.display()))
 }
 
+/// Script hash fields in transaction JSON may be byte arrays, as serde writes them, or
+/// strings holding hex or an address for `chain_id`.
+fn parse_transaction_json(text: &str, chain_id: u32) -> Result<Transaction, String> {
+    let mut value: serde_json::Value = serde_json::from_str(text).map_err(|err| err.to_string())?;
+    resolve_script_hash_fields(&mut value, chain_id)?;
+    serde_json::from_value(value).map_err(|err| err.to_string())
+}
+
+fn resolve_script_hash_fields(value: &mut serde_json::Value, chain_id: u32) -> Result<(), String> {
+    const SCRIPT_HASH_FIELDS: [&str; 3] = [
+        "owner_script_hash",
+        "issuer_script_hash",
+        "recipient_script_hash",
+    ];
+    match value {
+        serde_json::Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                match field {
+                    serde_json::Value::String(text)
+                        if SCRIPT_HASH_FIELDS.contains(&name.as_str()) =>
+                    {
+                        let script_hash = address::parse_script_hash(text, chain_id)
+                            .map_err(|err| format!("{name}: {err}"))?;
+                        *field = serde_json::Value::from(script_hash.to_vec());
+                    }
+                    _ => resolve_script_hash_fields(field, chain_id)?,
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                resolve_script_hash_fields(item, chain_id)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Largest block body read from `POST /blocks` or from a peer's `GET /blocks/{height}`.
+const MAX_BLOCK_BODY_BYTES: u64 = 8 * 1024 * 1024;
+
+/// Reads a block body of at most [`MAX_BLOCK_BODY_BYTES`]. A longer body is an
+/// `InvalidData` error, raised without buffering more than one byte past the limit.
+fn read_block_body(reader: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    reader
+        .take(MAX_BLOCK_BODY_BYTES + 1)
+        .read_to_end(&mut body)?;
+    if body.len() as u64 > MAX_BLOCK_BODY_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("block body exceeds {MAX_BLOCK_BODY_BYTES} bytes"),
+        ));
+    }
+    Ok(body)
+}
+
+/// Blocks submitted over HTTP are either the canonical encoding, recognized by its magic,
+/// or bincode as served by `GET /blocks/{height}` without a format.
+fn decode_submitted_block(body: &[u8]) -> Result<Block, String> {
+    if body.starts_with(consensus_codec::BLOCK_MAGIC) {
+        return consensus_codec::decode_block(body)
+            .map_err(|err| format!("invalid canonical block payload: {err}"));
+    }
+    bincode::deserialize(body).map_err(|err| format!("invalid block bincode payload: {err}"))
+}
+
 fn handle_request(runtime: &mut NodeRuntime, node_info: &wire::NodeInfo, mut request: Request) {
     let method = request.method().clone();
-    let path = request.url().split('?').next().unwrap_or("/").to_string();
+    let (path, query) = match request.url().split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (request.url().to_string(), String::new()),
+    };
 
     match (method, path.as_str()) {
         (Method::Get, "/node-info") => {
@@ -643,7 +991,11 @@ fn handle_request(runtime: &mut NodeRuntime, node_info: &wire::NodeInfo, mut req
                 return;
             };
 
+            let canonical = query.split('&').any(|param| param == "format=canonical");
             match runtime.blocks.get((height - 1) as usize) {
+                Some(block) if canonical => {
+                    let _ = respond_binary(request, 200, consensus_codec::encode_block(block));
+                }
                 Some(block) => match bincode::serialize(block) {
                     Ok(payload) => {
                         let _ = respond_binary(request, 200, payload);
@@ -658,21 +1010,84 @@ fn handle_request(runtime: &mut NodeRuntime, node_info: &wire::NodeInfo, mut req
                 }
             }
         }
-        (Method::Post, "/blocks") => {
-            let mut body = Vec::new();
-            if let Err(err) = request.as_reader().read_to_end(&mut body) {
-                let _ = respond_text(request, 400, &format!("failed to read request body: {err}"));
+        (Method::Get, _) if path.starts_with("/collections/") => {
+            let collection_id = from_hex(path.trim_start_matches("/collections/"))
+                .ok()
+                .and_then(|bytes| Hash256::try_from(bytes).ok());
+            let Some(collection_id) = collection_id else {
+                let _ = respond_text(request, 400, "collection id must be 64 hex characters");
                 return;
+            };
+
+            match collection_response(&runtime.chain.ledger, &CollectionId(collection_id)) {
+                Some(response) => {
+                    let _ = respond_json(request, 200, &response);
+                }
+                None => {
+                    let _ = respond_text(request, 404, "collection not found");
+                }
             }
+        }
+        (Method::Get, _) if path.starts_with("/assets/") && path.ends_with("/metadata") => {
+            let asset_id = from_hex(
+                path.trim_start_matches("/assets/")
+                    .trim_end_matches("/metadata"),
+            )
+            .ok()
+            .and_then(|bytes| Hash256::try_from(bytes).ok());
+            let Some(asset_id) = asset_id else {
+                let _ = respond_text(request, 400, "asset id must be 64 hex characters");
+                return;
+            };
+
+            match asset_metadata_response(&runtime.chain.ledger, &AssetId(asset_id)) {
+                Some(response) => {
+                    let _ = respond_json(request, 200, &response);
+                }
+                None => {
+                    let _ = respond_text(request, 404, "asset not found");
+                }
+            }
+        }
+        (Method::Get, _) if path.starts_with("/assets/") => {
+            let asset_id = from_hex(path.trim_start_matches("/assets/"))
+                .ok()
+                .and_then(|bytes| Hash256::try_from(bytes).ok());
+            let Some(asset_id) = asset_id else {
+                let _ = respond_text(request, 400, "asset id must be 64 hex characters");
+                return;
+            };
+
+            match asset_response(&runtime.chain.ledger, &AssetId(asset_id)) {
+                Ok(Some(response)) => {
+                    let _ = respond_json(request, 200, &response);
+                }
+                Ok(None) => {
+                    let _ = respond_text(request, 404, "asset not found");
+                }
+                Err(err) => {
+                    let _ = respond_text(request, 500, &err);
+                }
+            }
+        }
+        (Method::Post, "/blocks") => {
+            let body = match read_block_body(request.as_reader()) {
+                Ok(body) => body,
+                Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                    let _ = respond_text(request, 413, &err.to_string());
+                    return;
+                }
+                Err(err) => {
+                    let _ =
+                        respond_text(request, 400, &format!("failed to read request body: {err}"));
+                    return;
+                }
+            };
 
-            let block: Block = match bincode::deserialize(&body) {
+            let block = match decode_submitted_block(&body) {
                 Ok(block) => block,
                 Err(err) => {
-                    let _ = respond_text(
-                        request,
-                        400,
-                        &format!("invalid block bincode payload: {err}"),
-                    );
+                    let _ = respond_text(request, 400, &err);
                     return;
                 }
             };
@@ -702,6 +1117,71 @@ fn handle_request(runtime: &mut NodeRuntime, node_info: &wire::NodeInfo, mut req
     }
 }
 
+fn collection_response(
+    ledger: &LedgerState,
+    collection_id: &CollectionId,
+) -> Option<CollectionResponse> {
+    let collection = ledger.collections.get(collection_id)?;
+    Some(CollectionResponse {
+        collection_id_hex: to_hex(&collection_id.0),
+        issuer_script_hash_hex: to_hex(&collection.issuer_script_hash),
+        metadata_root_hex: to_hex(&collection.metadata_root),
+        members: ledger
+            .collection_items(collection_id)
+            .into_iter()
+            .map(|(serial, asset_id)| CollectionMemberResponse {
+                serial,
+                asset_id_hex: to_hex(&asset_id.0),
+            })
+            .collect(),
+    })
+}
+
+fn asset_metadata_response(
+    ledger: &LedgerState,
+    asset_id: &AssetId,
+) -> Option<AssetMetadataResponse> {
+    let definition = ledger.assets.get(asset_id)?;
+    Some(AssetMetadataResponse {
+        asset_id_hex: to_hex(&asset_id.0),
+        original_metadata_root_hex: to_hex(&definition.metadata_root),
+        current_metadata_root_hex: to_hex(&ledger.current_metadata_root(asset_id)?),
+        updates: ledger
+            .metadata_history(asset_id)
+            .iter()
+            .map(|update| MetadataUpdateResponse {
+                metadata_root_hex: to_hex(&update.metadata_root),
+                height: update.height,
+                tx_id_hex: to_hex(&update.tx_id),
+            })
+            .collect(),
+    })
+}
+
+fn asset_response(
+    ledger: &LedgerState,
+    asset_id: &AssetId,
+) -> Result<Option<AssetResponse>, String> {
+    let Some(definition) = ledger.asset_definition(asset_id) else {
+        return Ok(None);
+    };
+    let supply = ledger.asset_supply(asset_id).unwrap_or_default();
+    let circulating = supply.circulating().ok_or_else(|| {
+        format!(
+            "supply counters of asset {} burn more units than were minted",
+            to_hex(&asset_id.0)
+        )
+    })?;
+    Ok(Some(AssetResponse {
+        asset_id_hex: to_hex(&asset_id.0),
+        definition: definition.clone(),
+        minted: supply.minted.to_string(),
+        burned: supply.burned.to_string(),
+        circulating: circulating.to_string(),
+        holder_count: ledger.holder_count(asset_id),
+    }))
+}
+
 fn sync_all_peers_http(runtime: &Arc<Mutex<NodeRuntime>>, peers: &[String]) {
     for peer in peers {
         if let Err(err) = sync_from_peer_http(runtime, peer) {
@@ -757,10 +1237,7 @@ fn sync_from_peer_http(runtime: &Arc<Mutex<NodeRuntime>>, peer: &str) -> Result<
             .timeout(Duration::from_secs(3))
             .call()
             .map_err(|err| format!("block fetch failed at {next_height}: {err}"))?;
-        let mut block_bytes = Vec::new();
-        response
-            .into_reader()
-            .read_to_end(&mut block_bytes)
+        let block_bytes = read_block_body(response.into_reader())
             .map_err(|err| format!("block read failed at {next_height}: {err}"))?;
         let block: Block = bincode::deserialize(&block_bytes)
             .map_err(|err| format!("block parse failed at {next_height}: {err}"))?;
@@ -831,6 +1308,16 @@ fn apply_block(runtime: &mut NodeRuntime, block: Block) -> Result<u64, String> {
     Ok(runtime.chain.height)
 }
 
+/// Script engine for mempool admission. Outputs locked to script versions this node does
+/// not know are anyone-can-spend under consensus, so they are not relayed until upgraded
+/// nodes enforce their rules.
+fn policy_script_engine(chain_id: u32) -> DeterministicScriptEngine {
+    DeterministicScriptEngine::with_config(VmConfig {
+        unknown_versions: UnknownVersionPolicy::Reject,
+        ..VmConfig::for_chain(chain_id)
+    })
+}
+
 fn accept_transaction(
     runtime: &mut NodeRuntime,
     transaction: Transaction,
@@ -850,14 +1337,25 @@ fn accept_transaction(
         return Ok(TransactionAcceptStatus::AlreadyPending(tx_id));
     }
 
-    let mut ledger = runtime.chain.ledger.clone();
     let block_height = runtime.chain.height.saturating_add(1);
+    runtime
+        .standardness
+        .check(
+            &transaction,
+            runtime.policy_script_engine.config(),
+            block_height,
+        )
+        .map_err(|err| format!("Transaction rejected by mempool policy: {err}"))?;
+
+    let mut ledger = runtime.chain.ledger.clone();
+    let median_time_past = runtime.chain.median_time_past();
     for pending in &runtime.pending_transactions {
         ledger
             .apply_transaction(
                 pending,
                 &runtime.script_engine,
                 block_height,
+                median_time_past,
                 runtime.chain.chain_id,
             )
             .map_err(|err| format!("Pending mempool transaction became invalid: {err}"))?;
@@ -865,8 +1363,9 @@ fn accept_transaction(
     ledger
         .apply_transaction(
             &transaction,
-            &runtime.script_engine,
+            &runtime.policy_script_engine,
             block_height,
+            median_time_past,
             runtime.chain.chain_id,
         )
         .map_err(|err| format!("Failed to validate transaction for mempool admission: {err}"))?;
@@ -885,22 +1384,46 @@ fn reconcile_pending_transactions(runtime: &mut NodeRuntime) {
         .iter()
         .flat_map(|block| block.transactions.iter().map(Transaction::tx_id))
         .collect::<std::collections::HashSet<_>>();
-    let mut retained = Vec::with_capacity(runtime.pending_transactions.len());
-    let mut simulated_ledger = runtime.chain.ledger.clone();
-    let next_height = runtime.chain.height.saturating_add(1);
+    let pending = std::mem::take(&mut runtime.pending_transactions);
+    runtime.pending_transactions = retain_pending_transactions(
+        &runtime.chain,
+        &committed_tx_ids,
+        pending,
+        &runtime.script_engine,
+    );
+}
 
-    for transaction in runtime.pending_transactions.drain(..) {
+/// Pending transactions that still apply, in order, on top of `chain` at the next height.
+/// Committed ones are dropped, as are ones the new tip invalidated or whose
+/// `valid_until_height` it has passed.
+fn retain_pending_transactions(
+    chain: &ChainState,
+    committed_tx_ids: &std::collections::HashSet<Hash256>,
+    pending: Vec<Transaction>,
+    script_engine: &DeterministicScriptEngine,
+) -> Vec<Transaction> {
+    let mut retained = Vec::with_capacity(pending.len());
+    let mut simulated_ledger = chain.ledger.clone();
+    let next_height = chain.height.saturating_add(1);
+    let median_time_past = chain.median_time_past();
+
+    for transaction in pending {
         let tx_id = transaction.tx_id();
         if committed_tx_ids.contains(&tx_id) {
             continue;
         }
         match simulated_ledger.apply_transaction(
             &transaction,
-            &runtime.script_engine,
+            script_engine,
             next_height,
-            runtime.chain.chain_id,
+            median_time_past,
+            chain.chain_id,
         ) {
             Ok(()) => retained.push(transaction),
+            Err(err @ LedgerError::TransactionExpired { .. }) => eprintln!(
+                "Evicting expired pending transaction {}: {err}",
+                to_hex(&tx_id)
+            ),
             Err(err) => eprintln!(
                 "Dropping pending transaction {} after chain update: {err}",
                 to_hex(&tx_id)
@@ -908,7 +1431,7 @@ fn reconcile_pending_transactions(runtime: &mut NodeRuntime) {
         }
     }
 
-    runtime.pending_transactions = retained;
+    retained
 }
 
 fn transaction_is_committed(runtime: &NodeRuntime, tx_id: Hash256) -> bool {
@@ -1394,10 +1917,7 @@ fn default_chain_state() -> ChainState {
 }
 
 fn default_chain_state_with_id(chain_id: u32) -> ChainState {
-    let ledger = LedgerState {
-        utxos: Default::default(),
-        assets: Default::default(),
-    };
+    let ledger = LedgerState::default();
     let state_root = ledger.state_root();
     ChainState {
         ledger,
@@ -1406,6 +1926,9 @@ fn default_chain_state_with_id(chain_id: u32) -> ChainState {
         state_root,
         last_timestamp: 0,
         chain_id,
+        recent_timestamps: Vec::new(),
+        witness_commitments: false,
+        header_version: 0,
     }
 }
 
@@ -1438,13 +1961,98 @@ impl From<&ChainState> for PersistedChainState {
             .collect::<Vec<_>>();
         assets.sort_by(|left, right| left.asset_id.0.cmp(&right.asset_id.0));
 
+        let mut sub_assets = chain
+            .ledger
+            .sub_assets
+            .iter()
+            .map(|(asset_id, sub_asset)| PersistedSubAssetEntry {
+                asset_id: asset_id.clone(),
+                sub_asset: sub_asset.clone(),
+            })
+            .collect::<Vec<_>>();
+        sub_assets.sort_by_key(|entry| entry.asset_id.0);
+
+        let mut legacy_non_fungible = chain
+            .ledger
+            .legacy_non_fungible
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        legacy_non_fungible.sort_by_key(|asset_id| asset_id.0);
+
+        let mut collections = chain
+            .ledger
+            .collections
+            .iter()
+            .map(|(collection_id, collection)| PersistedCollectionEntry {
+                collection_id: collection_id.clone(),
+                collection: collection.clone(),
+            })
+            .collect::<Vec<_>>();
+        collections.sort_by_key(|entry| entry.collection_id.0);
+
+        let mut collection_items = chain
+            .ledger
+            .collection_items
+            .iter()
+            .map(|(asset_id, item)| PersistedCollectionItemEntry {
+                asset_id: asset_id.clone(),
+                item: item.clone(),
+            })
+            .collect::<Vec<_>>();
+        collection_items.sort_by_key(|entry| entry.asset_id.0);
+
+        let mut frozen_outputs = chain
+            .ledger
+            .frozen_outputs
+            .iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        frozen_outputs
+            .sort_by_key(|frozen| (frozen.key.tx_id, frozen.key.index, frozen.asset_id.0));
+
+        let mut metadata_updates = chain
+            .ledger
+            .metadata_updates
+            .iter()
+            .map(|(asset_id, updates)| PersistedMetadataHistoryEntry {
+                asset_id: asset_id.clone(),
+                updates: updates.clone(),
+            })
+            .collect::<Vec<_>>();
+        metadata_updates.sort_by_key(|entry| entry.asset_id.0);
+
+        let mut asset_supply = chain
+            .ledger
+            .asset_supply
+            .iter()
+            .map(|(asset_id, supply)| PersistedAssetSupplyEntry {
+                asset_id: asset_id.clone(),
+                supply: supply.clone(),
+            })
+            .collect::<Vec<_>>();
+        asset_supply.sort_by_key(|entry| entry.asset_id.0);
+
         Self {
-            ledger: PersistedLedgerState { utxos, assets },
+            ledger: PersistedLedgerState {
+                utxos,
+                assets,
+                sub_assets,
+                legacy_non_fungible,
+                collections,
+                collection_items,
+                frozen_outputs,
+                metadata_updates,
+                asset_supply,
+            },
             height: chain.height,
             tip_hash: chain.tip_hash,
             state_root: chain.state_root,
             last_timestamp: chain.last_timestamp,
             chain_id: chain.chain_id,
+            recent_timestamps: chain.recent_timestamps.clone(),
+            witness_commitments: chain.witness_commitments,
+            header_version: chain.header_version,
         }
     }
 }
@@ -1478,13 +2086,118 @@ impl PersistedChainState {
             }
         }
 
+        let mut sub_assets = HashMap::with_capacity(self.ledger.sub_assets.len());
+        for entry in self.ledger.sub_assets {
+            if sub_assets
+                .insert(entry.asset_id.clone(), entry.sub_asset)
+                .is_some()
+            {
+                return Err(format!(
+                    "duplicate sub-asset entry in persisted chain state for asset {}",
+                    to_hex(&entry.asset_id.0)
+                ));
+            }
+        }
+
+        let mut legacy_non_fungible = HashSet::with_capacity(self.ledger.legacy_non_fungible.len());
+        for asset_id in self.ledger.legacy_non_fungible {
+            if !legacy_non_fungible.insert(asset_id.clone()) {
+                return Err(format!(
+                    "duplicate legacy non-fungible entry in persisted chain state for asset {}",
+                    to_hex(&asset_id.0)
+                ));
+            }
+        }
+
+        let mut collections = HashMap::with_capacity(self.ledger.collections.len());
+        for entry in self.ledger.collections {
+            if collections
+                .insert(entry.collection_id.clone(), entry.collection)
+                .is_some()
+            {
+                return Err(format!(
+                    "duplicate collection entry in persisted chain state for collection {}",
+                    to_hex(&entry.collection_id.0)
+                ));
+            }
+        }
+
+        let mut collection_items = HashMap::with_capacity(self.ledger.collection_items.len());
+        for entry in self.ledger.collection_items {
+            if collection_items
+                .insert(entry.asset_id.clone(), entry.item)
+                .is_some()
+            {
+                return Err(format!(
+                    "duplicate collection item entry in persisted chain state for asset {}",
+                    to_hex(&entry.asset_id.0)
+                ));
+            }
+        }
+
+        let mut frozen_outputs = HashSet::with_capacity(self.ledger.frozen_outputs.len());
+        for frozen in self.ledger.frozen_outputs {
+            if !frozen_outputs.insert(frozen.clone()) {
+                return Err(format!(
+                    "duplicate frozen output entry in persisted chain state for {}:{}",
+                    to_hex(&frozen.key.tx_id),
+                    frozen.key.index
+                ));
+            }
+        }
+
+        let mut metadata_updates = HashMap::with_capacity(self.ledger.metadata_updates.len());
+        for entry in self.ledger.metadata_updates {
+            if metadata_updates
+                .insert(entry.asset_id.clone(), entry.updates)
+                .is_some()
+            {
+                return Err(format!(
+                    "duplicate metadata history entry in persisted chain state for asset {}",
+                    to_hex(&entry.asset_id.0)
+                ));
+            }
+        }
+
+        let mut asset_supply = HashMap::with_capacity(self.ledger.asset_supply.len());
+        for entry in self.ledger.asset_supply {
+            if entry.supply.circulating().is_none() {
+                return Err(format!(
+                    "asset supply entry in persisted chain state burns more than was minted for asset {}",
+                    to_hex(&entry.asset_id.0)
+                ));
+            }
+            if asset_supply
+                .insert(entry.asset_id.clone(), entry.supply)
+                .is_some()
+            {
+                return Err(format!(
+                    "duplicate asset supply entry in persisted chain state for asset {}",
+                    to_hex(&entry.asset_id.0)
+                ));
+            }
+        }
+
         Ok(ChainState {
-            ledger: LedgerState { utxos, assets },
+            ledger: LedgerState {
+                utxos,
+                assets,
+                sub_assets,
+                legacy_non_fungible,
+                collections,
+                collection_items,
+                frozen_outputs,
+                metadata_updates,
+                asset_supply,
+            },
             height: self.height,
             tip_hash: self.tip_hash,
             state_root: self.state_root,
             last_timestamp: self.last_timestamp,
             chain_id: self.chain_id,
+            recent_timestamps: self.recent_timestamps,
+            witness_commitments: self.witness_commitments,
+            header_version: self.header_version,
         })
     }
 }
@@ -1531,6 +2244,9 @@ fn load_or_repair_storage(
                 || chain.tip_hash != rebuilt_chain.tip_hash
                 || chain.state_root != rebuilt_chain.state_root
                 || chain.last_timestamp != rebuilt_chain.last_timestamp
+                || chain.recent_timestamps != rebuilt_chain.recent_timestamps
+                || chain.witness_commitments != rebuilt_chain.witness_commitments
+                || chain.header_version != rebuilt_chain.header_version
         }
         None => !stored_blocks.is_empty(),
     };
@@ -1554,7 +2270,7 @@ fn rebuild_chain_state_from_blocks(
 ) -> Result<ChainState, String> {
     let mut chain = default_chain_state_with_id(expected_chain_id);
     let registry = default_registry();
-    let script_engine = DeterministicScriptEngine::default();
+    let script_engine = DeterministicScriptEngine::for_chain(expected_chain_id);
 
     for (index, block) in blocks.iter().enumerate() {
         validate_replayed_block(&registry, &chain, block, validators).map_err(|err| {
@@ -1712,6 +2428,88 @@ fn generate_keypair(scheme: SchemeArg) {
     println!("{}", json);
 }
 
+fn run_script_command(command: ScriptCommand, chain_id: u32) -> Result<(), String> {
+    match command {
+        ScriptCommand::Asm { source } => {
+            let script = assemble_script_source(&source)?;
+            let output = AssembledScriptOutput {
+                script_hex: to_hex(&script_codec::encode_script(&script)),
+                owner_script_hash_hex: to_hex(&script.script_hash()),
+                address: address::encode_address(&script.script_hash(), chain_id),
+            };
+            let json = serde_json::to_string_pretty(&output).expect("serialization should succeed");
+            println!("{}", json);
+        }
+        ScriptCommand::Disasm { script_hex } => {
+            let bytes = from_hex(&script_hex)?;
+            let script = script_codec::decode_script(&bytes)
+                .map_err(|err| format!("Failed to decode script: {err}"))?;
+            println!("{}", asm::disassemble(&script));
+        }
+        ScriptCommand::Hash { source } => {
+            let script = assemble_script_source(&source)?;
+            println!("{}", to_hex(&script.script_hash()));
+        }
+        ScriptCommand::Tree { sources } => {
+            let scripts = sources
+                .iter()
+                .map(|source| assemble_script_source(source))
+                .collect::<Result<Vec<_>, _>>()?;
+            let tree = ScriptTree::new(scripts)
+                .map_err(|err| format!("Failed to build script tree: {err}"))?;
+            let leaves = sources
+                .iter()
+                .zip(tree.leaves())
+                .enumerate()
+                .map(|(index, (source, script))| ScriptTreeLeafOutput {
+                    source: source.display().to_string(),
+                    script_hex: to_hex(&script_codec::encode_script(script)),
+                    leaf_hash_hex: to_hex(&script_tree::leaf_hash(script)),
+                    merkle_path_hex: tree
+                        .path(index)
+                        .expect("leaf index is in range")
+                        .iter()
+                        .map(|hash| to_hex(hash))
+                        .collect(),
+                })
+                .collect();
+            let output = ScriptTreeOutput {
+                owner_script_hash_hex: to_hex(&tree.root()),
+                address: address::encode_address(&tree.root(), chain_id),
+                leaves,
+            };
+            let json = serde_json::to_string_pretty(&output).expect("serialization should succeed");
+            println!("{}", json);
+        }
+        ScriptCommand::Address { script_hash } => {
+            let script_hash = address::parse_script_hash(&script_hash, chain_id)
+                .map_err(|err| format!("Invalid script hash: {err}"))?;
+            let output = ScriptAddressOutput {
+                owner_script_hash_hex: to_hex(&script_hash),
+                address: address::encode_address(&script_hash, chain_id),
+            };
+            let json = serde_json::to_string_pretty(&output).expect("serialization should succeed");
+            println!("{}", json);
+        }
+    }
+    Ok(())
+}
+
+fn assemble_script_source(source: &Path) -> Result<Script, String> {
+    let text = if source == Path::new("-") {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|err| format!("Failed to read script from stdin: {err}"))?;
+        text
+    } else {
+        fs::read_to_string(source)
+            .map_err(|err| format!("Failed to read script {}: {err}", source.display()))?
+    };
+
+    asm::assemble(&text).map_err(|err| format!("Failed to assemble {}: {err}", source.display()))
+}
+
 fn scheme_name(id: SignatureSchemeId) -> String {
     id.to_string()
 }
@@ -1750,20 +2548,59 @@ fn decode_hex_nibble(byte: u8) -> Result<u8, String> {
 #[cfg(test)]
 mod tests {
     use super::{
+        asset_metadata_response, asset_response, collection_response, decode_submitted_block,
         default_chain_state, default_chain_state_with_id, default_multicast_v6_configs,
-        load_chain_state, load_or_initialize_chain_state, load_or_repair_storage,
-        merge_unique_hex_strings, resolve_produce_mode, save_block_history, save_chain_state,
-        write_file_atomically, ChainState, DEFAULT_CHAIN_ID,
+        format_input_trace, load_chain_state, load_or_initialize_chain_state,
+        load_or_repair_storage, merge_unique_hex_strings, parse_transaction_json, read_block_body,
+        resolve_produce_mode, retain_pending_transactions, save_block_history, save_chain_state,
+        to_hex, write_file_atomically, ChainState, CollectionMemberResponse,
+        MetadataUpdateResponse, DEFAULT_CHAIN_ID, MAX_BLOCK_BODY_BYTES,
     };
     use qcoin_consensus::{ConsensusEngine, DummyConsensusEngine};
     use qcoin_crypto::{default_registry, PqSchemeRegistry, SignatureSchemeId};
-    use qcoin_ledger::{TrackedOutput, UtxoKey};
+    use qcoin_ledger::{
+        AssetSupply, CollectionItem, FrozenOutput, LedgerState, MetadataUpdate, SubAsset,
+        TrackedOutput, UtxoKey,
+    };
     use qcoin_script::DeterministicScriptEngine;
-    use qcoin_types::{AssetAmount, AssetDefinition, AssetId, AssetKind, Output};
+    use qcoin_types::{
+        AssetAmount, AssetDefinition, AssetId, AssetKind, CollectionDefinition, CollectionId,
+        IssuerControls, Output, RoyaltyAmount, RoyaltyRule, TransferMode, TransferPolicy,
+        QCOIN_ASSET_ID,
+    };
     use std::collections::HashMap;
+    use std::io::Read;
     use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
     use tempfile::tempdir;
 
+    #[test]
+    fn input_trace_formatting_shortens_large_items() {
+        let input = qcoin_ledger::InputTrace {
+            input_index: 2,
+            result: Ok(()),
+            trace: qcoin_script::ScriptTrace {
+                steps: vec![qcoin_script::TraceStep {
+                    position: 0,
+                    op: qcoin_script::OpCode::PushBytes(vec![0xab; 40]),
+                    stack_before: Vec::new(),
+                    stack_after: vec![vec![0xab; 40]],
+                    gas_cost: 50,
+                    gas_used: 50,
+                    error: None,
+                }],
+            },
+        };
+
+        let text = format_input_trace(&input);
+        let short = format!("0x{}..(40 bytes)", "ab".repeat(32));
+        assert_eq!(
+            text,
+            format!(
+                "input 2: ok\n  [0] PUSH {short}  gas +50 = 50\n      before: []\n      after:  [{short}]\n"
+            )
+        );
+    }
+
     #[test]
     fn produce_mode_auto_enables_manifest_validator() {
         assert!(resolve_produce_mode(None, true, true, false));
@@ -1880,6 +2717,154 @@ mod tests {
         assert_eq!(repaired.height, 1);
     }
 
+    #[test]
+    fn submitted_blocks_decode_from_canonical_or_bincode() {
+        let block = DummyConsensusEngine::default()
+            .propose_block(&default_chain_state(), Vec::new())
+            .unwrap();
+
+        let canonical = qcoin_types::consensus_codec::encode_block(&block);
+        assert_eq!(decode_submitted_block(&canonical).unwrap(), block);
+        let bincode = bincode::serialize(&block).unwrap();
+        assert_eq!(decode_submitted_block(&bincode).unwrap(), block);
+
+        let err = decode_submitted_block(&canonical[..canonical.len() - 1]).unwrap_err();
+        assert!(err.contains("canonical"));
+    }
+
+    #[test]
+    fn block_bodies_are_read_up_to_the_limit() {
+        let limit = MAX_BLOCK_BODY_BYTES as usize;
+        let body = read_block_body(std::io::repeat(7).take(limit as u64)).unwrap();
+        assert_eq!(body.len(), limit);
+
+        let err = read_block_body(std::io::repeat(7)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn reconciliation_evicts_expired_pending_transactions() {
+        let engine = DeterministicScriptEngine::default();
+        let mut chain = default_chain_state();
+        let block = DummyConsensusEngine::default()
+            .propose_block(&chain, Vec::new())
+            .unwrap();
+        chain.apply_block(&block, &engine).unwrap();
+
+        let script = qcoin_script::Script(vec![qcoin_script::OpCode::Nop]);
+        let output = Output {
+            owner_script_hash: script.script_hash(),
+            assets: Vec::new(),
+            metadata_hash: None,
+        };
+        let spent = UtxoKey {
+            tx_id: [9u8; 32],
+            index: 0,
+        };
+        chain.ledger.utxos.insert(
+            spent.clone(),
+            TrackedOutput {
+                output: output.clone(),
+                created_height: 0,
+                created_time: 0,
+            },
+        );
+        let spend = |valid_until_height| qcoin_types::Transaction {
+            core: qcoin_types::TransactionCore {
+                kind: qcoin_types::TransactionKind::Transfer,
+                inputs: vec![qcoin_types::TransactionInput {
+                    tx_id: spent.tx_id,
+                    index: spent.index,
+                }],
+                outputs: vec![output.clone()],
+                valid_from_height: None,
+                valid_until_height: Some(valid_until_height),
+            },
+            witness: qcoin_types::TransactionWitness {
+                inputs: vec![qcoin_script::ScriptEnvelope::legacy(&script)
+                    .witness(Vec::new())
+                    .encode()],
+            },
+        };
+
+        // The next block is at height 2: a window ending at 1 has passed.
+        let expired = spend(1);
+        let current = spend(2);
+        let retained = retain_pending_transactions(
+            &chain,
+            &Default::default(),
+            vec![expired, current.clone()],
+            &engine,
+        );
+        assert_eq!(retained, vec![current]);
+    }
+
+    #[test]
+    fn transaction_json_accepts_addresses_and_hex_for_script_hashes() {
+        let (_, transaction) = qcoin_types::create_asset_transaction(
+            [6u8; 32],
+            AssetKind::Fungible,
+            [0u8; 32],
+            None,
+            0,
+            10,
+            [4u8; 32],
+            7,
+        );
+        let mut value = serde_json::to_value(&transaction).unwrap();
+        assert_eq!(
+            parse_transaction_json(&value.to_string(), 7).unwrap(),
+            transaction
+        );
+
+        value["core"]["outputs"][0]["owner_script_hash"] =
+            qcoin_types::address::encode_address(&[4u8; 32], 7).into();
+        value["core"]["kind"]["CreateAsset"]["definition"]["issuer_script_hash"] =
+            "06".repeat(32).into();
+        assert_eq!(
+            parse_transaction_json(&value.to_string(), 7).unwrap(),
+            transaction
+        );
+
+        let err = parse_transaction_json(&value.to_string(), 0).unwrap_err();
+        assert!(err.contains("owner_script_hash"), "{err}");
+    }
+
+    #[test]
+    fn transaction_json_accepts_addresses_for_royalty_recipients() {
+        let (_, mut transaction) = qcoin_types::create_asset_transaction(
+            [6u8; 32],
+            AssetKind::Fungible,
+            [0u8; 32],
+            None,
+            0,
+            10,
+            [4u8; 32],
+            7,
+        );
+        let qcoin_types::TransactionKind::CreateAsset { definition, .. } =
+            &mut transaction.core.kind
+        else {
+            panic!("create_asset_transaction builds a CreateAsset");
+        };
+        definition.royalty = Some(RoyaltyRule {
+            recipient_script_hash: [8u8; 32],
+            payment_asset_id: QCOIN_ASSET_ID,
+            amount: RoyaltyAmount::BasisPoints(250),
+        });
+
+        let mut value = serde_json::to_value(&transaction).unwrap();
+        value["core"]["kind"]["CreateAsset"]["definition"]["royalty"]["recipient_script_hash"] =
+            qcoin_types::address::encode_address(&[8u8; 32], 7).into();
+        assert_eq!(
+            parse_transaction_json(&value.to_string(), 7).unwrap(),
+            transaction
+        );
+
+        let err = parse_transaction_json(&value.to_string(), 0).unwrap_err();
+        assert!(err.contains("recipient_script_hash"), "{err}");
+    }
+
     #[test]
     fn load_or_repair_storage_truncates_state_ahead_of_block_history() {
         let dir = tempdir().unwrap();
@@ -1915,6 +2900,9 @@ mod tests {
         chain.tip_hash = [9u8; 32];
         chain.state_root = [7u8; 32];
         chain.last_timestamp = 42;
+        chain.recent_timestamps = vec![40, 42];
+        chain.witness_commitments = true;
+        chain.header_version = 1;
 
         let mut utxos = HashMap::new();
         utxos.insert(
@@ -1932,6 +2920,7 @@ mod tests {
                     metadata_hash: None,
                 },
                 created_height: 2,
+                created_time: 40,
             },
         );
 
@@ -1944,12 +2933,68 @@ mod tests {
                 max_supply: Some(99),
                 decimals: 2,
                 kind: AssetKind::Fungible,
+                transfer_policy: TransferPolicy {
+                    mode: TransferMode::Soulbound,
+                    burnable_by_holder: true,
+                },
+                issuer_controls: IssuerControls {
+                    freeze: true,
+                    clawback: false,
+                },
+                royalty: Some(RoyaltyRule {
+                    recipient_script_hash: [7u8; 32],
+                    payment_asset_id: QCOIN_ASSET_ID,
+                    amount: RoyaltyAmount::BasisPoints(250),
+                }),
             },
         );
 
         chain.ledger.utxos = utxos;
         chain.ledger.assets = assets;
-
+        chain.ledger.sub_assets.insert(
+            AssetId([9u8; 32]),
+            SubAsset {
+                class_id: AssetId([5u8; 32]),
+                sub_id: 3,
+            },
+        );
+        chain.ledger.collections.insert(
+            CollectionId([10u8; 32]),
+            CollectionDefinition {
+                issuer_script_hash: [6u8; 32],
+                metadata_root: [11u8; 32],
+            },
+        );
+        chain.ledger.collection_items.insert(
+            AssetId([5u8; 32]),
+            CollectionItem {
+                collection_id: CollectionId([10u8; 32]),
+                serial: 1,
+            },
+        );
+        chain.ledger.legacy_non_fungible.insert(AssetId([6u8; 32]));
+        chain.ledger.frozen_outputs.insert(FrozenOutput {
+            key: UtxoKey {
+                tx_id: [3u8; 32],
+                index: 1,
+            },
+            asset_id: AssetId([5u8; 32]),
+        });
+        chain.ledger.metadata_updates.insert(
+            AssetId([5u8; 32]),
+            vec![MetadataUpdate {
+                metadata_root: [12u8; 32],
+                height: 2,
+                tx_id: [13u8; 32],
+            }],
+        );
+        chain.ledger.asset_supply.insert(
+            AssetId([5u8; 32]),
+            AssetSupply {
+                minted: 9,
+                burned: 4,
+            },
+        );
         save_chain_state(&state_path, &chain).unwrap();
 
         let reloaded = load_chain_state(&state_path).unwrap().unwrap();
@@ -1958,8 +3003,184 @@ mod tests {
         assert_eq!(reloaded.state_root, chain.state_root);
         assert_eq!(reloaded.last_timestamp, chain.last_timestamp);
         assert_eq!(reloaded.chain_id, chain.chain_id);
+        assert_eq!(reloaded.recent_timestamps, chain.recent_timestamps);
+        assert!(reloaded.witness_commitments);
+        assert_eq!(reloaded.header_version, 1);
         assert_eq!(reloaded.ledger.utxos, chain.ledger.utxos);
         assert_eq!(reloaded.ledger.assets, chain.ledger.assets);
+        assert_eq!(reloaded.ledger.sub_assets, chain.ledger.sub_assets);
+        assert_eq!(
+            reloaded.ledger.legacy_non_fungible,
+            chain.ledger.legacy_non_fungible
+        );
+        assert_eq!(reloaded.ledger.collections, chain.ledger.collections);
+        assert_eq!(
+            reloaded.ledger.collection_items,
+            chain.ledger.collection_items
+        );
+        assert_eq!(reloaded.ledger.frozen_outputs, chain.ledger.frozen_outputs);
+        assert_eq!(
+            reloaded.ledger.metadata_updates,
+            chain.ledger.metadata_updates
+        );
+        assert_eq!(reloaded.ledger.asset_supply, chain.ledger.asset_supply);
+
+        chain.ledger.asset_supply.insert(
+            AssetId([5u8; 32]),
+            AssetSupply {
+                minted: 4,
+                burned: 9,
+            },
+        );
+        save_chain_state(&state_path, &chain).unwrap();
+        let err = load_chain_state(&state_path).unwrap_err();
+        assert!(err.contains("burns more than was minted"), "{err}");
+    }
+
+    #[test]
+    fn collection_response_lists_members_by_serial() {
+        let mut ledger = LedgerState::default();
+        let collection_id = CollectionId([10u8; 32]);
+        ledger.collections.insert(
+            collection_id.clone(),
+            CollectionDefinition {
+                issuer_script_hash: [6u8; 32],
+                metadata_root: [11u8; 32],
+            },
+        );
+        for (asset_byte, serial) in [(1u8, 7u64), (2, 3)] {
+            ledger.collection_items.insert(
+                AssetId([asset_byte; 32]),
+                CollectionItem {
+                    collection_id: collection_id.clone(),
+                    serial,
+                },
+            );
+        }
+
+        let response = collection_response(&ledger, &collection_id).unwrap();
+        assert_eq!(response.collection_id_hex, to_hex(&[10u8; 32]));
+        assert_eq!(response.issuer_script_hash_hex, to_hex(&[6u8; 32]));
+        assert_eq!(
+            response.members,
+            vec![
+                CollectionMemberResponse {
+                    serial: 3,
+                    asset_id_hex: to_hex(&[2u8; 32]),
+                },
+                CollectionMemberResponse {
+                    serial: 7,
+                    asset_id_hex: to_hex(&[1u8; 32]),
+                },
+            ]
+        );
+        assert!(collection_response(&ledger, &CollectionId([12u8; 32])).is_none());
+    }
+
+    #[test]
+    fn asset_metadata_response_reports_current_root_and_history() {
+        let mut ledger = LedgerState::default();
+        let asset_id = AssetId([1u8; 32]);
+        ledger.assets.insert(
+            asset_id.clone(),
+            AssetDefinition {
+                issuer_script_hash: [2u8; 32],
+                metadata_root: [3u8; 32],
+                max_supply: None,
+                decimals: 0,
+                kind: AssetKind::Fungible,
+                transfer_policy: TransferPolicy::default(),
+                issuer_controls: IssuerControls::default(),
+                royalty: None,
+            },
+        );
+
+        let response = asset_metadata_response(&ledger, &asset_id).unwrap();
+        assert_eq!(response.current_metadata_root_hex, to_hex(&[3u8; 32]));
+        assert!(response.updates.is_empty());
+
+        ledger.metadata_updates.insert(
+            asset_id.clone(),
+            vec![MetadataUpdate {
+                metadata_root: [4u8; 32],
+                height: 7,
+                tx_id: [5u8; 32],
+            }],
+        );
+        let response = asset_metadata_response(&ledger, &asset_id).unwrap();
+        assert_eq!(response.original_metadata_root_hex, to_hex(&[3u8; 32]));
+        assert_eq!(response.current_metadata_root_hex, to_hex(&[4u8; 32]));
+        assert_eq!(
+            response.updates,
+            vec![MetadataUpdateResponse {
+                metadata_root_hex: to_hex(&[4u8; 32]),
+                height: 7,
+                tx_id_hex: to_hex(&[5u8; 32]),
+            }]
+        );
+        assert!(asset_metadata_response(&ledger, &AssetId([6u8; 32])).is_none());
+    }
+
+    #[test]
+    fn asset_response_reports_supply_and_holders() {
+        let mut ledger = LedgerState::default();
+        let asset_id = AssetId([1u8; 32]);
+        let definition = AssetDefinition {
+            issuer_script_hash: [2u8; 32],
+            metadata_root: [3u8; 32],
+            max_supply: None,
+            decimals: 0,
+            kind: AssetKind::Fungible,
+            transfer_policy: TransferPolicy::default(),
+            issuer_controls: IssuerControls::default(),
+            royalty: None,
+        };
+        ledger.assets.insert(asset_id.clone(), definition.clone());
+        ledger.asset_supply.insert(
+            asset_id.clone(),
+            AssetSupply {
+                minted: u128::MAX,
+                burned: 5,
+            },
+        );
+        for (index, owner) in [[7u8; 32], [7u8; 32], [8u8; 32]].into_iter().enumerate() {
+            ledger.utxos.insert(
+                UtxoKey {
+                    tx_id: [9u8; 32],
+                    index: index as u32,
+                },
+                TrackedOutput {
+                    output: Output {
+                        owner_script_hash: owner,
+                        assets: vec![AssetAmount {
+                            asset_id: asset_id.clone(),
+                            amount: 1,
+                        }],
+                        metadata_hash: None,
+                    },
+                    created_height: 1,
+                    created_time: 0,
+                },
+            );
+        }
+
+        let response = asset_response(&ledger, &asset_id).unwrap().unwrap();
+        assert_eq!(response.asset_id_hex, to_hex(&asset_id.0));
+        assert_eq!(response.definition, definition);
+        assert_eq!(response.minted, u128::MAX.to_string());
+        assert_eq!(response.burned, "5");
+        assert_eq!(response.circulating, (u128::MAX - 5).to_string());
+        assert_eq!(response.holder_count, 2);
+        assert_eq!(asset_response(&ledger, &AssetId([6u8; 32])), Ok(None));
+
+        ledger.asset_supply.insert(
+            asset_id.clone(),
+            AssetSupply {
+                minted: 5,
+                burned: 6,
+            },
+        );
+        assert!(asset_response(&ledger, &asset_id).is_err());
     }
 
     #[test]
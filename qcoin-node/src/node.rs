@@ -1704,12 +1704,15 @@ mod tests {
             serde_json::to_string(&blocks).unwrap().as_bytes(),
         )?;
 
+        let chain_id = chain.chain_id;
         Ok(NodeRuntime {
             chain,
             blocks,
             pending_transactions: Vec::new(),
             consensus,
-            script_engine: DeterministicScriptEngine::default(),
+            script_engine: DeterministicScriptEngine::for_chain(chain_id),
+            policy_script_engine: crate::policy_script_engine(chain_id),
+            standardness: Default::default(),
             state_path,
             blocks_path,
             node_public_key_hex,
@@ -1754,12 +1757,15 @@ mod tests {
             serde_json::to_string(&blocks).unwrap().as_bytes(),
         )?;
 
+        let chain_id = chain.chain_id;
         Ok(NodeRuntime {
             chain,
             blocks,
             pending_transactions: Vec::new(),
             consensus,
-            script_engine: DeterministicScriptEngine::default(),
+            script_engine: DeterministicScriptEngine::for_chain(chain_id),
+            policy_script_engine: crate::policy_script_engine(chain_id),
+            standardness: Default::default(),
             state_path,
             blocks_path,
             node_public_key_hex,
@@ -1774,6 +1780,8 @@ mod tests {
                 kind: TransactionKind::Transfer,
                 inputs: Vec::new(),
                 outputs: Vec::new(),
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: TransactionWitness::default(),
         }
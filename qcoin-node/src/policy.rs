@@ -0,0 +1,187 @@
+//! Relay policy: which valid transactions this node admits to its mempool.
+//!
+//! Nothing here is a consensus rule. A block containing a non-standard transaction is
+//! still valid; the policy only stops this node from relaying and mining scripts that are
+//! unusual or expensive to check.
+
+use qcoin_ledger::input_scripts;
+use qcoin_script::{analysis, VmConfig};
+use qcoin_types::Transaction;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct StandardnessPolicy {
+    /// Admit input scripts that match no standard template.
+    pub(crate) allow_nonstandard: bool,
+    /// Largest encoded input script, in bytes.
+    pub(crate) max_script_bytes: usize,
+    /// Largest statically computed worst-case gas of an input script.
+    pub(crate) max_script_gas: u64,
+}
+
+impl Default for StandardnessPolicy {
+    fn default() -> Self {
+        Self {
+            allow_nonstandard: false,
+            max_script_bytes: 16 * 1024,
+            max_script_gas: 100_000,
+        }
+    }
+}
+
+impl StandardnessPolicy {
+    /// Checks every input script of `transaction` as it would run at `height`.
+    pub(crate) fn check(
+        &self,
+        transaction: &Transaction,
+        vm_config: &VmConfig,
+        height: u64,
+    ) -> Result<(), String> {
+        let scripts = input_scripts(transaction)
+            .map_err(|err| format!("failed to decode input witness: {err}"))?;
+
+        for (index, envelope) in scripts.iter().enumerate() {
+            if !envelope.is_known() {
+                return Err(format!(
+                    "input {index} uses unknown script version {}",
+                    envelope.version
+                ));
+            }
+            if envelope.body.len() > self.max_script_bytes {
+                return Err(format!(
+                    "input {index} script is {} bytes, policy allows {}",
+                    envelope.body.len(),
+                    self.max_script_bytes
+                ));
+            }
+
+            let script = envelope
+                .script()
+                .map_err(|err| format!("input {index} script is malformed: {err}"))?;
+            let analysis = analysis::analyze(&script, vm_config, height);
+            if let Some(reason) = &analysis.failure {
+                return Err(format!("input {index} script can never succeed: {reason}"));
+            }
+            if !self.allow_nonstandard && !analysis.is_standard() {
+                return Err(format!("input {index} script matches no standard template"));
+            }
+            if analysis.worst_case_gas > self.max_script_gas {
+                return Err(format!(
+                    "input {index} script may use {} gas, policy allows {}",
+                    analysis.worst_case_gas, self.max_script_gas
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qcoin_crypto::{default_registry, PqSchemeRegistry, SignatureSchemeId};
+    use qcoin_script::{templates, OpCode, Script, ScriptEnvelope};
+    use qcoin_types::{
+        AssetId, TransactionCore, TransactionInput, TransactionKind, TransactionWitness,
+    };
+
+    fn spending(script: Script) -> Transaction {
+        Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::Transfer,
+                inputs: vec![TransactionInput {
+                    tx_id: [1u8; 32],
+                    index: 0,
+                }],
+                outputs: Vec::new(),
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![ScriptEnvelope::legacy(&script).witness(Vec::new()).encode()],
+            },
+        }
+    }
+
+    #[test]
+    fn admits_standard_scripts_and_rejects_others() {
+        let (public_key, _) = default_registry()
+            .get(&SignatureSchemeId::Dilithium2)
+            .expect("scheme should exist")
+            .keygen()
+            .expect("keygen should work");
+        let (standard, _) = templates::single_key(&public_key).unwrap();
+        let config = VmConfig::default();
+        let policy = StandardnessPolicy::default();
+        assert!(policy
+            .check(&spending(standard.clone()), &config, 1)
+            .is_ok());
+
+        let custom = spending(Script(vec![OpCode::Nop]));
+        let err = policy.check(&custom, &config, 1).unwrap_err();
+        assert!(err.contains("no standard template"), "{err}");
+        let permissive = StandardnessPolicy {
+            allow_nonstandard: true,
+            ..StandardnessPolicy::default()
+        };
+        assert!(permissive.check(&custom, &config, 1).is_ok());
+
+        let doomed = spending(Script(vec![OpCode::CheckSig]));
+        let err = permissive.check(&doomed, &config, 1).unwrap_err();
+        assert!(err.contains("can never succeed"), "{err}");
+
+        let tight = StandardnessPolicy {
+            max_script_bytes: 64,
+            ..StandardnessPolicy::default()
+        };
+        let err = tight
+            .check(&spending(standard.clone()), &config, 1)
+            .unwrap_err();
+        assert!(err.contains("bytes"), "{err}");
+
+        let cheap = StandardnessPolicy {
+            max_script_gas: 1_000,
+            ..StandardnessPolicy::default()
+        };
+        let err = cheap.check(&spending(standard), &config, 1).unwrap_err();
+        assert!(err.contains("gas"), "{err}");
+    }
+
+    #[test]
+    fn admits_asset_gated_and_oracle_attested_scripts() {
+        let registry = default_registry();
+        let scheme = registry
+            .get(&SignatureSchemeId::Dilithium2)
+            .expect("scheme should exist");
+        let (public_key, _) = scheme.keygen().expect("keygen should work");
+        let (oracle_key, _) = scheme.keygen().expect("keygen should work");
+        let config = VmConfig::default();
+        let policy = StandardnessPolicy::default();
+
+        let (gated, _) = templates::asset_gated(AssetId([4u8; 32]), 1, &public_key).unwrap();
+        let (attested, _) =
+            templates::oracle_attested(&oracle_key, b"red wins".to_vec(), &public_key).unwrap();
+        for script in [gated, attested] {
+            assert!(policy.check(&spending(script.clone()), &config, 1).is_ok());
+
+            // Anything appended takes the script off its template.
+            let mut extended = script;
+            extended.0.push(OpCode::Nop);
+            let err = policy.check(&spending(extended), &config, 1).unwrap_err();
+            assert!(err.contains("no standard template"), "{err}");
+        }
+    }
+
+    #[test]
+    fn missing_fields_in_config_use_defaults() {
+        let policy: StandardnessPolicy =
+            serde_json::from_str(r#"{ "allow_nonstandard": true }"#).unwrap();
+        assert!(policy.allow_nonstandard);
+        assert_eq!(
+            policy.max_script_bytes,
+            StandardnessPolicy::default().max_script_bytes
+        );
+    }
+}
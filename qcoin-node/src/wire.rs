@@ -157,6 +157,8 @@ mod tests {
                 kind: TransactionKind::Transfer,
                 inputs: Vec::new(),
                 outputs: Vec::new(),
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: TransactionWitness::default(),
         };
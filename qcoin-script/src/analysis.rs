@@ -0,0 +1,343 @@
+//! Static analysis of scripts, without a transaction or host.
+//!
+//! Scripts are straight-line programs, so every opcode runs exactly once and the stack
+//! shape is known ahead of time. The analyzer walks the opcodes once, tracking which stack
+//! items are script constants (from `PushBytes`) and which depend on the witness. It
+//! reports an upper bound on gas, the deepest stack, and any failure that no witness
+//! could avoid. Mempool policy uses it to refuse scripts before evaluating them.
+
+use crate::gas::GasSchedule;
+use crate::templates::{self, Template};
+use crate::{HashAlgorithm, OpCode, Script, VmConfig};
+use qcoin_crypto::PublicKey;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptAnalysis {
+    /// Upper bound on the gas any execution can charge, successful or not. Witness items
+    /// are assumed to be `max_push_bytes` long and every signature check is priced with
+    /// the most expensive scheme. Not capped at `max_gas`.
+    pub worst_case_gas: u64,
+    pub max_stack_depth: usize,
+    /// Why every execution fails, if one opcode is certain to fail whatever the witness.
+    pub failure: Option<String>,
+    pub template: Template,
+}
+
+impl ScriptAnalysis {
+    pub fn can_succeed(&self) -> bool {
+        self.failure.is_none()
+    }
+
+    pub fn is_standard(&self) -> bool {
+        self.template != Template::NonStandard
+    }
+}
+
+/// Analyzes `script` under `config`, with the gas schedule active at `height`.
+pub fn analyze(script: &Script, config: &VmConfig, height: u64) -> ScriptAnalysis {
+    let schedule = config.gas_schedules.active_at(height);
+    let mut walk = Walk {
+        config,
+        schedule,
+        stack: Vec::new(),
+        max_depth: 0,
+        min_gas: 0,
+        worst_gas: 0,
+        failure: None,
+    };
+
+    if script.0.len() > config.max_script_len {
+        walk.fail("script length exceeds limit".to_string());
+    }
+    for (position, op) in script.0.iter().enumerate() {
+        if let Err(reason) = walk.step(op) {
+            walk.fail(format!("opcode {position} ({op}): {reason}"));
+            break;
+        }
+    }
+    if walk.min_gas > config.max_gas {
+        walk.fail("fixed opcode costs exceed the gas limit".to_string());
+    }
+
+    ScriptAnalysis {
+        worst_case_gas: walk.worst_gas,
+        max_stack_depth: walk.max_depth,
+        failure: walk.failure,
+        template: templates::classify(script),
+    }
+}
+
+/// A stack item: `Some` when it is a script constant, `None` when it depends on the witness.
+type Item = Option<Vec<u8>>;
+
+struct Walk<'a> {
+    config: &'a VmConfig,
+    schedule: &'a GasSchedule,
+    stack: Vec<Item>,
+    max_depth: usize,
+    /// Gas charged by every execution that reaches the end.
+    min_gas: u64,
+    worst_gas: u64,
+    failure: Option<String>,
+}
+
+impl Walk<'_> {
+    fn fail(&mut self, reason: String) {
+        self.failure.get_or_insert(reason);
+    }
+
+    fn charge(&mut self, fixed: u64, variable: u64) {
+        self.min_gas = self.min_gas.saturating_add(fixed);
+        self.worst_gas = self
+            .worst_gas
+            .saturating_add(fixed)
+            .saturating_add(variable);
+    }
+
+    fn push(&mut self, item: Item) -> Result<(), String> {
+        if self.stack.len() >= self.config.max_stack_items {
+            return Err("stack overflow".to_string());
+        }
+        self.stack.push(item);
+        self.max_depth = self.max_depth.max(self.stack.len());
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Item, String> {
+        self.stack
+            .pop()
+            .ok_or_else(|| "stack underflow".to_string())
+    }
+
+    /// Most expensive verification among the schedule's schemes.
+    fn max_sig_verify(&self) -> u64 {
+        self.schedule
+            .sig_verify
+            .values()
+            .copied()
+            .fold(self.schedule.sig_verify_default, u64::max)
+    }
+
+    fn hash_cost(&self, algorithm: HashAlgorithm, item: &Item) -> u64 {
+        let len = item
+            .as_ref()
+            .map_or(self.config.max_push_bytes, |bytes| bytes.len());
+        self.schedule.hash_input_cost(algorithm, len)
+    }
+
+    fn step(&mut self, op: &OpCode) -> Result<(), String> {
+        self.charge(self.schedule.op_cost(op), 0);
+
+        match op {
+            OpCode::Nop => {}
+            OpCode::PushBytes(data) => {
+                if data.len() > self.config.max_push_bytes {
+                    return Err("push exceeds byte limit".to_string());
+                }
+                self.push(Some(data.clone()))?;
+            }
+            OpCode::PushWitness(_) => {
+                let max_item = self.config.max_push_bytes as u64;
+                self.charge(0, self.schedule.witness_byte.saturating_mul(max_item));
+                self.push(None)?;
+            }
+            OpCode::CheckSig => {
+                self.pop()?;
+                let public_key = self.pop()?;
+                check_public_key(&public_key)?;
+                self.charge(0, self.max_sig_verify());
+            }
+            OpCode::CheckDataSig => {
+                self.pop()?;
+                let message = self.pop()?;
+                let public_key = self.pop()?;
+                check_public_key(&public_key)?;
+                let message_cost = self.hash_cost(HashAlgorithm::Sha3_256, &message);
+                self.charge(0, self.max_sig_verify().saturating_add(message_cost));
+            }
+            OpCode::CheckMultiSig { threshold, total } => {
+                if *threshold == 0 || *total == 0 || threshold > total {
+                    return Err("invalid multisig threshold".to_string());
+                }
+                for _ in 0..*threshold {
+                    self.pop()?;
+                }
+                for _ in 0..*total {
+                    check_public_key(&self.pop()?)?;
+                }
+                // Every key may be tried, and every failed attempt costs another base.
+                let per_key = self.max_sig_verify().saturating_add(self.schedule.sig_base);
+                self.charge(0, per_key.saturating_mul(u64::from(*total)));
+            }
+            OpCode::CheckTimeLock
+            | OpCode::CheckRelativeTimeLock
+            | OpCode::CheckTimestampLock
+            | OpCode::CheckRelativeTimestampLock => {
+                if let Some(operand) = self.pop()? {
+                    if operand.len() != 8 {
+                        return Err("timelock operand is not 8 bytes".to_string());
+                    }
+                }
+            }
+            OpCode::CheckAssetHeld => {
+                if matches!(self.pop()?, Some(amount) if amount.len() != 16) {
+                    return Err("asset amount operand is not 16 bytes".to_string());
+                }
+                if matches!(self.pop()?, Some(asset_id) if asset_id.len() != 32) {
+                    return Err("asset id operand is not 32 bytes".to_string());
+                }
+                // One lookup per input until a holder is found; the input count is only
+                // bounded by the gas limit.
+                self.charge(0, self.config.max_gas);
+            }
+            OpCode::Blake3 | OpCode::Sha256 | OpCode::Sha3_256 => {
+                let algorithm = op.hash_algorithm().expect("digest opcode has an algorithm");
+                let data = self.pop()?;
+                self.charge(0, self.hash_cost(algorithm, &data));
+                self.push(data.map(|bytes| algorithm.digest(&bytes).to_vec()))?;
+            }
+            OpCode::CheckHashLock | OpCode::CheckHashLockSha256 | OpCode::CheckHashLockSha3_256 => {
+                let algorithm = op
+                    .hash_algorithm()
+                    .expect("hashlock opcode has an algorithm");
+                let preimage = self.pop()?;
+                self.charge(0, self.hash_cost(algorithm, &preimage));
+                let expected = self.pop()?;
+                match (&expected, &preimage) {
+                    (Some(hash), _) if hash.len() != 32 => {
+                        return Err("hashlock expects 32-byte hash".to_string());
+                    }
+                    (Some(hash), Some(preimage)) if *hash != algorithm.digest(preimage) => {
+                        return Err(
+                            "hashlock preimage is a constant that does not match".to_string()
+                        );
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn check_public_key(item: &Item) -> Result<(), String> {
+    match item {
+        Some(bytes) if PublicKey::from_bytes(bytes).is_err() => {
+            Err("public key is a constant that does not decode".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::templates::Timelock;
+    use qcoin_crypto::{default_registry, PqSchemeRegistry, SignatureSchemeId};
+
+    fn public_key() -> PublicKey {
+        default_registry()
+            .get(&SignatureSchemeId::Dilithium2)
+            .expect("scheme should exist")
+            .keygen()
+            .expect("keygen should work")
+            .0
+    }
+
+    #[test]
+    fn reports_bounds_for_standard_templates() {
+        let config = VmConfig::default();
+        let (script, _) = templates::single_key(&public_key()).unwrap();
+        let analysis = analyze(&script, &config, 0);
+
+        assert!(analysis.can_succeed());
+        assert!(analysis.is_standard());
+        assert_eq!(analysis.max_stack_depth, 2);
+        let push = script.0[0].clone();
+        let legacy = GasSchedule::legacy();
+        let expected = legacy.op_cost(&push)
+            + legacy.base
+            + legacy.witness_byte * config.max_push_bytes as u64
+            + legacy.sig_base;
+        assert_eq!(analysis.worst_case_gas, expected);
+
+        let (timelocked, _) = templates::timelocked(Timelock::Absolute(5), &public_key()).unwrap();
+        let analysis = analyze(&timelocked, &config, 0);
+        assert!(analysis.can_succeed());
+        assert!(matches!(analysis.template, Template::Timelocked { .. }));
+    }
+
+    #[test]
+    fn detects_scripts_that_can_never_succeed() {
+        let config = VmConfig::default();
+        let cases = [
+            Script(vec![OpCode::CheckSig]),
+            Script(vec![OpCode::PushBytes(vec![1, 2]), OpCode::CheckTimeLock]),
+            Script(vec![
+                OpCode::PushBytes(vec![0; 32]),
+                OpCode::PushBytes(b"guess".to_vec()),
+                OpCode::CheckHashLock,
+            ]),
+            Script(vec![
+                OpCode::PushBytes(vec![0xff; 4]),
+                OpCode::PushWitness(0),
+                OpCode::CheckSig,
+            ]),
+            Script(vec![OpCode::CheckMultiSig {
+                threshold: 3,
+                total: 2,
+            }]),
+            Script(vec![
+                OpCode::PushBytes(vec![0; 31]),
+                OpCode::PushBytes(vec![0; 16]),
+                OpCode::CheckAssetHeld,
+            ]),
+            Script(vec![
+                OpCode::PushBytes(vec![0; config.max_push_bytes + 1]),
+                OpCode::Nop,
+            ]),
+        ];
+        for script in cases {
+            let analysis = analyze(&script, &config, 0);
+            assert!(!analysis.can_succeed(), "{script:?}");
+            assert!(!analysis.is_standard());
+        }
+
+        let preimage = b"open".to_vec();
+        let hash = HashAlgorithm::Sha256.digest(&preimage).to_vec();
+        let analysis = analyze(
+            &Script(vec![
+                OpCode::PushBytes(hash),
+                OpCode::PushBytes(preimage),
+                OpCode::CheckHashLockSha256,
+            ]),
+            &config,
+            0,
+        );
+        assert!(analysis.can_succeed());
+        assert!(!analysis.is_standard());
+    }
+
+    #[test]
+    fn fixed_costs_above_gas_limit_cannot_succeed() {
+        let config = VmConfig {
+            max_gas: 10_000,
+            ..VmConfig::default()
+        };
+        let script = Script(vec![
+            OpCode::PushWitness(0),
+            OpCode::PushWitness(1),
+            OpCode::PushWitness(2),
+            OpCode::PushWitness(3),
+            OpCode::CheckMultiSig {
+                threshold: 2,
+                total: 2,
+            },
+            OpCode::Nop,
+        ]);
+        let analysis = analyze(&script, &config, 0);
+        assert_eq!(analysis.max_stack_depth, 4);
+        assert!(!analysis.can_succeed());
+    }
+}
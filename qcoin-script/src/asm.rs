@@ -0,0 +1,283 @@
+//! Text assembly format for scripts.
+//!
+//! One mnemonic per opcode, separated by whitespace or newlines. `#` starts a
+//! comment that runs to the end of the line. Mnemonics are case-insensitive.
+//!
+//! ```text
+//! PUSH 0x0b00000000000000   # required height
+//! CHECKTIMELOCK
+//! PUSHWITNESS 0            # spender-supplied item, e.g. a signature
+//! CHECKMULTISIG 2 3
+//! ```
+//!
+//! `PUSH` takes either `0x`-prefixed hex (`PUSH 0x` pushes an empty item) or a
+//! decimal integer, which is pushed as 8 little-endian bytes to match what the
+//! timelock opcodes expect. The disassembler always emits hex, so
+//! `assemble(&disassemble(script))` returns the original script.
+
+use crate::{OpCode, Script};
+use std::fmt;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("line {line}, column {column}: {message}")]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+struct Token<'a> {
+    text: &'a str,
+    line: usize,
+    column: usize,
+}
+
+impl Token<'_> {
+    fn error(&self, message: impl Into<String>) -> AsmError {
+        AsmError {
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let code = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+
+        let mut start = None;
+        for (pos, ch) in code
+            .char_indices()
+            .chain(std::iter::once((code.len(), ' ')))
+        {
+            match (ch.is_whitespace(), start) {
+                (true, Some(begin)) => {
+                    tokens.push(Token {
+                        text: &code[begin..pos],
+                        line: line_idx + 1,
+                        column: code[..begin].chars().count() + 1,
+                    });
+                    start = None;
+                }
+                (false, None) => start = Some(pos),
+                _ => {}
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_push(token: &Token<'_>) -> Result<Vec<u8>, AsmError> {
+    let text = token.text;
+    if let Some(hex) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        // Checked byte-wise: `from_str_radix` would take a sign, and slicing `hex` could
+        // split a multi-byte character.
+        if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(token.error(format!("invalid hex digits in `{text}`")));
+        }
+        if hex.len() % 2 != 0 {
+            return Err(token.error("hex push has odd number of digits"));
+        }
+        let nibble = |byte: u8| (byte as char).to_digit(16).unwrap_or_default() as u8;
+        return Ok(hex
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| (nibble(pair[0]) << 4) | nibble(pair[1]))
+            .collect());
+    }
+
+    text.parse::<u64>()
+        .map(|value| value.to_le_bytes().to_vec())
+        .map_err(|_| token.error(format!("expected 0x-prefixed hex or u64, found `{text}`")))
+}
+
+fn parse_u8(token: &Token<'_>, what: &str) -> Result<u8, AsmError> {
+    token.text.parse::<u8>().map_err(|_| {
+        token.error(format!(
+            "expected {what} in 0..=255, found `{}`",
+            token.text
+        ))
+    })
+}
+
+/// Parses script text into a [`Script`].
+pub fn assemble(source: &str) -> Result<Script, AsmError> {
+    let tokens = tokenize(source);
+    let mut iter = tokens.iter();
+    let mut ops = Vec::new();
+
+    while let Some(token) = iter.next() {
+        let mut operand = |what: &str| {
+            iter.next()
+                .ok_or_else(|| token.error(format!("missing {what}")))
+        };
+
+        let op = match token.text.to_ascii_uppercase().as_str() {
+            "PUSH" => OpCode::PushBytes(parse_push(operand("push operand")?)?),
+            "CHECKSIG" => OpCode::CheckSig,
+            "CHECKMULTISIG" => {
+                let threshold = parse_u8(operand("multisig threshold")?, "threshold")?;
+                let total = parse_u8(operand("multisig total")?, "total")?;
+                OpCode::CheckMultiSig { threshold, total }
+            }
+            "CHECKTIMELOCK" => OpCode::CheckTimeLock,
+            "CHECKRELATIVETIMELOCK" => OpCode::CheckRelativeTimeLock,
+            "CHECKHASHLOCK" => OpCode::CheckHashLock,
+            "NOP" => OpCode::Nop,
+            "PUSHWITNESS" => OpCode::PushWitness(parse_u8(operand("witness index")?, "index")?),
+            "CHECKDATASIG" => OpCode::CheckDataSig,
+            "BLAKE3" => OpCode::Blake3,
+            "SHA256" => OpCode::Sha256,
+            "SHA3_256" => OpCode::Sha3_256,
+            "CHECKHASHLOCKSHA256" => OpCode::CheckHashLockSha256,
+            "CHECKHASHLOCKSHA3_256" => OpCode::CheckHashLockSha3_256,
+            "CHECKTIMESTAMPLOCK" => OpCode::CheckTimestampLock,
+            "CHECKRELATIVETIMESTAMPLOCK" => OpCode::CheckRelativeTimestampLock,
+            "CHECKASSETHELD" => OpCode::CheckAssetHeld,
+            _ => return Err(token.error(format!("unknown mnemonic `{}`", token.text))),
+        };
+        ops.push(op);
+    }
+
+    Ok(Script(ops))
+}
+
+/// Renders a script as text, one opcode per line.
+pub fn disassemble(script: &Script) -> String {
+    script
+        .0
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpCode::PushBytes(data) => {
+                write!(f, "PUSH 0x")?;
+                for byte in data {
+                    write!(f, "{byte:02x}")?;
+                }
+                Ok(())
+            }
+            OpCode::CheckSig => write!(f, "CHECKSIG"),
+            OpCode::CheckMultiSig { threshold, total } => {
+                write!(f, "CHECKMULTISIG {threshold} {total}")
+            }
+            OpCode::CheckTimeLock => write!(f, "CHECKTIMELOCK"),
+            OpCode::CheckRelativeTimeLock => write!(f, "CHECKRELATIVETIMELOCK"),
+            OpCode::CheckHashLock => write!(f, "CHECKHASHLOCK"),
+            OpCode::Nop => write!(f, "NOP"),
+            OpCode::PushWitness(index) => write!(f, "PUSHWITNESS {index}"),
+            OpCode::CheckDataSig => write!(f, "CHECKDATASIG"),
+            OpCode::Blake3 => write!(f, "BLAKE3"),
+            OpCode::Sha256 => write!(f, "SHA256"),
+            OpCode::Sha3_256 => write!(f, "SHA3_256"),
+            OpCode::CheckHashLockSha256 => write!(f, "CHECKHASHLOCKSHA256"),
+            OpCode::CheckHashLockSha3_256 => write!(f, "CHECKHASHLOCKSHA3_256"),
+            OpCode::CheckTimestampLock => write!(f, "CHECKTIMESTAMPLOCK"),
+            OpCode::CheckRelativeTimestampLock => write!(f, "CHECKRELATIVETIMESTAMPLOCK"),
+            OpCode::CheckAssetHeld => write!(f, "CHECKASSETHELD"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assembles_every_mnemonic() {
+        let source = "
+            # spend after height 11 with 2-of-3 keys
+            PUSH 11 checktimelock
+            PUSH 0xAABB PUSH 0x
+            CHECKMULTISIG 2 3
+            CHECKRELATIVETIMELOCK CHECKHASHLOCK CHECKSIG NOP PUSHWITNESS 1
+            CHECKDATASIG blake3 SHA256 sha3_256 CHECKHASHLOCKSHA256 CHECKHASHLOCKSHA3_256
+            CHECKTIMESTAMPLOCK checkrelativetimestamplock CHECKASSETHELD
+        ";
+
+        let script = assemble(source).expect("source should assemble");
+        assert_eq!(
+            script,
+            Script(vec![
+                OpCode::PushBytes(11u64.to_le_bytes().to_vec()),
+                OpCode::CheckTimeLock,
+                OpCode::PushBytes(vec![0xaa, 0xbb]),
+                OpCode::PushBytes(Vec::new()),
+                OpCode::CheckMultiSig {
+                    threshold: 2,
+                    total: 3
+                },
+                OpCode::CheckRelativeTimeLock,
+                OpCode::CheckHashLock,
+                OpCode::CheckSig,
+                OpCode::Nop,
+                OpCode::PushWitness(1),
+                OpCode::CheckDataSig,
+                OpCode::Blake3,
+                OpCode::Sha256,
+                OpCode::Sha3_256,
+                OpCode::CheckHashLockSha256,
+                OpCode::CheckHashLockSha3_256,
+                OpCode::CheckTimestampLock,
+                OpCode::CheckRelativeTimestampLock,
+                OpCode::CheckAssetHeld,
+            ])
+        );
+    }
+
+    #[test]
+    fn disassembly_round_trips() {
+        let script = Script(vec![
+            OpCode::PushBytes(vec![0, 1, 0xfe]),
+            OpCode::CheckMultiSig {
+                threshold: 1,
+                total: 2,
+            },
+            OpCode::Nop,
+        ]);
+
+        let text = disassemble(&script);
+        assert_eq!(text, "PUSH 0x0001fe\nCHECKMULTISIG 1 2\nNOP");
+        assert_eq!(assemble(&text).expect("round trip"), script);
+    }
+
+    #[test]
+    fn reports_line_and_column() {
+        let err = assemble("NOP\n  PUSH 0x01\n  CHECKSIGG").unwrap_err();
+        assert_eq!((err.line, err.column), (3, 3));
+
+        let err = assemble("PUSH 0xabc").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 6));
+
+        let err = assemble("NOP # trailing comment\nCHECKMULTISIG 2").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 1));
+        assert!(err.message.contains("total"));
+
+        let err = assemble("CHECKMULTISIG 300 3").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 15));
+    }
+
+    #[test]
+    fn rejects_non_hex_push_digits() {
+        let err = assemble("PUSH 0xaé1f").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 6));
+        assert!(err.message.contains("invalid hex digits"));
+
+        let err = assemble("PUSH 0x+f").unwrap_err();
+        assert!(err.message.contains("invalid hex digits"));
+
+        let err = assemble("PUSH 0x-1").unwrap_err();
+        assert!(err.message.contains("invalid hex digits"));
+    }
+}
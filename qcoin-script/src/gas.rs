@@ -0,0 +1,265 @@
+//! Gas costs charged by the script engine.
+//!
+//! Costs are consensus rules: two nodes charging different amounts for the same script can
+//! disagree on whether it ran out of gas. A [`GasSchedule`] therefore never changes in
+//! place. New costs are added as a new schedule in a [`GasScheduleTable`], bound to the
+//! height at which they activate, and every block below that height keeps being evaluated
+//! with the schedule it was produced under.
+
+use crate::{HashAlgorithm, OpCode};
+use qcoin_crypto::SignatureSchemeId;
+use qcoin_types::ChainActivations;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GasSchedule {
+    pub nop: u64,
+    /// Fixed part of pushes, witness reads and timelock checks.
+    pub base: u64,
+    /// Per byte of data pushed by `PushBytes`.
+    pub push_byte: u64,
+    /// Per byte of a witness item read by `PushWitness`. Signatures and revealed keys are
+    /// witness items, so this is what prices large PQ signatures.
+    pub witness_byte: u64,
+    /// Fixed part of every digest or hashlock opcode.
+    pub hash: u64,
+    /// Per started 64-byte block of hashed input, by algorithm.
+    pub blake3_block: u64,
+    pub sha256_block: u64,
+    pub sha3_256_block: u64,
+    /// Charged up front per required signature (`threshold` for `CheckMultiSig`), and again
+    /// for each multisig key tried without a match.
+    pub sig_base: u64,
+    /// Verification cost for schemes missing from `sig_verify`.
+    pub sig_verify_default: u64,
+    /// Charged per verification attempt, by the scheme of the key being checked.
+    pub sig_verify: HashMap<SignatureSchemeId, u64>,
+    /// Per transaction input resolved by `CheckAssetHeld`.
+    pub input_lookup: u64,
+}
+
+impl GasSchedule {
+    /// The flat costs the engine charged before schedules existed: every signature costs
+    /// 5,000 regardless of scheme, and witness bytes cost 1 each. Opcodes added later got
+    /// their costs in this schedule when they were introduced.
+    pub fn legacy() -> Self {
+        Self {
+            nop: 1,
+            base: 10,
+            push_byte: 1,
+            witness_byte: 1,
+            hash: 250,
+            // Blake3 input was never charged per byte; `hash` covers a full 4 KiB push.
+            blake3_block: 0,
+            sha256_block: 12,
+            sha3_256_block: 10,
+            sig_base: 5_000,
+            sig_verify_default: 0,
+            sig_verify: HashMap::new(),
+            input_lookup: 50,
+        }
+    }
+
+    /// [`GasSchedule::legacy`] with the flat 5,000 split into a 1,000 `sig_base` and a
+    /// verify cost per scheme. Dilithium2 keeps the legacy total; Falcon512 verification
+    /// is cheaper and costs 3,500 in all. Other schemes keep the legacy total, so no
+    /// signature costs more than it did before.
+    pub fn per_scheme_sig_verify() -> Self {
+        Self {
+            sig_base: 1_000,
+            sig_verify_default: 4_000,
+            sig_verify: HashMap::from([
+                (SignatureSchemeId::Dilithium2, 4_000),
+                (SignatureSchemeId::Falcon512, 2_500),
+            ]),
+            ..Self::legacy()
+        }
+    }
+
+    pub fn sig_verify_cost(&self, scheme: SignatureSchemeId) -> u64 {
+        self.sig_verify
+            .get(&scheme)
+            .copied()
+            .unwrap_or(self.sig_verify_default)
+    }
+
+    /// Per-length part of hashing `len` bytes; the fixed part is charged by `op_cost`.
+    pub fn hash_input_cost(&self, algorithm: HashAlgorithm, len: usize) -> u64 {
+        let per_block = match algorithm {
+            HashAlgorithm::Blake3 => self.blake3_block,
+            HashAlgorithm::Sha256 => self.sha256_block,
+            HashAlgorithm::Sha3_256 => self.sha3_256_block,
+        };
+        per_block * len.div_ceil(64) as u64
+    }
+
+    /// Cost charged before `op` executes. Parts that depend on runtime data (witness item
+    /// length, preimage length, key scheme) are charged by the engine when known.
+    pub fn op_cost(&self, op: &OpCode) -> u64 {
+        match op {
+            OpCode::Nop => self.nop,
+            OpCode::PushBytes(data) => self.base + self.push_byte * data.len() as u64,
+            OpCode::CheckSig | OpCode::CheckDataSig => self.sig_base,
+            OpCode::CheckMultiSig { threshold, .. } => self.sig_base * (*threshold as u64).max(1),
+            OpCode::CheckTimeLock
+            | OpCode::CheckRelativeTimeLock
+            | OpCode::CheckTimestampLock
+            | OpCode::CheckRelativeTimestampLock
+            | OpCode::CheckAssetHeld => self.base,
+            OpCode::CheckHashLock
+            | OpCode::CheckHashLockSha256
+            | OpCode::CheckHashLockSha3_256
+            | OpCode::Blake3
+            | OpCode::Sha256
+            | OpCode::Sha3_256 => self.hash,
+            OpCode::PushWitness(_) => self.base,
+        }
+    }
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        Self::legacy()
+    }
+}
+
+/// Gas schedules keyed by activation height.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GasScheduleTable {
+    /// Sorted by activation height; the first entry activates at height 0.
+    entries: Vec<(u64, GasSchedule)>,
+}
+
+impl GasScheduleTable {
+    pub fn new(genesis: GasSchedule) -> Self {
+        Self {
+            entries: vec![(0, genesis)],
+        }
+    }
+
+    /// The schedules of chain `chain_id`: the legacy costs, then per-scheme signature
+    /// costs from the chain's [`ChainActivations::per_scheme_sig_verify`] height.
+    pub fn for_chain(chain_id: u32) -> Self {
+        Self::default().with_activation(
+            ChainActivations::for_chain(chain_id).per_scheme_sig_verify,
+            GasSchedule::per_scheme_sig_verify(),
+        )
+    }
+
+    /// Adds `schedule`, active from `height` until the next activation.
+    pub fn with_activation(mut self, height: u64, schedule: GasSchedule) -> Self {
+        match self
+            .entries
+            .binary_search_by_key(&height, |(start, _)| *start)
+        {
+            Ok(pos) => self.entries[pos].1 = schedule,
+            Err(pos) => self.entries.insert(pos, (height, schedule)),
+        }
+        self
+    }
+
+    /// Every schedule with its activation height, in activation order.
+    pub fn entries(&self) -> &[(u64, GasSchedule)] {
+        &self.entries
+    }
+
+    pub fn active_at(&self, height: u64) -> &GasSchedule {
+        &self.entry_at(height).1
+    }
+
+    /// The schedule active at `height`, with the height it activated at.
+    pub fn entry_at(&self, height: u64) -> &(u64, GasSchedule) {
+        let pos = self.entries.partition_point(|(start, _)| *start <= height);
+        &self.entries[pos.saturating_sub(1)]
+    }
+}
+
+/// The legacy schedule at every height.
+impl Default for GasScheduleTable {
+    fn default() -> Self {
+        Self::new(GasSchedule::legacy())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_schedule_matches_flat_costs() {
+        let schedule = GasSchedule::legacy();
+        assert_eq!(schedule.op_cost(&OpCode::Nop), 1);
+        assert_eq!(schedule.op_cost(&OpCode::PushBytes(vec![0; 7])), 17);
+        assert_eq!(schedule.op_cost(&OpCode::CheckSig), 5_000);
+        assert_eq!(
+            schedule.op_cost(&OpCode::CheckMultiSig {
+                threshold: 2,
+                total: 3
+            }),
+            10_000
+        );
+        assert_eq!(schedule.op_cost(&OpCode::CheckHashLock), 250);
+        assert_eq!(schedule.hash_input_cost(HashAlgorithm::Blake3, 4096), 0);
+        assert_eq!(schedule.hash_input_cost(HashAlgorithm::Sha256, 65), 24);
+        assert_eq!(schedule.sig_verify_cost(SignatureSchemeId::Falcon512), 0);
+    }
+
+    #[test]
+    fn table_selects_schedule_by_activation_height() {
+        let mut later = GasSchedule::legacy();
+        later.sig_base = 100;
+        later
+            .sig_verify
+            .insert(SignatureSchemeId::Dilithium2, 3_000);
+
+        let table = GasScheduleTable::new(GasSchedule::legacy()).with_activation(50, later.clone());
+        assert_eq!(table.active_at(0), &GasSchedule::legacy());
+        assert_eq!(table.active_at(49), &GasSchedule::legacy());
+        assert_eq!(table.active_at(50), &later);
+        assert_eq!(table.active_at(u64::MAX), &later);
+        assert_eq!(
+            table
+                .active_at(50)
+                .sig_verify_cost(SignatureSchemeId::Dilithium2),
+            3_000
+        );
+        assert_eq!(
+            table
+                .active_at(50)
+                .sig_verify_cost(SignatureSchemeId::Falcon512),
+            0
+        );
+    }
+
+    #[test]
+    fn chain_tables_charge_signatures_by_scheme_from_their_activation() {
+        let total = |table: &GasScheduleTable, height, scheme| {
+            let schedule = table.active_at(height);
+            schedule.sig_base + schedule.sig_verify_cost(scheme)
+        };
+
+        // Chain 0 has history from before the repricing.
+        let table = GasScheduleTable::for_chain(0);
+        let activation = ChainActivations::for_chain(0).per_scheme_sig_verify;
+        assert!(activation > 0);
+        let before = activation - 1;
+        assert_eq!(table.active_at(before), &GasSchedule::legacy());
+        assert_eq!(total(&table, before, SignatureSchemeId::Dilithium2), 5_000);
+        assert_eq!(total(&table, before, SignatureSchemeId::Falcon512), 5_000);
+
+        let after = activation;
+        assert_eq!(total(&table, after, SignatureSchemeId::Dilithium2), 5_000);
+        assert_eq!(total(&table, after, SignatureSchemeId::Falcon512), 3_500);
+        assert_eq!(total(&table, after, SignatureSchemeId::Unknown(9)), 5_000);
+
+        // A new chain prices signatures by scheme from genesis.
+        let table = GasScheduleTable::for_chain(7);
+        assert_eq!(table.entries().len(), 1);
+        assert_eq!(total(&table, 0, SignatureSchemeId::Falcon512), 3_500);
+        assert_eq!(
+            GasScheduleTable::default().active_at(u64::MAX),
+            &GasSchedule::legacy()
+        );
+    }
+}
@@ -1,35 +1,193 @@
 use blake3::hash;
-use qcoin_crypto::{default_registry, PqSchemeRegistry, PublicKey, Signature};
-use qcoin_types::{Output, SighashFlags, Transaction, TransactionInput};
+use qcoin_crypto::{
+    default_registry, CryptoError, InMemoryRegistry, PqSchemeRegistry, PublicKey, Signature,
+};
+use qcoin_types::{
+    AssetId, Hash256, InputWitness, Output, SighashFlags, Transaction, TransactionInput,
+};
 use serde::{Deserialize, Serialize};
+use sha2::Digest;
 use thiserror::Error;
 
+use gas::{GasSchedule, GasScheduleTable};
+
+pub mod analysis;
+pub mod asm;
+pub mod gas;
+pub mod templates;
+pub mod tree;
+
 const DEFAULT_MAX_GAS: u64 = 50_000;
 const DEFAULT_MAX_STACK_ITEMS: usize = 1_024;
 const DEFAULT_MAX_PUSH_BYTES: usize = 4 * 1024;
 const DEFAULT_MAX_SCRIPT_LEN: usize = 2_048;
 
+/// Version of the VM rules a script body is evaluated under.
+pub type ScriptVersion = u8;
+
+/// Scripts from before versioning existed; their hash is the plain script hash.
+pub const SCRIPT_VERSION_LEGACY: ScriptVersion = 0;
+/// Highest version this engine can evaluate. Later versions are handled by
+/// [`UnknownVersionPolicy`].
+pub const MAX_KNOWN_SCRIPT_VERSION: ScriptVersion = SCRIPT_VERSION_LEGACY;
+
+const VERSIONED_SCRIPT_DOMAIN: &[u8] = b"QCOIN_SCRIPT_VERSIONED";
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum OpCode {
     CheckSig,
-    CheckMultiSig { threshold: u8, total: u8 },
+    CheckMultiSig {
+        threshold: u8,
+        total: u8,
+    },
     CheckTimeLock,
     CheckRelativeTimeLock,
     CheckHashLock,
     PushBytes(Vec<u8>),
     Nop,
+    /// Pushes a copy of the spender-supplied witness stack item at this index.
+    PushWitness(u8),
+    /// Pops a signature, a message and a public key, and verifies the signature over the
+    /// message itself rather than over the transaction (e.g. an oracle-attested result).
+    CheckDataSig,
+    /// Pops an item and pushes its 32-byte digest.
+    Blake3,
+    Sha256,
+    Sha3_256,
+    /// `CheckHashLock` with a SHA-256 or SHA3-256 preimage, for swaps with other chains.
+    CheckHashLockSha256,
+    CheckHashLockSha3_256,
+    /// `CheckTimeLock` / `CheckRelativeTimeLock` with an 8-byte unix-seconds operand,
+    /// checked against the host's median-time-past instead of the block height.
+    CheckTimestampLock,
+    CheckRelativeTimestampLock,
+    /// Pops a 16-byte little-endian amount and a 32-byte asset id, and requires some input
+    /// of the spending transaction (this one included) to hold at least that amount.
+    CheckAssetHeld,
+}
+
+impl OpCode {
+    /// The hash function used by a digest or hashlock opcode.
+    pub fn hash_algorithm(&self) -> Option<HashAlgorithm> {
+        match self {
+            OpCode::Blake3 | OpCode::CheckHashLock => Some(HashAlgorithm::Blake3),
+            OpCode::Sha256 | OpCode::CheckHashLockSha256 => Some(HashAlgorithm::Sha256),
+            OpCode::Sha3_256 | OpCode::CheckHashLockSha3_256 => Some(HashAlgorithm::Sha3_256),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum HashAlgorithm {
+    Blake3,
+    Sha256,
+    Sha3_256,
+}
+
+impl HashAlgorithm {
+    pub fn digest(self, data: &[u8]) -> Hash256 {
+        match self {
+            HashAlgorithm::Blake3 => *hash(data).as_bytes(),
+            HashAlgorithm::Sha256 => sha2::Sha256::digest(data).into(),
+            HashAlgorithm::Sha3_256 => sha3::Sha3_256::digest(data).into(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Script(pub Vec<OpCode>);
 
+impl Script {
+    /// Hash of the consensus encoding; this is the value outputs commit to as `owner_script_hash`.
+    pub fn script_hash(&self) -> qcoin_types::Hash256 {
+        *hash(&consensus_codec::encode_script(self)).as_bytes()
+    }
+}
+
+/// A script body together with the VM version that interprets it.
+///
+/// The body is kept as encoded bytes, so a node can commit to, relay and spend scripts of
+/// versions whose opcodes it cannot decode.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScriptEnvelope {
+    pub version: ScriptVersion,
+    pub body: Vec<u8>,
+}
+
+impl ScriptEnvelope {
+    pub fn new(version: ScriptVersion, script: &Script) -> Self {
+        Self {
+            version,
+            body: consensus_codec::encode_script(script),
+        }
+    }
+
+    pub fn legacy(script: &Script) -> Self {
+        Self::new(SCRIPT_VERSION_LEGACY, script)
+    }
+
+    /// Whether this engine has rules for the envelope's version.
+    pub fn is_known(&self) -> bool {
+        matches!(self.version, SCRIPT_VERSION_LEGACY)
+    }
+
+    /// Bytes committed to by the script and leaf hashes. Legacy scripts commit to their
+    /// body alone, so existing outputs keep their hashes; every other version is domain
+    /// separated and commits to the version byte.
+    pub fn commitment(&self) -> Vec<u8> {
+        if self.version == SCRIPT_VERSION_LEGACY {
+            return self.body.clone();
+        }
+        let mut out = Vec::with_capacity(VERSIONED_SCRIPT_DOMAIN.len() + 1 + self.body.len());
+        out.extend_from_slice(VERSIONED_SCRIPT_DOMAIN);
+        out.push(self.version);
+        out.extend_from_slice(&self.body);
+        out
+    }
+
+    pub fn script_hash(&self) -> Hash256 {
+        *hash(&self.commitment()).as_bytes()
+    }
+
+    /// Decodes the body as opcodes. Only meaningful for known versions.
+    pub fn script(&self) -> Result<Script, consensus_codec::DecodeError> {
+        consensus_codec::decode_script(&self.body)
+    }
+
+    /// A witness revealing this script directly, with `stack` as the `PushWitness` items.
+    pub fn witness(&self, stack: Vec<Vec<u8>>) -> InputWitness {
+        InputWitness {
+            script_version: self.version,
+            script: self.body.clone(),
+            stack,
+            metadata: None,
+            merkle_path: None,
+        }
+    }
+}
+
+impl From<&InputWitness> for ScriptEnvelope {
+    fn from(witness: &InputWitness) -> Self {
+        Self {
+            version: witness.script_version,
+            body: witness.script.clone(),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct ScriptContext {
     pub tx: Transaction,
     pub input_index: usize,
     pub current_height: Option<u64>,
     pub chain_id: u32,
+    /// Commitment to the executing script, signed by the sighash: the script hash for
+    /// direct spends, or the leaf hash when spending one branch of a script tree.
     pub script_hash: qcoin_types::Hash256,
+    /// Spender-supplied items (signatures, keys, preimages) read by `PushWitness`.
+    /// They are not part of the script, so they are not committed by `script_hash`.
+    pub witness: Vec<Vec<u8>>,
 }
 
 #[derive(Debug, Error)]
@@ -48,6 +206,12 @@ pub enum ScriptError {
 
     #[error("script length exceeded limit")]
     ScriptTooLarge,
+
+    #[error("malformed script encoding: {0}")]
+    MalformedScript(consensus_codec::DecodeError),
+
+    #[error("script version {0} is not known to this node")]
+    UnknownVersion(ScriptVersion),
 }
 
 pub trait ScriptEngine {
@@ -57,6 +221,25 @@ pub trait ScriptEngine {
         ctx: &ScriptContext,
         host: &H,
     ) -> Result<ScriptResult, ScriptError>;
+
+    /// Evaluates a versioned script with the rules of its version.
+    fn eval_envelope<H: ScriptHost>(
+        &self,
+        envelope: &ScriptEnvelope,
+        ctx: &ScriptContext,
+        host: &H,
+    ) -> Result<ScriptResult, ScriptError>;
+}
+
+/// How the engine treats scripts whose version is above [`MAX_KNOWN_SCRIPT_VERSION`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnknownVersionPolicy {
+    /// Consensus rule: succeed without evaluating. A later version can then only add
+    /// restrictions, so upgraded and non-upgraded nodes agree on every block.
+    #[default]
+    AnyoneCanSpend,
+    /// Relay policy: refuse to spend such outputs before their rules are active.
+    Reject,
 }
 
 #[derive(Clone, Debug)]
@@ -65,6 +248,9 @@ pub struct VmConfig {
     pub max_stack_items: usize,
     pub max_push_bytes: usize,
     pub max_script_len: usize,
+    /// Gas costs, selected by the height the script is evaluated at.
+    pub gas_schedules: GasScheduleTable,
+    pub unknown_versions: UnknownVersionPolicy,
 }
 
 impl Default for VmConfig {
@@ -74,6 +260,19 @@ impl Default for VmConfig {
             max_stack_items: DEFAULT_MAX_STACK_ITEMS,
             max_push_bytes: DEFAULT_MAX_PUSH_BYTES,
             max_script_len: DEFAULT_MAX_SCRIPT_LEN,
+            gas_schedules: GasScheduleTable::default(),
+            unknown_versions: UnknownVersionPolicy::default(),
+        }
+    }
+}
+
+impl VmConfig {
+    /// The default limits with the gas schedules of chain `chain_id`, see
+    /// [`GasScheduleTable::for_chain`].
+    pub fn for_chain(chain_id: u32) -> Self {
+        Self {
+            gas_schedules: GasScheduleTable::for_chain(chain_id),
+            ..Self::default()
         }
     }
 }
@@ -83,14 +282,35 @@ pub struct ScriptResult {
     pub gas_consumed: u64,
 }
 
+/// One executed opcode as recorded by [`DeterministicScriptEngine::eval_traced`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceStep {
+    pub position: usize,
+    pub op: OpCode,
+    pub stack_before: Vec<Vec<u8>>,
+    pub stack_after: Vec<Vec<u8>>,
+    pub gas_cost: u64,
+    pub gas_used: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ScriptTrace {
+    pub steps: Vec<TraceStep>,
+}
+
 #[derive(Clone, Debug)]
 pub struct ResolvedInput {
     pub output: Output,
     pub created_height: Option<u64>,
+    /// Median-time-past of the chain when the output was created.
+    pub created_time: Option<u64>,
 }
 
 pub trait ScriptHost {
     fn current_height(&self) -> Option<u64>;
+    /// Consensus median-time-past the spend is evaluated at, in unix seconds.
+    fn current_time(&self) -> Option<u64>;
     fn input_utxo(&self, input: &TransactionInput) -> Option<ResolvedInput>;
 }
 
@@ -103,10 +323,19 @@ impl DeterministicScriptEngine {
     pub fn with_config(config: VmConfig) -> Self {
         Self { config }
     }
+
+    /// An engine running the rules of chain `chain_id`, see [`VmConfig::for_chain`].
+    pub fn for_chain(chain_id: u32) -> Self {
+        Self::with_config(VmConfig::for_chain(chain_id))
+    }
+
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
 }
 
 pub mod consensus_codec {
-    use super::{OpCode, Script};
+    use super::{GasSchedule, OpCode, Script, UnknownVersionPolicy, VmConfig};
 
     fn encode_len(len: usize, out: &mut Vec<u8>) {
         let len: u32 = len
@@ -136,23 +365,180 @@ pub mod consensus_codec {
                     out.extend_from_slice(data);
                 }
                 OpCode::Nop => out.push(6),
+                OpCode::PushWitness(index) => {
+                    out.push(7);
+                    out.push(*index);
+                }
+                OpCode::CheckDataSig => out.push(8),
+                OpCode::Blake3 => out.push(9),
+                OpCode::Sha256 => out.push(10),
+                OpCode::Sha3_256 => out.push(11),
+                OpCode::CheckHashLockSha256 => out.push(12),
+                OpCode::CheckHashLockSha3_256 => out.push(13),
+                OpCode::CheckTimestampLock => out.push(14),
+                OpCode::CheckRelativeTimestampLock => out.push(15),
+                OpCode::CheckAssetHeld => out.push(16),
             }
         }
 
         out
     }
+
+    /// Encodes every rule in `config` that affects whether a script evaluated at `height`
+    /// succeeds, so that nodes can compare VM configurations by hash. Only the gas
+    /// schedule active at `height` is included, as a one-entry table with its activation
+    /// height: schedules added for later heights leave the encoding of earlier heights
+    /// unchanged. Per-scheme verify costs are sorted by scheme id.
+    pub fn encode_vm_config_at(config: &VmConfig, height: u64) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&config.max_gas.to_le_bytes());
+        for limit in [
+            config.max_stack_items,
+            config.max_push_bytes,
+            config.max_script_len,
+        ] {
+            out.extend_from_slice(&(limit as u64).to_le_bytes());
+        }
+        out.push(match config.unknown_versions {
+            UnknownVersionPolicy::AnyoneCanSpend => 0,
+            UnknownVersionPolicy::Reject => 1,
+        });
+
+        let (activation_height, schedule) = config.gas_schedules.entry_at(height);
+        encode_len(1, &mut out);
+        out.extend_from_slice(&activation_height.to_le_bytes());
+        encode_gas_schedule(schedule, &mut out);
+        out
+    }
+
+    fn encode_gas_schedule(schedule: &GasSchedule, out: &mut Vec<u8>) {
+        for cost in [
+            schedule.nop,
+            schedule.base,
+            schedule.push_byte,
+            schedule.witness_byte,
+            schedule.hash,
+            schedule.blake3_block,
+            schedule.sha256_block,
+            schedule.sha3_256_block,
+            schedule.sig_base,
+            schedule.sig_verify_default,
+            schedule.input_lookup,
+        ] {
+            out.extend_from_slice(&cost.to_le_bytes());
+        }
+
+        let mut sig_verify: Vec<(u16, u64)> = schedule
+            .sig_verify
+            .iter()
+            .map(|(scheme, cost)| (scheme.to_u16(), *cost))
+            .collect();
+        sig_verify.sort_unstable();
+        encode_len(sig_verify.len(), out);
+        for (scheme, cost) in sig_verify {
+            out.extend_from_slice(&scheme.to_le_bytes());
+            out.extend_from_slice(&cost.to_le_bytes());
+        }
+    }
+
+    #[derive(Debug, thiserror::Error, PartialEq, Eq)]
+    pub enum DecodeError {
+        #[error("unexpected end of script encoding")]
+        UnexpectedEof,
+        #[error("unknown opcode tag {0}")]
+        UnknownOpcode(u8),
+        #[error("{0} trailing bytes after script encoding")]
+        TrailingBytes(usize),
+    }
+
+    struct Reader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> Reader<'a> {
+        fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+            let end = self
+                .pos
+                .checked_add(len)
+                .filter(|end| *end <= self.bytes.len())
+                .ok_or(DecodeError::UnexpectedEof)?;
+            let slice = &self.bytes[self.pos..end];
+            self.pos = end;
+            Ok(slice)
+        }
+
+        fn u8(&mut self) -> Result<u8, DecodeError> {
+            Ok(self.take(1)?[0])
+        }
+
+        fn len(&mut self) -> Result<usize, DecodeError> {
+            let raw = self.take(4)?;
+            Ok(u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as usize)
+        }
+    }
+
+    /// Decodes the output of [`encode_script`], rejecting unknown opcodes and trailing bytes.
+    pub fn decode_script(bytes: &[u8]) -> Result<Script, DecodeError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        let count = reader.len()?;
+        // Every opcode takes at least one byte, so a count beyond the input is malformed.
+        if count > bytes.len() {
+            return Err(DecodeError::UnexpectedEof);
+        }
+
+        let mut ops = Vec::with_capacity(count);
+        for _ in 0..count {
+            let op = match reader.u8()? {
+                0 => OpCode::CheckSig,
+                1 => OpCode::CheckMultiSig {
+                    threshold: reader.u8()?,
+                    total: reader.u8()?,
+                },
+                2 => OpCode::CheckTimeLock,
+                3 => OpCode::CheckRelativeTimeLock,
+                4 => OpCode::CheckHashLock,
+                5 => {
+                    let len = reader.len()?;
+                    OpCode::PushBytes(reader.take(len)?.to_vec())
+                }
+                6 => OpCode::Nop,
+                7 => OpCode::PushWitness(reader.u8()?),
+                8 => OpCode::CheckDataSig,
+                9 => OpCode::Blake3,
+                10 => OpCode::Sha256,
+                11 => OpCode::Sha3_256,
+                12 => OpCode::CheckHashLockSha256,
+                13 => OpCode::CheckHashLockSha3_256,
+                14 => OpCode::CheckTimestampLock,
+                15 => OpCode::CheckRelativeTimestampLock,
+                16 => OpCode::CheckAssetHeld,
+                other => return Err(DecodeError::UnknownOpcode(other)),
+            };
+            ops.push(op);
+        }
+
+        let remaining = bytes.len() - reader.pos;
+        if remaining != 0 {
+            return Err(DecodeError::TrailingBytes(remaining));
+        }
+
+        Ok(Script(ops))
+    }
 }
 
-struct GasMeter {
+struct GasMeter<'a> {
     remaining: u64,
     limit: u64,
+    schedule: &'a GasSchedule,
 }
 
-impl GasMeter {
-    fn new(limit: u64) -> Self {
+impl<'a> GasMeter<'a> {
+    fn new(limit: u64, schedule: &'a GasSchedule) -> Self {
         Self {
             remaining: limit,
             limit,
+            schedule,
         }
     }
 
@@ -201,265 +587,546 @@ impl ScriptEngine for DeterministicScriptEngine {
         script: &Script,
         ctx: &ScriptContext,
         host: &H,
+    ) -> Result<ScriptResult, ScriptError> {
+        self.run(script, ctx, host, None)
+    }
+
+    fn eval_envelope<H: ScriptHost>(
+        &self,
+        envelope: &ScriptEnvelope,
+        ctx: &ScriptContext,
+        host: &H,
+    ) -> Result<ScriptResult, ScriptError> {
+        match self.dispatch(envelope)? {
+            Some(script) => self.run(&script, ctx, host, None),
+            None => Ok(ScriptResult::default()),
+        }
+    }
+}
+
+impl DeterministicScriptEngine {
+    /// Evaluates `script` like [`ScriptEngine::eval`] while recording every executed opcode.
+    ///
+    /// The trace is returned even when evaluation fails; the failing opcode is the last step
+    /// and carries the error message.
+    pub fn eval_traced<H: ScriptHost>(
+        &self,
+        script: &Script,
+        ctx: &ScriptContext,
+        host: &H,
+    ) -> (Result<ScriptResult, ScriptError>, ScriptTrace) {
+        let mut trace = ScriptTrace::default();
+        let result = self.run(script, ctx, host, Some(&mut trace));
+        (result, trace)
+    }
+
+    /// [`Self::eval_traced`] for a versioned script. Unknown versions accepted by policy
+    /// produce an empty trace.
+    pub fn eval_envelope_traced<H: ScriptHost>(
+        &self,
+        envelope: &ScriptEnvelope,
+        ctx: &ScriptContext,
+        host: &H,
+    ) -> (Result<ScriptResult, ScriptError>, ScriptTrace) {
+        match self.dispatch(envelope) {
+            Ok(Some(script)) => self.eval_traced(&script, ctx, host),
+            Ok(None) => (Ok(ScriptResult::default()), ScriptTrace::default()),
+            Err(err) => (Err(err), ScriptTrace::default()),
+        }
+    }
+
+    /// Selects the rules for `envelope`: its decoded script for a known version, or
+    /// `None` when an unknown version is accepted without evaluation.
+    fn dispatch(&self, envelope: &ScriptEnvelope) -> Result<Option<Script>, ScriptError> {
+        match envelope.version {
+            SCRIPT_VERSION_LEGACY => envelope
+                .script()
+                .map(Some)
+                .map_err(ScriptError::MalformedScript),
+            version => match self.config.unknown_versions {
+                UnknownVersionPolicy::AnyoneCanSpend => Ok(None),
+                UnknownVersionPolicy::Reject => Err(ScriptError::UnknownVersion(version)),
+            },
+        }
+    }
+
+    fn run<H: ScriptHost>(
+        &self,
+        script: &Script,
+        ctx: &ScriptContext,
+        host: &H,
+        mut trace: Option<&mut ScriptTrace>,
     ) -> Result<ScriptResult, ScriptError> {
         if script.0.len() > self.config.max_script_len {
             return Err(ScriptError::ScriptTooLarge);
         }
 
-        let mut gas = GasMeter::new(self.config.max_gas);
+        let height = host.current_height().or(ctx.current_height).unwrap_or(0);
+        let mut gas = GasMeter::new(
+            self.config.max_gas,
+            self.config.gas_schedules.active_at(height),
+        );
         let mut stack = Stack::new(self.config.max_stack_items);
         let registry = default_registry();
 
-        for op in &script.0 {
-            let op_cost = gas_cost(op, self.config.max_push_bytes)?;
-            gas.consume(op_cost)?;
+        for (position, op) in script.0.iter().enumerate() {
+            let Some(trace) = trace.as_deref_mut() else {
+                self.step(op, &mut stack, &mut gas, ctx, host, &registry)?;
+                continue;
+            };
+
+            let stack_before = stack.items.clone();
+            let gas_before = gas.used();
+            let result = self.step(op, &mut stack, &mut gas, ctx, host, &registry);
+            trace.steps.push(TraceStep {
+                position,
+                op: op.clone(),
+                stack_before,
+                stack_after: stack.items.clone(),
+                gas_cost: gas.used() - gas_before,
+                gas_used: gas.used(),
+                error: result.as_ref().err().map(ToString::to_string),
+            });
+            result?;
+        }
 
-            match op {
-                OpCode::PushBytes(data) => {
-                    if data.len() > self.config.max_push_bytes {
-                        return Err(ScriptError::Evaluation(
-                            "push exceeds byte limit".to_string(),
-                        ));
-                    }
-                    stack.push(data.clone())?;
-                }
-                OpCode::Nop => {}
-                OpCode::CheckSig => {
-                    let signature_bytes = stack.pop()?;
-                    let public_key_bytes = stack.pop()?;
+        Ok(ScriptResult {
+            gas_consumed: gas.used(),
+        })
+    }
 
-                    let public_key = PublicKey::from_bytes(&public_key_bytes).map_err(|err| {
-                        ScriptError::Evaluation(format!("invalid public key: {err}"))
+    fn step<H: ScriptHost>(
+        &self,
+        op: &OpCode,
+        stack: &mut Stack,
+        gas: &mut GasMeter,
+        ctx: &ScriptContext,
+        host: &H,
+        registry: &InMemoryRegistry,
+    ) -> Result<(), ScriptError> {
+        let schedule = gas.schedule;
+        let op_cost = gas_cost(op, schedule, self.config.max_push_bytes)?;
+        gas.consume(op_cost)?;
+
+        match op {
+            OpCode::PushBytes(data) => {
+                if data.len() > self.config.max_push_bytes {
+                    return Err(ScriptError::Evaluation(
+                        "push exceeds byte limit".to_string(),
+                    ));
+                }
+                stack.push(data.clone())?;
+            }
+            OpCode::Nop => {}
+            OpCode::PushWitness(index) => {
+                let item = ctx.witness.get(*index as usize).ok_or_else(|| {
+                    ScriptError::Evaluation(format!("witness item {index} not provided"))
+                })?;
+                if item.len() > self.config.max_push_bytes {
+                    return Err(ScriptError::Evaluation(
+                        "witness item exceeds byte limit".to_string(),
+                    ));
+                }
+                gas.consume(schedule.witness_byte * item.len() as u64)?;
+                stack.push(item.clone())?;
+            }
+            OpCode::CheckSig => {
+                let signature_bytes = stack.pop()?;
+                let public_key_bytes = stack.pop()?;
+
+                let public_key = PublicKey::from_bytes(&public_key_bytes)
+                    .map_err(|err| ScriptError::Evaluation(format!("invalid public key: {err}")))?;
+                let (signature, flags) = decode_signature_item(&signature_bytes)?;
+
+                let scheme = registry.get(&public_key.scheme).ok_or_else(|| {
+                    ScriptError::Evaluation("signature scheme not registered".to_string())
+                })?;
+                gas.consume(schedule.sig_verify_cost(public_key.scheme))?;
+
+                let prev_output = resolve_spent_output(ctx, host)?;
+                let sighash = signature_hash(ctx, &prev_output, flags)?;
+
+                scheme
+                    .verify(&public_key, &sighash, &signature)
+                    .map_err(|err| {
+                        ScriptError::Evaluation(format!("signature verification failed: {err}"))
                     })?;
-                    let signature = Signature::from_bytes(&signature_bytes).map_err(|err| {
-                        ScriptError::Evaluation(format!("invalid signature: {err}"))
+            }
+            OpCode::CheckDataSig => {
+                let signature_bytes = stack.pop()?;
+                let message = stack.pop()?;
+                let public_key_bytes = stack.pop()?;
+
+                let public_key = PublicKey::from_bytes(&public_key_bytes)
+                    .map_err(|err| ScriptError::Evaluation(format!("invalid public key: {err}")))?;
+                // Data signatures sign no transaction, so they carry no sighash flags.
+                let signature = Signature::from_bytes(&signature_bytes)
+                    .map_err(|err| ScriptError::Evaluation(format!("invalid signature: {err}")))?;
+
+                let scheme = registry.get(&public_key.scheme).ok_or_else(|| {
+                    ScriptError::Evaluation("signature scheme not registered".to_string())
+                })?;
+                gas.consume(schedule.sig_verify_cost(public_key.scheme))?;
+                // PQ schemes absorb the message with SHAKE, priced like SHA3.
+                gas.consume(schedule.hash_input_cost(HashAlgorithm::Sha3_256, message.len()))?;
+
+                scheme
+                    .verify(&public_key, &message, &signature)
+                    .map_err(|err| {
+                        ScriptError::Evaluation(format!(
+                            "data signature verification failed: {err}"
+                        ))
                     })?;
+            }
+            OpCode::CheckMultiSig { threshold, total } => {
+                let threshold = *threshold as usize;
+                let total = *total as usize;
+
+                if threshold == 0 || total == 0 || threshold > total {
+                    return Err(ScriptError::Evaluation(
+                        "invalid multisig threshold".to_string(),
+                    ));
+                }
 
-                    let scheme = registry.get(&public_key.scheme).ok_or_else(|| {
-                        ScriptError::Evaluation("signature scheme not registered".to_string())
+                let mut signatures = Vec::with_capacity(threshold);
+                for _ in 0..threshold {
+                    signatures.push(decode_signature_item(&stack.pop()?)?);
+                }
+
+                let mut pubkeys = Vec::with_capacity(total);
+                for _ in 0..total {
+                    let pk_bytes = stack.pop()?;
+                    let public_key = PublicKey::from_bytes(&pk_bytes).map_err(|err| {
+                        ScriptError::Evaluation(format!("invalid public key: {err}"))
                     })?;
+                    pubkeys.push(public_key);
+                }
 
-                    let prev_output = host
-                        .input_utxo(ctx.tx.core.inputs.get(ctx.input_index).ok_or_else(|| {
-                            ScriptError::Evaluation("input index out of bounds".to_string())
-                        })?)
-                        .ok_or_else(|| {
-                            ScriptError::Evaluation("host could not resolve input".to_string())
+                // Items were popped top-first; restore push order so that signatures are
+                // matched against keys in the order both were pushed. Each signature must
+                // verify against a later key than the previous one, so any `threshold` of
+                // the `total` keys can sign as long as their signatures keep key order.
+                signatures.reverse();
+                pubkeys.reverse();
+
+                // Each signature carries its own flags, so each gets its own sighash.
+                let prev_output = resolve_spent_output(ctx, host)?;
+                let signatures = signatures
+                    .into_iter()
+                    .map(|(signature, flags)| {
+                        signature_hash(ctx, &prev_output, flags).map(|hash| (signature, hash))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let mut keys = pubkeys.iter();
+                for (signature, sighash) in &signatures {
+                    loop {
+                        let public_key = keys.next().ok_or_else(|| {
+                            ScriptError::Evaluation(
+                                "multisig verification failed: no remaining key matches signature"
+                                    .to_string(),
+                            )
                         })?;
+                        if public_key.scheme != signature.scheme {
+                            continue;
+                        }
 
-                    let sighash = ctx.tx.sighash(
-                        ctx.input_index,
-                        &prev_output.output,
-                        ctx.script_hash,
-                        ctx.chain_id,
-                        SighashFlags::default(),
-                    );
-
-                    scheme
-                        .verify(&public_key, &sighash, &signature)
-                        .map_err(|err| {
-                            ScriptError::Evaluation(format!("signature verification failed: {err}"))
+                        let scheme = registry.get(&public_key.scheme).ok_or_else(|| {
+                            ScriptError::Evaluation("signature scheme not registered".to_string())
                         })?;
+                        gas.consume(schedule.sig_verify_cost(public_key.scheme))?;
+                        if scheme.verify(public_key, sighash, signature).is_ok() {
+                            break;
+                        }
+                        // Only `threshold` signature bases are prepaid; skipped keys cost extra.
+                        gas.consume(schedule.sig_base)?;
+                    }
+                }
+            }
+            OpCode::CheckTimeLock => {
+                let required_height_bytes = stack.pop()?;
+                if required_height_bytes.len() != 8 {
+                    return Err(ScriptError::Evaluation(
+                        "timelock expects 8-byte height".to_string(),
+                    ));
                 }
-                OpCode::CheckMultiSig { threshold, total } => {
-                    let threshold = *threshold as usize;
-                    let total = *total as usize;
 
-                    if threshold == 0 || total == 0 || threshold > total {
-                        return Err(ScriptError::Evaluation(
-                            "invalid multisig threshold".to_string(),
-                        ));
-                    }
+                let required_height = u64::from_le_bytes(
+                    required_height_bytes
+                        .as_slice()
+                        .try_into()
+                        .expect("length already checked"),
+                );
 
-                    let mut signatures = Vec::with_capacity(threshold);
-                    for _ in 0..threshold {
-                        let sig_bytes = stack.pop()?;
-                        let signature = Signature::from_bytes(&sig_bytes).map_err(|err| {
-                            ScriptError::Evaluation(format!("invalid signature: {err}"))
+                let current_height =
+                    host.current_height()
+                        .or(ctx.current_height)
+                        .ok_or_else(|| {
+                            ScriptError::Evaluation(
+                                "current height unavailable for timelock".to_string(),
+                            )
                         })?;
-                        signatures.push(signature);
-                    }
 
-                    let mut pubkeys = Vec::with_capacity(total);
-                    for _ in 0..total {
-                        let pk_bytes = stack.pop()?;
-                        let public_key = PublicKey::from_bytes(&pk_bytes).map_err(|err| {
-                            ScriptError::Evaluation(format!("invalid public key: {err}"))
-                        })?;
-                        pubkeys.push(public_key);
-                    }
+                if current_height < required_height {
+                    return Err(ScriptError::Evaluation(
+                        "absolute timelock not satisfied".to_string(),
+                    ));
+                }
+            }
+            OpCode::CheckRelativeTimeLock => {
+                let relative_bytes = stack.pop()?;
+                if relative_bytes.len() != 8 {
+                    return Err(ScriptError::Evaluation(
+                        "relative timelock expects 8-byte height".to_string(),
+                    ));
+                }
 
-                    for (idx, signature) in signatures.iter().enumerate() {
-                        let public_key = pubkeys.get(idx).ok_or_else(|| {
+                let relative_height = u64::from_le_bytes(
+                    relative_bytes
+                        .as_slice()
+                        .try_into()
+                        .expect("length already checked"),
+                );
+
+                let input = ctx.tx.core.inputs.get(ctx.input_index).ok_or_else(|| {
+                    ScriptError::Evaluation("input index out of bounds".to_string())
+                })?;
+
+                let resolved = host.input_utxo(input).ok_or_else(|| {
+                    ScriptError::Evaluation(
+                        "host could not resolve input for relative timelock".to_string(),
+                    )
+                })?;
+
+                let created_height = resolved.created_height.ok_or_else(|| {
+                    ScriptError::Evaluation("input creation height unavailable".to_string())
+                })?;
+
+                let current_height =
+                    host.current_height()
+                        .or(ctx.current_height)
+                        .ok_or_else(|| {
                             ScriptError::Evaluation(
-                                "multisig stack did not contain enough public keys".to_string(),
+                                "current height unavailable for timelock".to_string(),
                             )
                         })?;
 
-                        let scheme = registry.get(&public_key.scheme).ok_or_else(|| {
-                            ScriptError::Evaluation("signature scheme not registered".to_string())
-                        })?;
-
-                        let prev_output = host
-                            .input_utxo(ctx.tx.core.inputs.get(ctx.input_index).ok_or_else(
-                                || ScriptError::Evaluation("input index out of bounds".to_string()),
-                            )?)
-                            .ok_or_else(|| {
-                                ScriptError::Evaluation("host could not resolve input".to_string())
-                            })?;
-
-                        let sighash = ctx.tx.sighash(
-                            ctx.input_index,
-                            &prev_output.output,
-                            ctx.script_hash,
-                            ctx.chain_id,
-                            SighashFlags::default(),
-                        );
-
-                        scheme
-                            .verify(public_key, &sighash, signature)
-                            .map_err(|err| {
-                                ScriptError::Evaluation(format!(
-                                    "multisig verification failed: {err}"
-                                ))
-                            })?;
-                    }
+                if current_height < created_height + relative_height {
+                    return Err(ScriptError::Evaluation(
+                        "relative timelock not satisfied".to_string(),
+                    ));
                 }
-                OpCode::CheckTimeLock => {
-                    let required_height_bytes = stack.pop()?;
-                    if required_height_bytes.len() != 8 {
-                        return Err(ScriptError::Evaluation(
-                            "timelock expects 8-byte height".to_string(),
-                        ));
-                    }
-
-                    let required_height = u64::from_le_bytes(
-                        required_height_bytes
-                            .as_slice()
-                            .try_into()
-                            .expect("length already checked"),
-                    );
-
-                    let current_height =
-                        host.current_height()
-                            .or(ctx.current_height)
-                            .ok_or_else(|| {
-                                ScriptError::Evaluation(
-                                    "current height unavailable for timelock".to_string(),
-                                )
-                            })?;
-
-                    if current_height < required_height {
-                        return Err(ScriptError::Evaluation(
-                            "absolute timelock not satisfied".to_string(),
-                        ));
-                    }
+            }
+            OpCode::CheckTimestampLock => {
+                let required_time = pop_u64(stack, "timestamp lock expects 8-byte time")?;
+                let current_time = host.current_time().ok_or_else(|| {
+                    ScriptError::Evaluation("current time unavailable for timelock".to_string())
+                })?;
+
+                if current_time < required_time {
+                    return Err(ScriptError::Evaluation(
+                        "absolute timestamp lock not satisfied".to_string(),
+                    ));
                 }
-                OpCode::CheckRelativeTimeLock => {
-                    let relative_bytes = stack.pop()?;
-                    if relative_bytes.len() != 8 {
-                        return Err(ScriptError::Evaluation(
-                            "relative timelock expects 8-byte height".to_string(),
-                        ));
-                    }
-
-                    let relative_height = u64::from_le_bytes(
-                        relative_bytes
-                            .as_slice()
-                            .try_into()
-                            .expect("length already checked"),
-                    );
-
-                    let input = ctx.tx.core.inputs.get(ctx.input_index).ok_or_else(|| {
-                        ScriptError::Evaluation("input index out of bounds".to_string())
-                    })?;
-
+            }
+            OpCode::CheckRelativeTimestampLock => {
+                let relative_time =
+                    pop_u64(stack, "relative timestamp lock expects 8-byte duration")?;
+                let resolved = resolve_input(ctx, host)?;
+                let created_time = resolved.created_time.ok_or_else(|| {
+                    ScriptError::Evaluation("input creation time unavailable".to_string())
+                })?;
+                let current_time = host.current_time().ok_or_else(|| {
+                    ScriptError::Evaluation("current time unavailable for timelock".to_string())
+                })?;
+
+                if current_time < created_time.saturating_add(relative_time) {
+                    return Err(ScriptError::Evaluation(
+                        "relative timestamp lock not satisfied".to_string(),
+                    ));
+                }
+            }
+            OpCode::CheckAssetHeld => {
+                let min_amount =
+                    u128::from_le_bytes(pop_array(stack, "asset check expects 16-byte amount")?);
+                let asset_id = AssetId(pop_array(stack, "asset check expects 32-byte asset id")?);
+
+                let mut held = false;
+                for input in &ctx.tx.core.inputs {
+                    gas.consume(schedule.input_lookup)?;
                     let resolved = host.input_utxo(input).ok_or_else(|| {
-                        ScriptError::Evaluation(
-                            "host could not resolve input for relative timelock".to_string(),
-                        )
+                        ScriptError::Evaluation("host could not resolve input".to_string())
                     })?;
-
-                    let created_height = resolved.created_height.ok_or_else(|| {
-                        ScriptError::Evaluation("input creation height unavailable".to_string())
-                    })?;
-
-                    let current_height =
-                        host.current_height()
-                            .or(ctx.current_height)
-                            .ok_or_else(|| {
-                                ScriptError::Evaluation(
-                                    "current height unavailable for timelock".to_string(),
-                                )
-                            })?;
-
-                    if current_height < created_height + relative_height {
-                        return Err(ScriptError::Evaluation(
-                            "relative timelock not satisfied".to_string(),
-                        ));
+                    let amount = resolved
+                        .output
+                        .assets
+                        .iter()
+                        .filter(|asset| asset.asset_id == asset_id)
+                        .fold(0u128, |total, asset| total.saturating_add(asset.amount));
+                    if amount >= min_amount {
+                        held = true;
+                        break;
                     }
                 }
-                OpCode::CheckHashLock => {
-                    let preimage = stack.pop()?;
-                    let expected_hash = stack.pop()?;
-
-                    if expected_hash.len() != 32 {
-                        return Err(ScriptError::Evaluation(
-                            "hashlock expects 32-byte hash".to_string(),
-                        ));
-                    }
 
-                    let actual = hash(&preimage);
-                    if expected_hash.as_slice() != actual.as_bytes() {
-                        return Err(ScriptError::Evaluation(
-                            "hashlock preimage mismatch".to_string(),
-                        ));
-                    }
+                if !held {
+                    return Err(ScriptError::Evaluation(
+                        "no input holds the required asset amount".to_string(),
+                    ));
+                }
+            }
+            OpCode::Blake3 | OpCode::Sha256 | OpCode::Sha3_256 => {
+                let algorithm = op.hash_algorithm().expect("digest opcode has an algorithm");
+                let data = stack.pop()?;
+                gas.consume(schedule.hash_input_cost(algorithm, data.len()))?;
+                stack.push(algorithm.digest(&data).to_vec())?;
+            }
+            OpCode::CheckHashLock | OpCode::CheckHashLockSha256 | OpCode::CheckHashLockSha3_256 => {
+                let algorithm = op
+                    .hash_algorithm()
+                    .expect("hashlock opcode has an algorithm");
+                let preimage = stack.pop()?;
+                gas.consume(schedule.hash_input_cost(algorithm, preimage.len()))?;
+                let expected_hash = stack.pop()?;
+
+                if expected_hash.len() != 32 {
+                    return Err(ScriptError::Evaluation(
+                        "hashlock expects 32-byte hash".to_string(),
+                    ));
+                }
+
+                if expected_hash.as_slice() != algorithm.digest(&preimage) {
+                    return Err(ScriptError::Evaluation(
+                        "hashlock preimage mismatch".to_string(),
+                    ));
                 }
             }
         }
 
-        Ok(ScriptResult {
-            gas_consumed: gas.used(),
-        })
+        Ok(())
     }
 }
 
-fn gas_cost(op: &OpCode, max_push_bytes: usize) -> Result<u64, ScriptError> {
-    const BASE_COST: u64 = 10;
-    const SIG_COST: u64 = 5_000;
-    const HASH_COST: u64 = 250;
+/// Encodes a signature as a witness stack item: the scheme-tagged signature encoding,
+/// followed by the sighash flag byte unless `flags` is [`SighashFlags::ALL`].
+pub fn encode_signature_item(
+    signature: &Signature,
+    flags: SighashFlags,
+) -> Result<Vec<u8>, CryptoError> {
+    let mut item = signature.to_bytes()?;
+    if flags != SighashFlags::ALL {
+        item.push(flags.to_byte());
+    }
+    Ok(item)
+}
 
-    match op {
-        OpCode::Nop => Ok(1),
-        OpCode::PushBytes(data) => {
-            if data.len() > max_push_bytes {
-                return Err(ScriptError::Evaluation(
-                    "push exceeds byte limit".to_string(),
-                ));
-            }
-            Ok(BASE_COST + data.len() as u64)
+fn decode_signature_item(item: &[u8]) -> Result<(Signature, SighashFlags), ScriptError> {
+    const PREFIX_LEN: usize = 2 + 4;
+    let invalid = |reason: &str| ScriptError::Evaluation(format!("invalid signature: {reason}"));
+
+    let len_bytes = item
+        .get(2..PREFIX_LEN)
+        .ok_or_else(|| invalid("truncated encoding"))?;
+    let encoded_len =
+        PREFIX_LEN + u32::from_le_bytes(len_bytes.try_into().expect("slice has 4 bytes")) as usize;
+
+    let flags = match item.len().checked_sub(encoded_len) {
+        Some(0) => SighashFlags::ALL,
+        // ALL is only ever encoded by omitting the byte, so each item has one encoding.
+        Some(1) => SighashFlags::from_byte(item[encoded_len])
+            .filter(|flags| *flags != SighashFlags::ALL)
+            .ok_or_else(|| invalid("unknown sighash flags"))?,
+        _ => return Err(invalid("unexpected trailing bytes")),
+    };
+
+    let signature = Signature::from_bytes(&item[..encoded_len])
+        .map_err(|err| ScriptError::Evaluation(format!("invalid signature: {err}")))?;
+    Ok((signature, flags))
+}
+
+fn resolve_input<H: ScriptHost>(
+    ctx: &ScriptContext,
+    host: &H,
+) -> Result<ResolvedInput, ScriptError> {
+    let input = ctx
+        .tx
+        .core
+        .inputs
+        .get(ctx.input_index)
+        .ok_or_else(|| ScriptError::Evaluation("input index out of bounds".to_string()))?;
+    host.input_utxo(input)
+        .ok_or_else(|| ScriptError::Evaluation("host could not resolve input".to_string()))
+}
+
+fn resolve_spent_output<H: ScriptHost>(
+    ctx: &ScriptContext,
+    host: &H,
+) -> Result<Output, ScriptError> {
+    resolve_input(ctx, host).map(|resolved| resolved.output)
+}
+
+/// Pops an 8-byte little-endian operand, as pushed for timelocks.
+fn pop_u64(stack: &mut Stack, error: &str) -> Result<u64, ScriptError> {
+    pop_array(stack, error).map(u64::from_le_bytes)
+}
+
+/// Pops an item that must be exactly `N` bytes long.
+fn pop_array<const N: usize>(stack: &mut Stack, error: &str) -> Result<[u8; N], ScriptError> {
+    stack
+        .pop()?
+        .as_slice()
+        .try_into()
+        .map_err(|_| ScriptError::Evaluation(error.to_string()))
+}
+
+fn signature_hash(
+    ctx: &ScriptContext,
+    prev_output: &Output,
+    flags: SighashFlags,
+) -> Result<Hash256, ScriptError> {
+    if flags.base() == SighashFlags::SINGLE && ctx.input_index >= ctx.tx.core.outputs.len() {
+        return Err(ScriptError::Evaluation(
+            "SINGLE sighash without a matching output".to_string(),
+        ));
+    }
+    Ok(ctx.tx.sighash(
+        ctx.input_index,
+        prev_output,
+        ctx.script_hash,
+        ctx.chain_id,
+        flags,
+    ))
+}
+
+fn gas_cost(
+    op: &OpCode,
+    schedule: &GasSchedule,
+    max_push_bytes: usize,
+) -> Result<u64, ScriptError> {
+    if let OpCode::PushBytes(data) = op {
+        if data.len() > max_push_bytes {
+            return Err(ScriptError::Evaluation(
+                "push exceeds byte limit".to_string(),
+            ));
         }
-        OpCode::CheckSig => Ok(SIG_COST),
-        OpCode::CheckMultiSig { threshold, .. } => Ok(SIG_COST * (*threshold as u64).max(1)),
-        OpCode::CheckTimeLock | OpCode::CheckRelativeTimeLock => Ok(BASE_COST),
-        OpCode::CheckHashLock => Ok(HASH_COST),
     }
+    Ok(schedule.op_cost(op))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use qcoin_crypto::SignatureSchemeId;
+    use qcoin_crypto::{PrivateKey, SignatureSchemeId};
     use qcoin_types::{
-        AssetAmount, AssetId, Hash256, Output, TransactionCore, TransactionInput, TransactionKind,
-        TransactionWitness,
+        AssetAmount, AssetId, ChainActivations, Hash256, Output, TransactionCore, TransactionInput,
+        TransactionKind, TransactionWitness,
     };
     use std::collections::HashMap;
 
     #[derive(Default)]
     struct StaticHost {
         current_height: Option<u64>,
+        current_time: Option<u64>,
         inputs: HashMap<(Hash256, u32), ResolvedInput>,
     }
 
@@ -467,10 +1134,16 @@ mod tests {
         fn new(current_height: Option<u64>) -> Self {
             Self {
                 current_height,
+                current_time: None,
                 inputs: HashMap::new(),
             }
         }
 
+        fn at_time(mut self, current_time: u64) -> Self {
+            self.current_time = Some(current_time);
+            self
+        }
+
         fn with_input(mut self, input: TransactionInput, resolved: ResolvedInput) -> Self {
             self.inputs.insert((input.tx_id, input.index), resolved);
             self
@@ -482,6 +1155,10 @@ mod tests {
             self.current_height
         }
 
+        fn current_time(&self) -> Option<u64> {
+            self.current_time
+        }
+
         fn input_utxo(&self, input: &TransactionInput) -> Option<ResolvedInput> {
             self.inputs.get(&(input.tx_id, input.index)).cloned()
         }
@@ -505,6 +1182,8 @@ mod tests {
                     }],
                     metadata_hash: None,
                 }],
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: TransactionWitness::default(),
         };
@@ -555,6 +1234,7 @@ mod tests {
             ResolvedInput {
                 output: tx.core.outputs[0].clone(),
                 created_height: Some(1),
+                created_time: None,
             },
         );
 
@@ -564,6 +1244,7 @@ mod tests {
             current_height: Some(10),
             chain_id: 0,
             script_hash,
+            witness: Vec::new(),
         };
 
         let engine = default_engine();
@@ -598,6 +1279,7 @@ mod tests {
             ResolvedInput {
                 output: tx.core.outputs[0].clone(),
                 created_height: Some(0),
+                created_time: None,
             },
         );
 
@@ -607,6 +1289,7 @@ mod tests {
             current_height: Some(5),
             chain_id: 0,
             script_hash,
+            witness: Vec::new(),
         };
 
         let engine = default_engine();
@@ -631,6 +1314,7 @@ mod tests {
             ResolvedInput {
                 output: tx.core.outputs[0].clone(),
                 created_height: Some(0),
+                created_time: None,
             },
         );
 
@@ -640,6 +1324,7 @@ mod tests {
             current_height: Some(10),
             chain_id: 0,
             script_hash,
+            witness: Vec::new(),
         };
 
         let engine = default_engine();
@@ -651,6 +1336,7 @@ mod tests {
             ResolvedInput {
                 output: tx.core.outputs[0].clone(),
                 created_height: Some(0),
+                created_time: None,
             },
         );
         let ctx = ScriptContext {
@@ -659,6 +1345,7 @@ mod tests {
             current_height: Some(12),
             chain_id: 0,
             script_hash,
+            witness: Vec::new(),
         };
 
         let result = engine.eval(&script, &ctx, &host);
@@ -676,6 +1363,7 @@ mod tests {
         let resolved = ResolvedInput {
             output: tx.core.outputs[0].clone(),
             created_height: Some(5),
+            created_time: None,
         };
 
         let script_hash = script_hash(&script);
@@ -687,6 +1375,7 @@ mod tests {
             current_height: Some(7),
             chain_id: 0,
             script_hash,
+            witness: Vec::new(),
         };
         let engine = default_engine();
         let result = engine.eval(&script, &ctx, &host);
@@ -699,11 +1388,204 @@ mod tests {
             current_height: Some(9),
             chain_id: 0,
             script_hash,
+            witness: Vec::new(),
         };
         let result = engine.eval(&script, &ctx, &host);
         assert!(result.is_ok());
     }
 
+    #[test]
+    fn envelope_commits_to_version_and_dispatches_on_it() {
+        let (tx, _) = sample_tx();
+        let script = Script(vec![OpCode::PushBytes(vec![1]), OpCode::Nop]);
+        let legacy = ScriptEnvelope::legacy(&script);
+        let future = ScriptEnvelope::new(MAX_KNOWN_SCRIPT_VERSION + 1, &script);
+        assert_eq!(legacy.script_hash(), script.script_hash());
+        assert_ne!(future.script_hash(), legacy.script_hash());
+        assert_eq!(tree::leaf_hash(&script), tree::envelope_leaf_hash(&legacy));
+        assert!(legacy.is_known() && !future.is_known());
+
+        let ctx = ScriptContext {
+            tx,
+            input_index: 0,
+            current_height: Some(1),
+            chain_id: 0,
+            script_hash: legacy.script_hash(),
+            witness: Vec::new(),
+        };
+        let host = StaticHost::new(Some(1));
+        let engine = default_engine();
+        let result = engine.eval_envelope(&legacy, &ctx, &host).unwrap();
+        assert_eq!(result.gas_consumed, 12);
+
+        // Consensus accepts unknown versions without evaluating them, even if the body is
+        // not a valid legacy encoding.
+        let opaque = ScriptEnvelope {
+            version: 7,
+            body: vec![0xff; 3],
+        };
+        assert_eq!(
+            engine
+                .eval_envelope(&opaque, &ctx, &host)
+                .unwrap()
+                .gas_consumed,
+            0
+        );
+
+        let policy = DeterministicScriptEngine::with_config(VmConfig {
+            unknown_versions: UnknownVersionPolicy::Reject,
+            ..VmConfig::default()
+        });
+        assert!(matches!(
+            policy.eval_envelope(&opaque, &ctx, &host),
+            Err(ScriptError::UnknownVersion(7))
+        ));
+        assert!(policy.eval_envelope(&legacy, &ctx, &host).is_ok());
+
+        let malformed = ScriptEnvelope {
+            version: SCRIPT_VERSION_LEGACY,
+            body: vec![0xff; 3],
+        };
+        assert!(matches!(
+            engine.eval_envelope(&malformed, &ctx, &host),
+            Err(ScriptError::MalformedScript(_))
+        ));
+    }
+
+    #[test]
+    fn checks_asset_held_by_any_input() {
+        let (mut tx, own_input) = sample_tx();
+        let item = AssetId([9u8; 32]);
+        let key_input = TransactionInput {
+            tx_id: [4u8; 32],
+            index: 1,
+        };
+        tx.core.inputs.push(key_input.clone());
+
+        let output_with = |assets: Vec<AssetAmount>| ResolvedInput {
+            output: Output {
+                owner_script_hash: [2u8; 32],
+                assets,
+                metadata_hash: None,
+            },
+            created_height: Some(0),
+            created_time: None,
+        };
+        let host = StaticHost::new(Some(1))
+            .with_input(own_input, output_with(Vec::new()))
+            .with_input(
+                key_input,
+                output_with(vec![
+                    AssetAmount {
+                        asset_id: item.clone(),
+                        amount: 3,
+                    },
+                    AssetAmount {
+                        asset_id: item.clone(),
+                        amount: 2,
+                    },
+                ]),
+            );
+
+        let engine = default_engine();
+        let eval_for = |min_amount: u128| {
+            let script = Script(vec![
+                OpCode::PushBytes(item.0.to_vec()),
+                OpCode::PushBytes(min_amount.to_le_bytes().to_vec()),
+                OpCode::CheckAssetHeld,
+            ]);
+            let ctx = ScriptContext {
+                tx: tx.clone(),
+                input_index: 0,
+                current_height: Some(1),
+                chain_id: 0,
+                script_hash: script_hash(&script),
+                witness: Vec::new(),
+            };
+            engine.eval(&script, &ctx, &host)
+        };
+
+        // Two lookups: the spending input holds nothing, the second input holds 3 + 2.
+        let result = eval_for(5).expect("second input holds enough");
+        let legacy = GasSchedule::legacy();
+        assert_eq!(
+            result.gas_consumed,
+            (legacy.base + 32) + (legacy.base + 16) + legacy.base + 2 * legacy.input_lookup
+        );
+        assert!(matches!(eval_for(6), Err(ScriptError::Evaluation(_))));
+
+        let malformed = Script(vec![
+            OpCode::PushBytes(item.0.to_vec()),
+            OpCode::PushBytes(vec![1]),
+            OpCode::CheckAssetHeld,
+        ]);
+        let ctx = ScriptContext {
+            tx: tx.clone(),
+            input_index: 0,
+            current_height: Some(1),
+            chain_id: 0,
+            script_hash: script_hash(&malformed),
+            witness: Vec::new(),
+        };
+        assert!(engine.eval(&malformed, &ctx, &host).is_err());
+    }
+
+    #[test]
+    fn enforces_timestamp_locks_against_host_time() {
+        let (tx, input) = sample_tx();
+        let resolved = ResolvedInput {
+            output: tx.core.outputs[0].clone(),
+            created_height: Some(5),
+            created_time: Some(1_000),
+        };
+        let engine = default_engine();
+        let eval_at = |script: &Script, host: StaticHost| {
+            let ctx = ScriptContext {
+                tx: tx.clone(),
+                input_index: 0,
+                current_height: Some(100),
+                chain_id: 0,
+                script_hash: script_hash(script),
+                witness: Vec::new(),
+            };
+            engine.eval(script, &ctx, &host)
+        };
+
+        let absolute = Script(vec![
+            OpCode::PushBytes(u64_le_bytes(2_000)),
+            OpCode::CheckTimestampLock,
+        ]);
+        let host = || StaticHost::new(Some(100)).with_input(input.clone(), resolved.clone());
+        assert!(eval_at(&absolute, host().at_time(1_999)).is_err());
+        assert!(eval_at(&absolute, host().at_time(2_000)).is_ok());
+        // Without a consensus time the lock can never be satisfied.
+        assert!(eval_at(&absolute, host()).is_err());
+
+        let relative = Script(vec![
+            OpCode::PushBytes(u64_le_bytes(600)),
+            OpCode::CheckRelativeTimestampLock,
+        ]);
+        assert!(eval_at(&relative, host().at_time(1_599)).is_err());
+        assert!(eval_at(&relative, host().at_time(1_600)).is_ok());
+
+        let unknown_creation = StaticHost::new(Some(100))
+            .with_input(
+                input.clone(),
+                ResolvedInput {
+                    created_time: None,
+                    ..resolved.clone()
+                },
+            )
+            .at_time(5_000);
+        assert!(eval_at(&relative, unknown_creation).is_err());
+
+        let short_operand = Script(vec![
+            OpCode::PushBytes(vec![1, 2, 3]),
+            OpCode::CheckTimestampLock,
+        ]);
+        assert!(eval_at(&short_operand, host().at_time(5_000)).is_err());
+    }
+
     #[test]
     fn validates_hashlock_preimage() {
         let (tx, input) = sample_tx();
@@ -723,6 +1605,7 @@ mod tests {
             ResolvedInput {
                 output: tx.core.outputs[0].clone(),
                 created_height: Some(0),
+                created_time: None,
             },
         );
         let ctx = ScriptContext {
@@ -731,6 +1614,7 @@ mod tests {
             current_height: Some(1),
             chain_id: 0,
             script_hash,
+            witness: Vec::new(),
         };
         let engine = default_engine();
         let result = engine.eval(&script, &ctx, &host);
@@ -745,6 +1629,97 @@ mod tests {
         assert!(matches!(result, Err(ScriptError::Evaluation(_))));
     }
 
+    #[test]
+    fn traces_each_opcode_until_failure() {
+        let (tx, input) = sample_tx();
+        let expected_hash = hash(b"secret").as_bytes().to_vec();
+        let script = Script(vec![
+            OpCode::Nop,
+            OpCode::PushBytes(expected_hash.clone()),
+            OpCode::PushBytes(b"wrong".to_vec()),
+            OpCode::CheckHashLock,
+            OpCode::Nop,
+        ]);
+
+        let host = StaticHost::new(Some(1)).with_input(
+            input,
+            ResolvedInput {
+                output: tx.core.outputs[0].clone(),
+                created_height: Some(0),
+                created_time: None,
+            },
+        );
+        let ctx = ScriptContext {
+            tx,
+            input_index: 0,
+            current_height: Some(1),
+            chain_id: 0,
+            script_hash: script_hash(&script),
+            witness: Vec::new(),
+        };
+
+        let (result, trace) = default_engine().eval_traced(&script, &ctx, &host);
+        assert!(matches!(result, Err(ScriptError::Evaluation(_))));
+        assert_eq!(trace.steps.len(), 4);
+
+        let push = &trace.steps[2];
+        assert_eq!(push.stack_before, vec![expected_hash.clone()]);
+        assert_eq!(push.stack_after, vec![expected_hash, b"wrong".to_vec()]);
+        assert_eq!(push.gas_cost, 10 + 5);
+        assert_eq!(push.gas_used, 1 + 42 + 15);
+
+        let failed = trace.steps.last().expect("failing step");
+        assert_eq!(failed.op, OpCode::CheckHashLock);
+        assert!(failed.stack_after.is_empty());
+        assert!(failed
+            .error
+            .as_deref()
+            .is_some_and(|err| err.contains("preimage mismatch")));
+
+        let untraced = default_engine().eval(&script, &ctx, &host);
+        assert!(matches!(untraced, Err(ScriptError::Evaluation(_))));
+    }
+
+    #[test]
+    fn decodes_consensus_encoding() {
+        let script = Script(vec![
+            OpCode::PushBytes(vec![1, 2, 3]),
+            OpCode::CheckMultiSig {
+                threshold: 1,
+                total: 1,
+            },
+            OpCode::CheckTimeLock,
+            OpCode::CheckRelativeTimeLock,
+            OpCode::CheckHashLock,
+            OpCode::CheckSig,
+            OpCode::Nop,
+            OpCode::PushWitness(2),
+            OpCode::CheckTimestampLock,
+            OpCode::CheckRelativeTimestampLock,
+            OpCode::CheckAssetHeld,
+        ]);
+        let encoded = consensus_codec::encode_script(&script);
+        assert_eq!(
+            consensus_codec::decode_script(&encoded).expect("decode"),
+            script
+        );
+
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert_eq!(
+            consensus_codec::decode_script(&trailing),
+            Err(consensus_codec::DecodeError::TrailingBytes(1))
+        );
+        assert_eq!(
+            consensus_codec::decode_script(&encoded[..encoded.len() - 1]),
+            Err(consensus_codec::DecodeError::UnexpectedEof)
+        );
+        assert_eq!(
+            consensus_codec::decode_script(&[1, 0, 0, 0, 99]),
+            Err(consensus_codec::DecodeError::UnknownOpcode(99))
+        );
+    }
+
     #[test]
     fn halts_when_out_of_gas() {
         let (tx, input) = sample_tx();
@@ -757,6 +1732,7 @@ mod tests {
             ResolvedInput {
                 output: tx.core.outputs[0].clone(),
                 created_height: Some(0),
+                created_time: None,
             },
         );
         let ctx = ScriptContext {
@@ -765,6 +1741,7 @@ mod tests {
             current_height: Some(0),
             chain_id: 0,
             script_hash,
+            witness: Vec::new(),
         };
 
         let engine = DeterministicScriptEngine::with_config(VmConfig {
@@ -775,4 +1752,428 @@ mod tests {
         let result = engine.eval(&script, &ctx, &host);
         assert!(matches!(result, Err(ScriptError::OutOfGas)));
     }
+
+    #[test]
+    fn charges_signature_costs_from_schedule_active_at_height() {
+        let registry = default_registry();
+        let scheme = registry
+            .get(&SignatureSchemeId::Dilithium2)
+            .expect("scheme should exist");
+        let (pk, sk) = scheme.keygen().expect("keygen should work");
+
+        let (tx, input) = sample_tx();
+        let script = Script(vec![
+            OpCode::PushBytes(pk.to_bytes().expect("pk to bytes")),
+            OpCode::PushWitness(0),
+            OpCode::CheckSig,
+        ]);
+        let script_hash = script_hash(&script);
+        let sighash = tx.sighash(
+            0,
+            &tx.core.outputs[0],
+            script_hash,
+            0,
+            SighashFlags::default(),
+        );
+        let signature = scheme
+            .sign(&sk, &sighash)
+            .expect("signing should work")
+            .to_bytes()
+            .expect("sig to bytes");
+
+        let mut repriced = GasSchedule::legacy();
+        repriced.witness_byte = 2;
+        repriced
+            .sig_verify
+            .insert(SignatureSchemeId::Dilithium2, 1_234);
+        let engine = DeterministicScriptEngine::with_config(VmConfig {
+            gas_schedules: GasScheduleTable::default().with_activation(10, repriced),
+            ..VmConfig::default()
+        });
+
+        let gas_at = |height: u64| {
+            let host = StaticHost::new(Some(height)).with_input(
+                input.clone(),
+                ResolvedInput {
+                    output: tx.core.outputs[0].clone(),
+                    created_height: Some(0),
+                    created_time: None,
+                },
+            );
+            let ctx = ScriptContext {
+                tx: tx.clone(),
+                input_index: 0,
+                current_height: Some(height),
+                chain_id: 0,
+                script_hash,
+                witness: vec![signature.clone()],
+            };
+            engine
+                .eval(&script, &ctx, &host)
+                .expect("signature should verify")
+                .gas_consumed
+        };
+
+        let legacy = gas_at(9);
+        assert_eq!(
+            legacy,
+            GasSchedule::legacy().op_cost(&script.0[0]) + 10 + signature.len() as u64 + 5_000
+        );
+        assert_eq!(gas_at(10), legacy + signature.len() as u64 + 1_234);
+    }
+
+    #[test]
+    fn check_multisig_matches_keys_in_push_order_and_charges_skipped_keys() {
+        let registry = default_registry();
+        let keypair = |scheme_id| {
+            registry
+                .get(&scheme_id)
+                .expect("scheme should exist")
+                .keygen()
+                .expect("keygen should work")
+        };
+        let dilithium: Vec<_> = (0..3)
+            .map(|_| keypair(SignatureSchemeId::Dilithium2))
+            .collect();
+        let falcon = keypair(SignatureSchemeId::Falcon512);
+
+        let (tx, input) = sample_tx();
+        let host = StaticHost::new(Some(1)).with_input(
+            input,
+            ResolvedInput {
+                output: tx.core.outputs[0].clone(),
+                created_height: Some(0),
+                created_time: None,
+            },
+        );
+        let multisig_script = |keys: &[&PublicKey]| {
+            let mut ops: Vec<_> = keys
+                .iter()
+                .map(|pk| OpCode::PushBytes(pk.to_bytes().expect("pk to bytes")))
+                .collect();
+            ops.extend([
+                OpCode::PushWitness(0),
+                OpCode::PushWitness(1),
+                OpCode::CheckMultiSig {
+                    threshold: 2,
+                    total: keys.len() as u8,
+                },
+            ]);
+            Script(ops)
+        };
+        // Returns the gas used, and the gas the script costs with no key skipped.
+        let eval = |script: &Script, signers: &[&PrivateKey]| {
+            let script_hash = script_hash(script);
+            let sighash = tx.sighash(
+                0,
+                &tx.core.outputs[0],
+                script_hash,
+                0,
+                SighashFlags::default(),
+            );
+            let witness: Vec<_> = signers
+                .iter()
+                .map(|sk| {
+                    registry
+                        .get(&sk.scheme)
+                        .expect("scheme should exist")
+                        .sign(sk, &sighash)
+                        .expect("signing should work")
+                        .to_bytes()
+                        .expect("sig to bytes")
+                })
+                .collect();
+            let schedule = GasSchedule::legacy();
+            let unskipped = script.0.iter().map(|op| schedule.op_cost(op)).sum::<u64>()
+                + witness.iter().map(|item| item.len() as u64).sum::<u64>();
+            let ctx = ScriptContext {
+                tx: tx.clone(),
+                input_index: 0,
+                current_height: Some(1),
+                chain_id: 0,
+                script_hash,
+                witness,
+            };
+            default_engine()
+                .eval(script, &ctx, &host)
+                .map(|result| (result.gas_consumed, unskipped))
+        };
+
+        let script = multisig_script(&[&dilithium[0].0, &dilithium[1].0, &dilithium[2].0]);
+
+        // Adjacent keys match without retries.
+        let (used, unskipped) =
+            eval(&script, &[&dilithium[0].1, &dilithium[1].1]).expect("keys 0 and 1 sign");
+        assert_eq!(used, unskipped);
+
+        // Key 1 is tried against the second signature, fails and is charged one more
+        // `sig_base`. The old positional pairing would have checked it against key 1 only.
+        let (used, unskipped) =
+            eval(&script, &[&dilithium[0].1, &dilithium[2].1]).expect("keys 0 and 2 sign");
+        assert_eq!(used, unskipped + GasSchedule::legacy().sig_base);
+
+        // Signatures must follow key order, and one key cannot satisfy two signatures.
+        assert!(eval(&script, &[&dilithium[2].1, &dilithium[0].1]).is_err());
+        assert!(eval(&script, &[&dilithium[1].1, &dilithium[1].1]).is_err());
+
+        // A key of another scheme is skipped without a verification attempt or charge.
+        let mixed = multisig_script(&[&dilithium[0].0, &falcon.0, &dilithium[2].0]);
+        let (used, unskipped) =
+            eval(&mixed, &[&dilithium[0].1, &dilithium[2].1]).expect("keys 0 and 2 sign");
+        assert_eq!(used, unskipped);
+    }
+
+    #[test]
+    fn chain_engine_prices_schemes_apart_after_activation() {
+        let registry = default_registry();
+        let (tx, input) = sample_tx();
+        // Gas of a single-key spend beyond its pushes and witness bytes, which differ in
+        // size between schemes.
+        let signature_gas = |scheme_id, height| {
+            let scheme = registry.get(&scheme_id).expect("scheme should exist");
+            let (pk, sk) = scheme.keygen().expect("keygen should work");
+            let script = Script(vec![
+                OpCode::PushBytes(pk.to_bytes().expect("pk to bytes")),
+                OpCode::PushWitness(0),
+                OpCode::CheckSig,
+            ]);
+            let script_hash = script_hash(&script);
+            let sighash = tx.sighash(
+                0,
+                &tx.core.outputs[0],
+                script_hash,
+                0,
+                SighashFlags::default(),
+            );
+            let signature = scheme
+                .sign(&sk, &sighash)
+                .expect("signing should work")
+                .to_bytes()
+                .expect("sig to bytes");
+            let host = StaticHost::new(Some(height)).with_input(
+                input.clone(),
+                ResolvedInput {
+                    output: tx.core.outputs[0].clone(),
+                    created_height: Some(0),
+                    created_time: None,
+                },
+            );
+            let schedule = GasSchedule::legacy();
+            let data_gas = schedule.op_cost(&script.0[0])
+                + schedule.op_cost(&script.0[1])
+                + signature.len() as u64;
+            let ctx = ScriptContext {
+                tx: tx.clone(),
+                input_index: 0,
+                current_height: Some(height),
+                chain_id: 0,
+                script_hash,
+                witness: vec![signature],
+            };
+            DeterministicScriptEngine::for_chain(0)
+                .eval(&script, &ctx, &host)
+                .expect("signature should verify")
+                .gas_consumed
+                - data_gas
+        };
+
+        let activation = ChainActivations::for_chain(0).per_scheme_sig_verify;
+        let before = activation - 1;
+        assert_eq!(signature_gas(SignatureSchemeId::Dilithium2, before), 5_000);
+        assert_eq!(signature_gas(SignatureSchemeId::Falcon512, before), 5_000);
+
+        let after = activation;
+        assert_eq!(signature_gas(SignatureSchemeId::Dilithium2, after), 5_000);
+        assert_eq!(signature_gas(SignatureSchemeId::Falcon512, after), 3_500);
+    }
+
+    #[test]
+    fn sighash_flags_travel_with_witness_signature() {
+        let registry = default_registry();
+        let scheme = registry
+            .get(&SignatureSchemeId::Dilithium2)
+            .expect("scheme should exist");
+        let (pk, sk) = scheme.keygen().expect("keygen should work");
+
+        let (offer, input) = sample_tx();
+        let script = Script(vec![
+            OpCode::PushBytes(pk.to_bytes().expect("pk to bytes")),
+            OpCode::PushWitness(0),
+            OpCode::CheckSig,
+        ]);
+        let script_hash = script_hash(&script);
+        let prev_output = offer.core.outputs[0].clone();
+        let sign = |flags: SighashFlags| {
+            let sighash = offer.sighash(0, &prev_output, script_hash, 0, flags);
+            let signature = scheme.sign(&sk, &sighash).expect("signing should work");
+            encode_signature_item(&signature, flags).expect("sig to bytes")
+        };
+
+        // A buyer completes the offer by adding their own input and change output.
+        let mut completed = offer.clone();
+        completed.core.inputs.push(TransactionInput {
+            tx_id: [4u8; 32],
+            index: 1,
+        });
+        completed.core.outputs.push(prev_output.clone());
+
+        let host = StaticHost::new(Some(1)).with_input(
+            input,
+            ResolvedInput {
+                output: prev_output.clone(),
+                created_height: Some(0),
+                created_time: None,
+            },
+        );
+        let eval = |tx: &Transaction, item: Vec<u8>| {
+            let ctx = ScriptContext {
+                tx: tx.clone(),
+                input_index: 0,
+                current_height: Some(1),
+                chain_id: 0,
+                script_hash,
+                witness: vec![item],
+            };
+            default_engine().eval(&script, &ctx, &host)
+        };
+
+        let all = sign(SighashFlags::ALL);
+        let single_acp = sign(SighashFlags::SINGLE | SighashFlags::ANYONECANPAY);
+        assert_eq!(single_acp.len(), all.len() + 1);
+
+        assert!(eval(&offer, all.clone()).is_ok());
+        assert!(eval(&completed, all.clone()).is_err());
+        assert!(eval(&offer, single_acp.clone()).is_ok());
+        assert!(eval(&completed, single_acp.clone()).is_ok());
+
+        let mut changed_price = completed.clone();
+        changed_price.core.outputs[0].assets[0].amount = 1;
+        assert!(eval(&changed_price, single_acp.clone()).is_err());
+
+        // ALL has a single encoding, and unknown modes are rejected.
+        let mut explicit_all = all.clone();
+        explicit_all.push(0x00);
+        assert!(eval(&offer, explicit_all).is_err());
+        let mut unknown = all;
+        unknown.push(0x05);
+        assert!(eval(&offer, unknown).is_err());
+    }
+
+    #[test]
+    fn checks_data_signature_over_pushed_message() {
+        let registry = default_registry();
+        let scheme = registry
+            .get(&SignatureSchemeId::Falcon512)
+            .expect("scheme should exist");
+        let (oracle_pk, oracle_sk) = scheme.keygen().expect("keygen should work");
+        let outcome = b"match 42: home team wins".to_vec();
+        let attestation = scheme
+            .sign(&oracle_sk, &outcome)
+            .expect("signing should work")
+            .to_bytes()
+            .expect("sig to bytes");
+
+        let script = Script(vec![
+            OpCode::PushBytes(oracle_pk.to_bytes().expect("pk to bytes")),
+            OpCode::PushBytes(outcome),
+            OpCode::PushWitness(0),
+            OpCode::CheckDataSig,
+        ]);
+        let (tx, _) = sample_tx();
+        let host = StaticHost::new(Some(1));
+        let eval = |witness: Vec<u8>| {
+            let ctx = ScriptContext {
+                tx: tx.clone(),
+                input_index: 0,
+                current_height: Some(1),
+                chain_id: 0,
+                script_hash: script_hash(&script),
+                witness: vec![witness],
+            };
+            default_engine().eval(&script, &ctx, &host)
+        };
+
+        assert!(eval(attestation).is_ok());
+
+        let other_outcome = scheme
+            .sign(&oracle_sk, b"match 42: away team wins")
+            .expect("signing should work")
+            .to_bytes()
+            .expect("sig to bytes");
+        assert!(matches!(
+            eval(other_outcome),
+            Err(ScriptError::Evaluation(message)) if message.contains("data signature")
+        ));
+    }
+
+    #[test]
+    fn digest_opcodes_push_standard_hashes() {
+        let (tx, _) = sample_tx();
+        let script = Script(vec![
+            OpCode::PushBytes(b"abc".to_vec()),
+            OpCode::Sha256,
+            OpCode::PushBytes(b"abc".to_vec()),
+            OpCode::Sha3_256,
+            OpCode::PushBytes(b"abc".to_vec()),
+            OpCode::Blake3,
+        ]);
+        let ctx = ScriptContext {
+            tx,
+            input_index: 0,
+            current_height: Some(1),
+            chain_id: 0,
+            script_hash: script_hash(&script),
+            witness: Vec::new(),
+        };
+
+        let (result, trace) = default_engine().eval_traced(&script, &ctx, &StaticHost::new(None));
+        result.expect("digests should succeed");
+        let stack = &trace.steps.last().expect("steps recorded").stack_after;
+        let hex = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>()
+        };
+        assert_eq!(
+            hex(&stack[0]),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            hex(&stack[1]),
+            "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532"
+        );
+        assert_eq!(stack[2], hash(b"abc").as_bytes().to_vec());
+        // 250 fixed per digest, plus one SHA-256 and one SHA3-256 block.
+        assert_eq!(trace.steps[1].gas_cost, 250 + 12);
+        assert_eq!(trace.steps[3].gas_cost, 250 + 10);
+        assert_eq!(trace.steps[5].gas_cost, 250);
+    }
+
+    #[test]
+    fn hashlock_variants_use_their_algorithm() {
+        let (tx, _) = sample_tx();
+        let preimage = b"cross-chain swap".to_vec();
+        let ctx = ScriptContext {
+            tx,
+            input_index: 0,
+            current_height: Some(1),
+            chain_id: 0,
+            script_hash: [0u8; 32],
+            witness: Vec::new(),
+        };
+        let eval = |algorithm: HashAlgorithm, lock: OpCode| {
+            let script = Script(vec![
+                OpCode::PushBytes(algorithm.digest(&preimage).to_vec()),
+                OpCode::PushBytes(preimage.clone()),
+                lock,
+            ]);
+            default_engine().eval(&script, &ctx, &StaticHost::new(None))
+        };
+
+        assert!(eval(HashAlgorithm::Sha256, OpCode::CheckHashLockSha256).is_ok());
+        assert!(eval(HashAlgorithm::Sha3_256, OpCode::CheckHashLockSha3_256).is_ok());
+        assert!(eval(HashAlgorithm::Sha256, OpCode::CheckHashLock).is_err());
+        assert!(eval(HashAlgorithm::Blake3, OpCode::CheckHashLockSha3_256).is_err());
+    }
 }
@@ -0,0 +1,757 @@
+//! Standard owner script templates.
+//!
+//! Builders return the script together with the `owner_script_hash` an output must carry
+//! to be spendable by it, so wallets can recognize their own outputs from a key alone.
+//! Spender data (signatures, revealed keys, preimages) is never part of the script: it is
+//! supplied as witness stack items and read with `PushWitness`. Each template documents
+//! the witness items it expects, in order.
+
+use crate::tree::ScriptTree;
+use crate::{HashAlgorithm, OpCode, Script};
+use qcoin_crypto::{CryptoError, PublicKey};
+use qcoin_types::{AssetId, Hash256};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TemplateError {
+    #[error("invalid public key: {0}")]
+    InvalidPublicKey(#[from] CryptoError),
+    #[error("invalid multisig threshold {threshold} of {total} keys")]
+    InvalidThreshold { threshold: usize, total: usize },
+    #[error("non-standard scripts have no template to build")]
+    NonStandard,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Timelock {
+    /// Spendable once the chain reaches this height.
+    Absolute(u64),
+    /// Spendable this many blocks after the output was created.
+    Relative(u64),
+    /// Spendable once the chain's median-time-past reaches this unix time.
+    AbsoluteTime(u64),
+    /// Spendable this many seconds of median-time-past after the output was created.
+    RelativeTime(u64),
+}
+
+impl Timelock {
+    fn value(self) -> u64 {
+        match self {
+            Timelock::Absolute(value)
+            | Timelock::Relative(value)
+            | Timelock::AbsoluteTime(value)
+            | Timelock::RelativeTime(value) => value,
+        }
+    }
+
+    fn check_op(self) -> OpCode {
+        match self {
+            Timelock::Absolute(_) => OpCode::CheckTimeLock,
+            Timelock::Relative(_) => OpCode::CheckRelativeTimeLock,
+            Timelock::AbsoluteTime(_) => OpCode::CheckTimestampLock,
+            Timelock::RelativeTime(_) => OpCode::CheckRelativeTimestampLock,
+        }
+    }
+
+    fn from_check_op(op: &OpCode, value: u64) -> Option<Self> {
+        Some(match op {
+            OpCode::CheckTimeLock => Timelock::Absolute(value),
+            OpCode::CheckRelativeTimeLock => Timelock::Relative(value),
+            OpCode::CheckTimestampLock => Timelock::AbsoluteTime(value),
+            OpCode::CheckRelativeTimestampLock => Timelock::RelativeTime(value),
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Template {
+    /// Witness: `[signature]`.
+    SingleKey {
+        public_key: PublicKey,
+    },
+    /// Witness: `[encoded public key, signature]`; the key must hash to `key_hash`.
+    PayToKeyHash {
+        key_hash: Hash256,
+    },
+    /// Witness: `threshold` signatures, in the same order as their keys.
+    MultiSig {
+        threshold: u8,
+        public_keys: Vec<PublicKey>,
+    },
+    /// Witness: `[signature]`, accepted only once the timelock is satisfied.
+    Timelocked {
+        lock: Timelock,
+        public_key: PublicKey,
+    },
+    /// Witness: `[preimage, signature]`; the preimage must hash to `hash` under `algorithm`.
+    Hashlocked {
+        algorithm: HashAlgorithm,
+        hash: Hash256,
+        public_key: PublicKey,
+    },
+    /// Witness: `[signature]`, accepted only if some input of the spending transaction
+    /// holds at least `min_amount` of `asset_id` in one output.
+    AssetGated {
+        asset_id: AssetId,
+        min_amount: u128,
+        public_key: PublicKey,
+    },
+    /// Witness: `[oracle signature over outcome, signature]`. The owner's signature binds
+    /// the spend to the transaction, since the oracle's signs only the outcome.
+    OracleAttested {
+        oracle_key: PublicKey,
+        outcome: Vec<u8>,
+        public_key: PublicKey,
+    },
+    NonStandard,
+}
+
+/// blake3 of the scheme-tagged key encoding, as committed by [`Template::PayToKeyHash`].
+pub fn key_hash(public_key: &PublicKey) -> Result<Hash256, TemplateError> {
+    Ok(*blake3::hash(&public_key.to_bytes()?).as_bytes())
+}
+
+pub fn single_key(public_key: &PublicKey) -> Result<(Script, Hash256), TemplateError> {
+    Template::SingleKey {
+        public_key: public_key.clone(),
+    }
+    .build()
+}
+
+pub fn pay_to_key_hash(key_hash: Hash256) -> (Script, Hash256) {
+    Template::PayToKeyHash { key_hash }
+        .build()
+        .expect("key-hash template has no keys to encode")
+}
+
+pub fn multisig(
+    threshold: u8,
+    public_keys: &[PublicKey],
+) -> Result<(Script, Hash256), TemplateError> {
+    Template::MultiSig {
+        threshold,
+        public_keys: public_keys.to_vec(),
+    }
+    .build()
+}
+
+pub fn timelocked(
+    lock: Timelock,
+    public_key: &PublicKey,
+) -> Result<(Script, Hash256), TemplateError> {
+    Template::Timelocked {
+        lock,
+        public_key: public_key.clone(),
+    }
+    .build()
+}
+
+pub fn hashlocked(
+    algorithm: HashAlgorithm,
+    hash: Hash256,
+    public_key: &PublicKey,
+) -> Result<(Script, Hash256), TemplateError> {
+    Template::Hashlocked {
+        algorithm,
+        hash,
+        public_key: public_key.clone(),
+    }
+    .build()
+}
+
+pub fn asset_gated(
+    asset_id: AssetId,
+    min_amount: u128,
+    public_key: &PublicKey,
+) -> Result<(Script, Hash256), TemplateError> {
+    Template::AssetGated {
+        asset_id,
+        min_amount,
+        public_key: public_key.clone(),
+    }
+    .build()
+}
+
+pub fn oracle_attested(
+    oracle_key: &PublicKey,
+    outcome: Vec<u8>,
+    public_key: &PublicKey,
+) -> Result<(Script, Hash256), TemplateError> {
+    Template::OracleAttested {
+        oracle_key: oracle_key.clone(),
+        outcome,
+        public_key: public_key.clone(),
+    }
+    .build()
+}
+
+/// Hash time-locked contract as a two-leaf script tree: leaf 0 is a [`hashlocked`] claim
+/// by `recipient`, leaf 1 a [`timelocked`] refund to `refund_key`. Outputs commit to
+/// [`ScriptTree::root`]; a spend reveals only the leaf it uses. Swaps with other chains
+/// use the counterparty chain's hash function, e.g. [`HashAlgorithm::Sha256`].
+pub fn htlc(
+    algorithm: HashAlgorithm,
+    hash: Hash256,
+    recipient: &PublicKey,
+    refund_after: Timelock,
+    refund_key: &PublicKey,
+) -> Result<ScriptTree, TemplateError> {
+    let (claim, _) = hashlocked(algorithm, hash, recipient)?;
+    let (refund, _) = timelocked(refund_after, refund_key)?;
+    Ok(ScriptTree::new(vec![claim, refund]).expect("two leaves fit in a script tree"))
+}
+
+fn check_key_ops(public_key: &PublicKey) -> Result<Vec<OpCode>, TemplateError> {
+    Ok(vec![
+        OpCode::PushBytes(public_key.to_bytes()?),
+        OpCode::PushWitness(0),
+        OpCode::CheckSig,
+    ])
+}
+
+impl Template {
+    /// Builds the script for this template and its `owner_script_hash`.
+    pub fn build(&self) -> Result<(Script, Hash256), TemplateError> {
+        let ops = match self {
+            Template::SingleKey { public_key } => check_key_ops(public_key)?,
+            Template::PayToKeyHash { key_hash } => vec![
+                OpCode::PushBytes(key_hash.to_vec()),
+                OpCode::PushWitness(0),
+                OpCode::CheckHashLock,
+                OpCode::PushWitness(0),
+                OpCode::PushWitness(1),
+                OpCode::CheckSig,
+            ],
+            Template::MultiSig {
+                threshold,
+                public_keys,
+            } => {
+                let total = public_keys.len();
+                if *threshold == 0 || usize::from(*threshold) > total || total > u8::MAX as usize {
+                    return Err(TemplateError::InvalidThreshold {
+                        threshold: usize::from(*threshold),
+                        total,
+                    });
+                }
+
+                let mut ops = public_keys
+                    .iter()
+                    .map(|key| key.to_bytes().map(OpCode::PushBytes))
+                    .collect::<Result<Vec<_>, _>>()?;
+                ops.extend((0..*threshold).map(OpCode::PushWitness));
+                ops.push(OpCode::CheckMultiSig {
+                    threshold: *threshold,
+                    total: total as u8,
+                });
+                ops
+            }
+            Template::Timelocked { lock, public_key } => {
+                let mut ops = vec![
+                    OpCode::PushBytes(lock.value().to_le_bytes().to_vec()),
+                    lock.check_op(),
+                ];
+                ops.extend(check_key_ops(public_key)?);
+                ops
+            }
+            Template::Hashlocked {
+                algorithm,
+                hash,
+                public_key,
+            } => vec![
+                OpCode::PushBytes(hash.to_vec()),
+                OpCode::PushWitness(0),
+                hashlock_op(*algorithm),
+                OpCode::PushBytes(public_key.to_bytes()?),
+                OpCode::PushWitness(1),
+                OpCode::CheckSig,
+            ],
+            Template::AssetGated {
+                asset_id,
+                min_amount,
+                public_key,
+            } => {
+                let mut ops = vec![
+                    OpCode::PushBytes(asset_id.0.to_vec()),
+                    OpCode::PushBytes(min_amount.to_le_bytes().to_vec()),
+                    OpCode::CheckAssetHeld,
+                ];
+                ops.extend(check_key_ops(public_key)?);
+                ops
+            }
+            Template::OracleAttested {
+                oracle_key,
+                outcome,
+                public_key,
+            } => vec![
+                OpCode::PushBytes(oracle_key.to_bytes()?),
+                OpCode::PushBytes(outcome.clone()),
+                OpCode::PushWitness(0),
+                OpCode::CheckDataSig,
+                OpCode::PushBytes(public_key.to_bytes()?),
+                OpCode::PushWitness(1),
+                OpCode::CheckSig,
+            ],
+            Template::NonStandard => return Err(TemplateError::NonStandard),
+        };
+
+        let script = Script(ops);
+        let script_hash = script.script_hash();
+        Ok((script, script_hash))
+    }
+}
+
+fn hashlock_op(algorithm: HashAlgorithm) -> OpCode {
+    match algorithm {
+        HashAlgorithm::Blake3 => OpCode::CheckHashLock,
+        HashAlgorithm::Sha256 => OpCode::CheckHashLockSha256,
+        HashAlgorithm::Sha3_256 => OpCode::CheckHashLockSha3_256,
+    }
+}
+
+fn parse_key(bytes: &[u8]) -> Option<PublicKey> {
+    PublicKey::from_bytes(bytes).ok()
+}
+
+fn parse_hash(bytes: &[u8]) -> Option<Hash256> {
+    bytes.try_into().ok()
+}
+
+fn parse_u64(bytes: &[u8]) -> Option<u64> {
+    bytes.try_into().ok().map(u64::from_le_bytes)
+}
+
+fn parse_u128(bytes: &[u8]) -> Option<u128> {
+    bytes.try_into().ok().map(u128::from_le_bytes)
+}
+
+/// Classifies `script` as one of the standard templates, or [`Template::NonStandard`].
+///
+/// A script matches only if it is exactly what the corresponding builder produces, so
+/// `classify(&template.build()?.0) == template` for every standard template.
+pub fn classify(script: &Script) -> Template {
+    classify_ops(&script.0).unwrap_or(Template::NonStandard)
+}
+
+fn classify_ops(ops: &[OpCode]) -> Option<Template> {
+    use OpCode::*;
+
+    let template = match ops {
+        [PushBytes(key), PushWitness(0), CheckSig] => Template::SingleKey {
+            public_key: parse_key(key)?,
+        },
+        [PushBytes(hash), PushWitness(0), CheckHashLock, PushWitness(0), PushWitness(1), CheckSig] => {
+            Template::PayToKeyHash {
+                key_hash: parse_hash(hash)?,
+            }
+        }
+        [PushBytes(value), lock @ (CheckTimeLock
+        | CheckRelativeTimeLock
+        | CheckTimestampLock
+        | CheckRelativeTimestampLock), PushBytes(key), PushWitness(0), CheckSig] => {
+            Template::Timelocked {
+                lock: Timelock::from_check_op(lock, parse_u64(value)?)?,
+                public_key: parse_key(key)?,
+            }
+        }
+        [PushBytes(hash), PushWitness(0), lock @ (CheckHashLock | CheckHashLockSha256 | CheckHashLockSha3_256), PushBytes(key), PushWitness(1), CheckSig] => {
+            Template::Hashlocked {
+                algorithm: lock.hash_algorithm()?,
+                hash: parse_hash(hash)?,
+                public_key: parse_key(key)?,
+            }
+        }
+        [PushBytes(asset_id), PushBytes(amount), CheckAssetHeld, PushBytes(key), PushWitness(0), CheckSig] => {
+            Template::AssetGated {
+                asset_id: AssetId(parse_hash(asset_id)?),
+                min_amount: parse_u128(amount)?,
+                public_key: parse_key(key)?,
+            }
+        }
+        [PushBytes(oracle_key), PushBytes(outcome), PushWitness(0), CheckDataSig, PushBytes(key), PushWitness(1), CheckSig] => {
+            Template::OracleAttested {
+                oracle_key: parse_key(oracle_key)?,
+                outcome: outcome.clone(),
+                public_key: parse_key(key)?,
+            }
+        }
+        [rest @ .., CheckMultiSig { threshold, total }] => {
+            let (threshold, total) = (usize::from(*threshold), usize::from(*total));
+            if threshold == 0 || threshold > total || rest.len() != total + threshold {
+                return None;
+            }
+
+            let (keys, witness) = rest.split_at(total);
+            let public_keys = keys
+                .iter()
+                .map(|op| match op {
+                    PushBytes(key) => parse_key(key),
+                    _ => None,
+                })
+                .collect::<Option<Vec<_>>>()?;
+            let in_order = witness
+                .iter()
+                .enumerate()
+                .all(|(idx, op)| *op == PushWitness(idx as u8));
+            if !in_order {
+                return None;
+            }
+
+            Template::MultiSig {
+                threshold: threshold as u8,
+                public_keys,
+            }
+        }
+        _ => return None,
+    };
+
+    Some(template)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        DeterministicScriptEngine, ResolvedInput, ScriptContext, ScriptEngine, ScriptError,
+        ScriptHost,
+    };
+    use qcoin_crypto::{default_registry, PqSchemeRegistry, PrivateKey, SignatureSchemeId};
+    use qcoin_types::{
+        AssetAmount, AssetId, Output, SighashFlags, Transaction, TransactionCore, TransactionInput,
+        TransactionKind, TransactionWitness,
+    };
+
+    struct SingleInputHost {
+        height: u64,
+        resolved: ResolvedInput,
+    }
+
+    impl ScriptHost for SingleInputHost {
+        fn current_height(&self) -> Option<u64> {
+            Some(self.height)
+        }
+
+        fn current_time(&self) -> Option<u64> {
+            None
+        }
+
+        fn input_utxo(&self, _input: &TransactionInput) -> Option<ResolvedInput> {
+            Some(self.resolved.clone())
+        }
+    }
+
+    fn keypair() -> (PublicKey, PrivateKey) {
+        default_registry()
+            .get(&SignatureSchemeId::Dilithium2)
+            .expect("scheme should exist")
+            .keygen()
+            .expect("keygen should work")
+    }
+
+    fn spend(owner_script_hash: Hash256) -> (Transaction, Output) {
+        let prev_output = Output {
+            owner_script_hash,
+            assets: vec![AssetAmount {
+                asset_id: AssetId([3u8; 32]),
+                amount: 10,
+            }],
+            metadata_hash: None,
+        };
+        let tx = Transaction {
+            core: TransactionCore {
+                kind: TransactionKind::Transfer,
+                inputs: vec![TransactionInput {
+                    tx_id: [1u8; 32],
+                    index: 0,
+                }],
+                outputs: vec![prev_output.clone()],
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness::default(),
+        };
+        (tx, prev_output)
+    }
+
+    fn sign(sk: &PrivateKey, tx: &Transaction, prev_output: &Output) -> Vec<u8> {
+        let sighash = tx.sighash(
+            0,
+            prev_output,
+            prev_output.owner_script_hash,
+            0,
+            SighashFlags::default(),
+        );
+        default_registry()
+            .get(&sk.scheme)
+            .expect("scheme should exist")
+            .sign(sk, &sighash)
+            .expect("signing should work")
+            .to_bytes()
+            .expect("signature encodes")
+    }
+
+    fn eval(
+        script: &Script,
+        tx: &Transaction,
+        prev_output: &Output,
+        height: u64,
+        witness: Vec<Vec<u8>>,
+    ) -> Result<(), ScriptError> {
+        let host = SingleInputHost {
+            height,
+            resolved: ResolvedInput {
+                output: prev_output.clone(),
+                created_height: Some(0),
+                created_time: Some(0),
+            },
+        };
+        let ctx = ScriptContext {
+            tx: tx.clone(),
+            input_index: 0,
+            current_height: Some(height),
+            chain_id: 0,
+            script_hash: prev_output.owner_script_hash,
+            witness,
+        };
+        DeterministicScriptEngine::default()
+            .eval(script, &ctx, &host)
+            .map(|_| ())
+    }
+
+    #[test]
+    fn builders_round_trip_through_classify() {
+        let (pk_a, _) = keypair();
+        let (pk_b, _) = keypair();
+        let templates = vec![
+            Template::SingleKey {
+                public_key: pk_a.clone(),
+            },
+            Template::PayToKeyHash {
+                key_hash: key_hash(&pk_a).unwrap(),
+            },
+            Template::MultiSig {
+                threshold: 1,
+                public_keys: vec![pk_a.clone(), pk_b.clone()],
+            },
+            Template::Timelocked {
+                lock: Timelock::Absolute(100),
+                public_key: pk_b.clone(),
+            },
+            Template::Timelocked {
+                lock: Timelock::Relative(6),
+                public_key: pk_b.clone(),
+            },
+            Template::Timelocked {
+                lock: Timelock::AbsoluteTime(1_700_000_000),
+                public_key: pk_b.clone(),
+            },
+            Template::Timelocked {
+                lock: Timelock::RelativeTime(86_400),
+                public_key: pk_b.clone(),
+            },
+            Template::Hashlocked {
+                algorithm: HashAlgorithm::Blake3,
+                hash: [9u8; 32],
+                public_key: pk_a.clone(),
+            },
+            Template::Hashlocked {
+                algorithm: HashAlgorithm::Sha256,
+                hash: [9u8; 32],
+                public_key: pk_a.clone(),
+            },
+            Template::Hashlocked {
+                algorithm: HashAlgorithm::Sha3_256,
+                hash: [9u8; 32],
+                public_key: pk_a.clone(),
+            },
+            Template::AssetGated {
+                asset_id: AssetId([4u8; 32]),
+                min_amount: 1,
+                public_key: pk_b.clone(),
+            },
+            Template::OracleAttested {
+                oracle_key: pk_a,
+                outcome: b"red wins".to_vec(),
+                public_key: pk_b,
+            },
+        ];
+
+        for template in templates {
+            let (script, script_hash) = template.build().expect("template builds");
+            assert_eq!(script_hash, script.script_hash());
+            assert_eq!(classify(&script), template);
+        }
+    }
+
+    #[test]
+    fn classifies_other_scripts_as_non_standard() {
+        let (pk, _) = keypair();
+        let (mut script, _) = single_key(&pk).unwrap();
+        script.0.push(OpCode::Nop);
+        assert_eq!(classify(&script), Template::NonStandard);
+
+        let bad_key = Script(vec![
+            OpCode::PushBytes(vec![1, 2, 3]),
+            OpCode::PushWitness(0),
+            OpCode::CheckSig,
+        ]);
+        assert_eq!(classify(&bad_key), Template::NonStandard);
+
+        let reordered_witness = Script(vec![
+            OpCode::PushBytes(pk.to_bytes().unwrap()),
+            OpCode::PushWitness(1),
+            OpCode::PushWitness(0),
+            OpCode::CheckMultiSig {
+                threshold: 2,
+                total: 1,
+            },
+        ]);
+        assert_eq!(classify(&reordered_witness), Template::NonStandard);
+        assert_eq!(classify(&Script(Vec::new())), Template::NonStandard);
+
+        assert!(matches!(
+            multisig(3, &[pk.clone(), pk]),
+            Err(TemplateError::InvalidThreshold {
+                threshold: 3,
+                total: 2
+            })
+        ));
+    }
+
+    #[test]
+    fn single_key_and_key_hash_spend_with_witness_signature() {
+        let (pk, sk) = keypair();
+
+        let (script, script_hash) = single_key(&pk).unwrap();
+        let (tx, prev_output) = spend(script_hash);
+        let signature = sign(&sk, &tx, &prev_output);
+        assert!(eval(&script, &tx, &prev_output, 1, vec![signature.clone()]).is_ok());
+        assert!(eval(&script, &tx, &prev_output, 1, Vec::new()).is_err());
+
+        let (script, script_hash) = pay_to_key_hash(key_hash(&pk).unwrap());
+        let (tx, prev_output) = spend(script_hash);
+        let signature = sign(&sk, &tx, &prev_output);
+        let encoded_key = pk.to_bytes().unwrap();
+        assert!(eval(
+            &script,
+            &tx,
+            &prev_output,
+            1,
+            vec![encoded_key, signature.clone()]
+        )
+        .is_ok());
+
+        let (other_pk, _) = keypair();
+        let wrong_key = other_pk.to_bytes().unwrap();
+        assert!(eval(&script, &tx, &prev_output, 1, vec![wrong_key, signature]).is_err());
+    }
+
+    #[test]
+    fn multisig_accepts_any_threshold_subset_in_key_order() {
+        let keys: Vec<_> = (0..3).map(|_| keypair()).collect();
+        let public_keys: Vec<_> = keys.iter().map(|(pk, _)| pk.clone()).collect();
+        let (script, script_hash) = multisig(2, &public_keys).unwrap();
+        let (tx, prev_output) = spend(script_hash);
+        let sigs: Vec<_> = keys
+            .iter()
+            .map(|(_, sk)| sign(sk, &tx, &prev_output))
+            .collect();
+
+        for (first, second) in [(0, 1), (0, 2), (1, 2)] {
+            let witness = vec![sigs[first].clone(), sigs[second].clone()];
+            assert!(eval(&script, &tx, &prev_output, 1, witness).is_ok());
+        }
+
+        let out_of_order = vec![sigs[2].clone(), sigs[0].clone()];
+        assert!(eval(&script, &tx, &prev_output, 1, out_of_order).is_err());
+        let duplicate = vec![sigs[1].clone(), sigs[1].clone()];
+        assert!(eval(&script, &tx, &prev_output, 1, duplicate).is_err());
+    }
+
+    #[test]
+    fn timelocked_and_hashlocked_enforce_their_conditions() {
+        let (pk, sk) = keypair();
+
+        let (script, script_hash) = timelocked(Timelock::Absolute(10), &pk).unwrap();
+        let (tx, prev_output) = spend(script_hash);
+        let signature = sign(&sk, &tx, &prev_output);
+        assert!(eval(&script, &tx, &prev_output, 9, vec![signature.clone()]).is_err());
+        assert!(eval(&script, &tx, &prev_output, 10, vec![signature]).is_ok());
+
+        let preimage = b"swap secret".to_vec();
+        for algorithm in [
+            HashAlgorithm::Blake3,
+            HashAlgorithm::Sha256,
+            HashAlgorithm::Sha3_256,
+        ] {
+            let hash = algorithm.digest(&preimage);
+            let (script, script_hash) = hashlocked(algorithm, hash, &pk).unwrap();
+            let (tx, prev_output) = spend(script_hash);
+            let signature = sign(&sk, &tx, &prev_output);
+            assert!(eval(
+                &script,
+                &tx,
+                &prev_output,
+                1,
+                vec![preimage.clone(), signature.clone()]
+            )
+            .is_ok());
+            assert!(eval(
+                &script,
+                &tx,
+                &prev_output,
+                1,
+                vec![b"guess".to_vec(), signature]
+            )
+            .is_err());
+        }
+    }
+
+    #[test]
+    fn asset_gated_and_oracle_attested_enforce_their_conditions() {
+        let (pk, sk) = keypair();
+
+        // The spent output holds 10 units of asset [3; 32].
+        for (min_amount, held) in [(10, true), (11, false)] {
+            let (script, script_hash) = asset_gated(AssetId([3u8; 32]), min_amount, &pk).unwrap();
+            let (tx, prev_output) = spend(script_hash);
+            let signature = sign(&sk, &tx, &prev_output);
+            assert_eq!(
+                eval(&script, &tx, &prev_output, 1, vec![signature]).is_ok(),
+                held
+            );
+        }
+
+        let (oracle_pk, oracle_sk) = keypair();
+        let outcome = b"red wins".to_vec();
+        let attest = |message: &[u8]| {
+            default_registry()
+                .get(&oracle_sk.scheme)
+                .expect("scheme should exist")
+                .sign(&oracle_sk, message)
+                .expect("signing should work")
+                .to_bytes()
+                .expect("signature encodes")
+        };
+        let (script, script_hash) = oracle_attested(&oracle_pk, outcome.clone(), &pk).unwrap();
+        let (tx, prev_output) = spend(script_hash);
+        let signature = sign(&sk, &tx, &prev_output);
+        assert!(eval(
+            &script,
+            &tx,
+            &prev_output,
+            1,
+            vec![attest(&outcome), signature.clone()]
+        )
+        .is_ok());
+        assert!(eval(
+            &script,
+            &tx,
+            &prev_output,
+            1,
+            vec![attest(b"blue wins"), signature]
+        )
+        .is_err());
+    }
+}
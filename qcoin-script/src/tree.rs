@@ -0,0 +1,157 @@
+//! Merkelized alternative spending paths.
+//!
+//! An output can commit to the root of a tree of leaf scripts instead of a single script
+//! hash. A spend reveals only the leaf it executes plus the sibling hashes on the path to
+//! the root, so unused branches (and their large PQ keys) never appear on-chain.
+//!
+//! Leaf and branch hashes are domain separated from each other and from plain script
+//! hashes, so a tree root can never be confused with the hash of a single script.
+//! Branches hash their children in sorted order, which lets a path be a plain list of
+//! sibling hashes without left/right flags. A node without a sibling on its level is
+//! carried up unchanged rather than paired with itself.
+
+use crate::{Script, ScriptEnvelope};
+use qcoin_types::Hash256;
+use thiserror::Error;
+
+const LEAF_DOMAIN: &[u8] = b"QCOIN_SCRIPT_LEAF_V1";
+const BRANCH_DOMAIN: &[u8] = b"QCOIN_SCRIPT_BRANCH_V1";
+
+/// Longest Merkle path accepted by consensus; bounds the work needed to check a witness.
+pub const MAX_PATH_LEN: usize = 32;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum TreeError {
+    #[error("script tree needs at least one leaf")]
+    Empty,
+    #[error("script tree is deeper than {MAX_PATH_LEN} levels")]
+    TooDeep,
+}
+
+pub fn leaf_hash(script: &Script) -> Hash256 {
+    envelope_leaf_hash(&ScriptEnvelope::legacy(script))
+}
+
+/// Leaf hash of a versioned script; equal to [`leaf_hash`] for legacy scripts.
+pub fn envelope_leaf_hash(envelope: &ScriptEnvelope) -> Hash256 {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(LEAF_DOMAIN);
+    hasher.update(&envelope.commitment());
+    *hasher.finalize().as_bytes()
+}
+
+pub fn branch_hash(a: &Hash256, b: &Hash256) -> Hash256 {
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(BRANCH_DOMAIN);
+    hasher.update(first);
+    hasher.update(second);
+    *hasher.finalize().as_bytes()
+}
+
+/// Folds a leaf hash up a Merkle path of sibling hashes.
+pub fn root_from_path(leaf: Hash256, path: &[Hash256]) -> Hash256 {
+    path.iter()
+        .fold(leaf, |node, sibling| branch_hash(&node, sibling))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScriptTree {
+    leaves: Vec<Script>,
+    /// Level 0 holds the leaf hashes; the last level holds only the root.
+    levels: Vec<Vec<Hash256>>,
+}
+
+impl ScriptTree {
+    pub fn new(leaves: Vec<Script>) -> Result<Self, TreeError> {
+        if leaves.is_empty() {
+            return Err(TreeError::Empty);
+        }
+
+        let mut levels = vec![leaves.iter().map(leaf_hash).collect::<Vec<_>>()];
+        while levels.last().expect("at least one level").len() > 1 {
+            if levels.len() > MAX_PATH_LEN {
+                return Err(TreeError::TooDeep);
+            }
+            let next = levels
+                .last()
+                .expect("at least one level")
+                .chunks(2)
+                .map(|pair| match pair {
+                    [a, b] => branch_hash(a, b),
+                    [single] => *single,
+                    _ => unreachable!("chunks(2) yields one or two nodes"),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Ok(Self { leaves, levels })
+    }
+
+    /// The value an output commits to as `owner_script_hash`.
+    pub fn root(&self) -> Hash256 {
+        self.levels.last().expect("at least one level")[0]
+    }
+
+    pub fn leaves(&self) -> &[Script] {
+        &self.leaves
+    }
+
+    /// Sibling hashes from the leaf at `index` up to the root.
+    pub fn path(&self, index: usize) -> Option<Vec<Hash256>> {
+        if index >= self.leaves.len() {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            if let Some(sibling) = level.get(position ^ 1) {
+                path.push(*sibling);
+            }
+            position /= 2;
+        }
+        Some(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OpCode;
+
+    fn leaf(tag: u8) -> Script {
+        Script(vec![OpCode::PushBytes(vec![tag]), OpCode::Nop])
+    }
+
+    #[test]
+    fn every_leaf_path_folds_to_the_root() {
+        for count in 1..=7u8 {
+            let tree = ScriptTree::new((0..count).map(leaf).collect()).expect("tree builds");
+            for (index, script) in tree.leaves().iter().enumerate() {
+                let path = tree.path(index).expect("leaf exists");
+                assert_eq!(root_from_path(leaf_hash(script), &path), tree.root());
+            }
+            assert!(tree.path(count as usize).is_none());
+        }
+    }
+
+    #[test]
+    fn root_is_domain_separated_from_script_hash() {
+        let script = leaf(1);
+        let tree = ScriptTree::new(vec![script.clone()]).unwrap();
+        assert_eq!(tree.root(), leaf_hash(&script));
+        assert_ne!(tree.root(), script.script_hash());
+        assert!(tree.path(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn wrong_leaf_or_path_changes_the_root() {
+        let tree = ScriptTree::new((0..4).map(leaf).collect()).unwrap();
+        let path = tree.path(1).unwrap();
+        assert_ne!(root_from_path(leaf_hash(&leaf(9)), &path), tree.root());
+        assert_ne!(root_from_path(leaf_hash(&leaf(1)), &path[..1]), tree.root());
+        assert_eq!(ScriptTree::new(Vec::new()), Err(TreeError::Empty));
+    }
+}
@@ -0,0 +1,306 @@
+//! Human-readable addresses for owner script hashes.
+//!
+//! An address is the bech32m encoding (BIP 350) of an address version followed by a
+//! 32-byte script hash. The human-readable part is `qc` plus the decimal chain id, so an
+//! address for one chain fails to parse for another, and the checksum catches typos.
+
+use super::Hash256;
+use thiserror::Error;
+
+/// Prefix of every address HRP; the chain id follows it.
+pub const ADDRESS_HRP_PREFIX: &str = "qc";
+/// The only address version: the payload is an `owner_script_hash`.
+pub const ADDRESS_VERSION_SCRIPT_HASH: u8 = 0;
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+const CHECKSUM_LEN: usize = 6;
+const MAX_LEN: usize = 90;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum AddressError {
+    #[error("address mixes upper and lower case")]
+    MixedCase,
+    #[error("address is longer than {MAX_LEN} characters")]
+    TooLong,
+    #[error("address has no separator")]
+    MissingSeparator,
+    #[error("invalid address character '{0}'")]
+    InvalidCharacter(char),
+    #[error("address checksum is invalid")]
+    InvalidChecksum,
+    #[error("address prefix '{0}' is not qc followed by a chain id")]
+    InvalidHrp(String),
+    #[error("address is for chain {found}, expected chain {expected}")]
+    WrongChain { expected: u32, found: u32 },
+    #[error("unsupported address version {0}")]
+    UnsupportedVersion(u8),
+    #[error("address payload is not a 32-byte script hash")]
+    InvalidPayload,
+    #[error("expected a qc address or 64 hex characters")]
+    InvalidScriptHash,
+}
+
+pub fn address_hrp(chain_id: u32) -> String {
+    format!("{ADDRESS_HRP_PREFIX}{chain_id}")
+}
+
+/// Formats `script_hash` as an address for `chain_id`.
+pub fn encode_address(script_hash: &Hash256, chain_id: u32) -> String {
+    let mut data = vec![ADDRESS_VERSION_SCRIPT_HASH];
+    data.extend(convert_bits(script_hash, 8, 5, true).expect("padding is allowed"));
+    bech32m_encode(&address_hrp(chain_id), &data)
+}
+
+/// Parses any valid address, returning its chain id and script hash.
+pub fn decode_address(address: &str) -> Result<(u32, Hash256), AddressError> {
+    let (hrp, data) = bech32m_decode(address)?;
+    let chain_id = hrp
+        .strip_prefix(ADDRESS_HRP_PREFIX)
+        .filter(|digits| !digits.starts_with('0') || *digits == "0")
+        .and_then(|digits| digits.parse::<u32>().ok())
+        .ok_or_else(|| AddressError::InvalidHrp(hrp.clone()))?;
+
+    let (&version, payload) = data.split_first().ok_or(AddressError::InvalidPayload)?;
+    if version != ADDRESS_VERSION_SCRIPT_HASH {
+        return Err(AddressError::UnsupportedVersion(version));
+    }
+    let script_hash = convert_bits(payload, 5, 8, false)
+        .and_then(|bytes| Hash256::try_from(bytes).ok())
+        .ok_or(AddressError::InvalidPayload)?;
+    Ok((chain_id, script_hash))
+}
+
+/// Parses an address that must belong to `chain_id`.
+pub fn parse_address(address: &str, chain_id: u32) -> Result<Hash256, AddressError> {
+    let (found, script_hash) = decode_address(address)?;
+    if found != chain_id {
+        return Err(AddressError::WrongChain {
+            expected: chain_id,
+            found,
+        });
+    }
+    Ok(script_hash)
+}
+
+/// Accepts either an address for `chain_id` or a script hash as 64 hex characters, the
+/// form used before addresses existed.
+pub fn parse_script_hash(input: &str, chain_id: u32) -> Result<Hash256, AddressError> {
+    let input = input.trim();
+    if input.len() == 64 && input.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        let mut hash = [0u8; 32];
+        for (byte, pair) in hash.iter_mut().zip(input.as_bytes().chunks(2)) {
+            let pair = std::str::from_utf8(pair).expect("hex digits are ascii");
+            *byte = u8::from_str_radix(pair, 16).expect("checked hex digits");
+        }
+        return Ok(hash);
+    }
+    if input.contains('1') {
+        return parse_address(input, chain_id);
+    }
+    Err(AddressError::InvalidScriptHash)
+}
+
+fn polymod(values: impl IntoIterator<Item = u8>) -> u32 {
+    const GENERATORS: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x01ff_ffff) << 5) ^ u32::from(value);
+        for (bit, generator) in GENERATORS.iter().enumerate() {
+            if (top >> bit) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn hrp_expand(hrp: &str) -> Vec<u8> {
+    let bytes = hrp.as_bytes();
+    let mut out: Vec<u8> = bytes.iter().map(|byte| byte >> 5).collect();
+    out.push(0);
+    out.extend(bytes.iter().map(|byte| byte & 0x1f));
+    out
+}
+
+fn bech32m_encode(hrp: &str, data: &[u8]) -> String {
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0; CHECKSUM_LEN]);
+    let checksum = polymod(values) ^ BECH32M_CONST;
+
+    let mut out = String::with_capacity(hrp.len() + 1 + data.len() + CHECKSUM_LEN);
+    out.push_str(hrp);
+    out.push('1');
+    for value in data {
+        out.push(CHARSET[usize::from(*value)] as char);
+    }
+    for index in 0..CHECKSUM_LEN {
+        let value = (checksum >> (5 * (CHECKSUM_LEN - 1 - index))) & 0x1f;
+        out.push(CHARSET[value as usize] as char);
+    }
+    out
+}
+
+/// Returns the lowercase HRP and the data values without the checksum.
+fn bech32m_decode(input: &str) -> Result<(String, Vec<u8>), AddressError> {
+    if input.len() > MAX_LEN {
+        return Err(AddressError::TooLong);
+    }
+    let has_lower = input.bytes().any(|byte| byte.is_ascii_lowercase());
+    let has_upper = input.bytes().any(|byte| byte.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return Err(AddressError::MixedCase);
+    }
+    let input = input.to_ascii_lowercase();
+
+    let separator = input.rfind('1').ok_or(AddressError::MissingSeparator)?;
+    let (hrp, rest) = input.split_at(separator);
+    let data_part = &rest[1..];
+    if let Some(invalid) = hrp.chars().find(|c| !(33..=126).contains(&(*c as u32))) {
+        return Err(AddressError::InvalidCharacter(invalid));
+    }
+    if hrp.is_empty() || data_part.len() < CHECKSUM_LEN {
+        return Err(AddressError::MissingSeparator);
+    }
+
+    let data = data_part
+        .chars()
+        .map(|c| {
+            CHARSET
+                .iter()
+                .position(|candidate| *candidate as char == c)
+                .map(|position| position as u8)
+                .ok_or(AddressError::InvalidCharacter(c))
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+
+    let mut values = hrp_expand(hrp);
+    values.extend_from_slice(&data);
+    if polymod(values) != BECH32M_CONST {
+        return Err(AddressError::InvalidChecksum);
+    }
+
+    let payload_len = data.len() - CHECKSUM_LEN;
+    Ok((hrp.to_string(), data[..payload_len].to_vec()))
+}
+
+/// Regroups `data` from `from`-bit to `to`-bit values. Without `pad`, leftover bits must
+/// be fewer than `from` and all zero, so each payload has one encoding.
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut acc = 0u32;
+    let mut bits = 0u32;
+    let max = (1u32 << to) - 1;
+    let mut out = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for value in data {
+        let value = u32::from(*value);
+        if value >> from != 0 {
+            return None;
+        }
+        acc = (acc << from) | value;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            out.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            out.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        return None;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bech32m_matches_bip350_vectors() {
+        for valid in [
+            "A1LQFN3A",
+            "a1lqfn3a",
+            "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+            "split1checkupstagehandshakeupstreamerranterredcaperredlc445v",
+            "?1v759aa",
+        ] {
+            assert!(bech32m_decode(valid).is_ok(), "{valid}");
+        }
+        // Valid bech32 (not bech32m) checksums are rejected.
+        assert_eq!(
+            bech32m_decode("a12uel5l"),
+            Err(AddressError::InvalidChecksum)
+        );
+        assert_eq!(bech32m_decode("A1LqFN3A"), Err(AddressError::MixedCase));
+    }
+
+    #[test]
+    fn addresses_round_trip_and_are_bound_to_their_chain() {
+        let script_hash = [0xabu8; 32];
+        let address = encode_address(&script_hash, 7);
+        assert!(address.starts_with("qc71"));
+        assert_eq!(decode_address(&address), Ok((7, script_hash)));
+        assert_eq!(parse_address(&address.to_uppercase(), 7), Ok(script_hash));
+        assert_eq!(
+            parse_address(&address, 0),
+            Err(AddressError::WrongChain {
+                expected: 0,
+                found: 7
+            })
+        );
+
+        let mut typo = address.clone().into_bytes();
+        let last = typo.len() - 1;
+        typo[last] = if typo[last] == b'q' { b'p' } else { b'q' };
+        assert_eq!(
+            decode_address(std::str::from_utf8(&typo).unwrap()),
+            Err(AddressError::InvalidChecksum)
+        );
+
+        let zero = encode_address(&script_hash, 0);
+        assert!(zero.starts_with("qc01"));
+        assert_eq!(decode_address(&zero), Ok((0, script_hash)));
+    }
+
+    #[test]
+    fn script_hashes_parse_from_hex_or_address() {
+        let script_hash = [0x5au8; 32];
+        let hex = "5a".repeat(32);
+        assert_eq!(parse_script_hash(&hex, 3), Ok(script_hash));
+        assert_eq!(
+            parse_script_hash(&encode_address(&script_hash, 3), 3),
+            Ok(script_hash)
+        );
+        assert_eq!(
+            parse_script_hash("5a5a", 3),
+            Err(AddressError::InvalidScriptHash)
+        );
+
+        // A well-formed bech32m string with another prefix or version is not an address.
+        let mut data = vec![1u8];
+        data.extend(convert_bits(&script_hash, 8, 5, true).unwrap());
+        assert_eq!(
+            decode_address(&bech32m_encode("qc3", &data)),
+            Err(AddressError::UnsupportedVersion(1))
+        );
+        data[0] = ADDRESS_VERSION_SCRIPT_HASH;
+        assert!(matches!(
+            decode_address(&bech32m_encode("bc", &data)),
+            Err(AddressError::InvalidHrp(_))
+        ));
+        assert!(matches!(
+            decode_address(&bech32m_encode("qc03", &data)),
+            Err(AddressError::InvalidHrp(_))
+        ));
+    }
+}
@@ -0,0 +1,1315 @@
+//! Byte encodings that are hashed or signed, and their strict decoders.
+//!
+//! Every decoder accepts exactly the bytes its encoder produces: truncated input, trailing
+//! bytes, unknown tags and alternative encodings of the same value are all rejected. A
+//! value therefore has one encoding, and decoding then re-encoding returns the input.
+//! Integers are little-endian and lengths are `u32`.
+
+use super::{
+    AssetAmount, AssetDefinition, AssetId, AssetKind, Block, BlockHeader, CollectionDefinition,
+    CollectionId, Hash256, InputWitness, IssuerControls, Output, RoyaltyAmount, RoyaltyRule,
+    SalePrice, Transaction, TransactionCore, TransactionInput, TransactionKind, TransactionWitness,
+    TransferMode, TransferPolicy, BLOCK_HEADER_VERSION_LEGACY, LEGACY_WITNESS_ROOT,
+};
+use qcoin_crypto::{PublicKey, Signature, SignatureSchemeId};
+use thiserror::Error;
+
+/// Leading bytes of an encoded [`InputWitness`]. No legacy bincode witness layout can
+/// start with them and still fit in a transaction: read as a length, they exceed 20 MB.
+pub const WITNESS_MAGIC: &[u8; 4] = b"QWIT";
+pub const WITNESS_ENCODING_VERSION: u8 = 1;
+
+/// Leading bytes of an encoded [`Block`].
+pub const BLOCK_MAGIC: &[u8; 4] = b"QBLK";
+pub const BLOCK_ENCODING_VERSION: u8 = 1;
+
+/// Set in the transaction kind byte when a validity window follows the kind's fields.
+/// Transactions without one keep the encoding, and so the id, they had before windows.
+pub const TX_VALIDITY_WINDOW_FLAG: u8 = 0x80;
+
+/// Set in the asset kind byte when a non-default transfer policy follows the decimals.
+/// Definitions with the default policy keep their encoding, and so their asset id.
+pub const ASSET_TRANSFER_POLICY_FLAG: u8 = 0x80;
+
+/// Set in the asset kind byte when issuer controls follow, after any transfer policy.
+pub const ASSET_ISSUER_CONTROLS_FLAG: u8 = 0x40;
+const ISSUER_CONTROL_FREEZE: u8 = 0x01;
+const ISSUER_CONTROL_CLAWBACK: u8 = 0x02;
+
+/// Set in the asset kind byte when a royalty rule follows, after any issuer controls.
+pub const ASSET_ROYALTY_FLAG: u8 = 0x20;
+const ASSET_KIND_FLAGS: u8 =
+    ASSET_TRANSFER_POLICY_FLAG | ASSET_ISSUER_CONTROLS_FLAG | ASSET_ROYALTY_FLAG;
+
+/// Sizes of the legacy header layouts, without and with a witness root.
+const LEGACY_HEADER_LEN: usize = 3 * 32 + 8 + 8;
+const LEGACY_WITNESS_HEADER_LEN: usize = LEGACY_HEADER_LEN + 32;
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum DecodeError {
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+    #[error("{0} trailing bytes after value")]
+    TrailingBytes(usize),
+    #[error("invalid {field} tag {tag}")]
+    InvalidTag { field: &'static str, tag: u8 },
+    #[error("missing magic bytes")]
+    MissingMagic,
+    #[error("unsupported encoding version {0}")]
+    UnsupportedVersion(u32),
+    #[error("non-canonical encoding: {0}")]
+    NonCanonical(&'static str),
+}
+
+/// Cursor over consensus-encoded bytes. Every read fails rather than running past
+/// the end, and [`Reader::finish`] rejects leftovers, so each value has exactly one
+/// accepted encoding.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.bytes.len() < len {
+            return Err(DecodeError::UnexpectedEnd);
+        }
+        let (head, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        Ok(self.take(N)?.try_into().expect("take returns N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn u128(&mut self) -> Result<u128, DecodeError> {
+        Ok(u128::from_le_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> Result<usize, DecodeError> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn hash(&mut self) -> Result<Hash256, DecodeError> {
+        self.array()
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, DecodeError> {
+        let len = self.len()?;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    /// Reads a `0`/`1` presence tag.
+    fn present(&mut self, field: &'static str) -> Result<bool, DecodeError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(DecodeError::InvalidTag { field, tag }),
+        }
+    }
+
+    /// Reads a count followed by that many items.
+    fn list<T>(
+        &mut self,
+        mut item: impl FnMut(&mut Self) -> Result<T, DecodeError>,
+    ) -> Result<Vec<T>, DecodeError> {
+        let count = self.len()?;
+        let mut items = Vec::with_capacity(self.list_capacity::<T>(count));
+        for _ in 0..count {
+            items.push(item(self)?);
+        }
+        Ok(items)
+    }
+
+    /// The count comes from the wire, so it is not trusted for preallocation: at most as
+    /// many bytes as remain in the input are reserved up front.
+    fn list_capacity<T>(&self, count: usize) -> usize {
+        count.min(self.bytes.len() / std::mem::size_of::<T>().max(1))
+    }
+
+    fn magic(&mut self, magic: &[u8]) -> Result<(), DecodeError> {
+        match self.take(magic.len()) {
+            Ok(bytes) if bytes == magic => Ok(()),
+            _ => Err(DecodeError::MissingMagic),
+        }
+    }
+
+    fn finish(self) -> Result<(), DecodeError> {
+        match self.bytes.len() {
+            0 => Ok(()),
+            extra => Err(DecodeError::TrailingBytes(extra)),
+        }
+    }
+}
+
+/// Runs `read` over all of `bytes`, rejecting anything left over.
+fn decode_all<T>(
+    bytes: &[u8],
+    read: impl FnOnce(&mut Reader) -> Result<T, DecodeError>,
+) -> Result<T, DecodeError> {
+    let mut reader = Reader::new(bytes);
+    let value = read(&mut reader)?;
+    reader.finish()?;
+    Ok(value)
+}
+
+fn encode_len(len: usize, out: &mut Vec<u8>) {
+    let len: u32 = len
+        .try_into()
+        .expect("consensus encoding length should fit into u32");
+    out.extend_from_slice(&len.to_le_bytes());
+}
+
+pub fn encode_hash(hash: &Hash256, out: &mut Vec<u8>) {
+    out.extend_from_slice(hash);
+}
+
+fn encode_transaction_input(input: &TransactionInput, out: &mut Vec<u8>) {
+    encode_hash(&input.tx_id, out);
+    out.extend_from_slice(&input.index.to_le_bytes());
+}
+
+fn read_transaction_input(reader: &mut Reader) -> Result<TransactionInput, DecodeError> {
+    Ok(TransactionInput {
+        tx_id: reader.hash()?,
+        index: reader.u32()?,
+    })
+}
+
+fn encode_asset_amount(asset: &AssetAmount, out: &mut Vec<u8>) {
+    encode_hash(&asset.asset_id.0, out);
+    out.extend_from_slice(&asset.amount.to_le_bytes());
+}
+
+fn read_asset_amount(reader: &mut Reader) -> Result<AssetAmount, DecodeError> {
+    Ok(AssetAmount {
+        asset_id: AssetId(reader.hash()?),
+        amount: reader.u128()?,
+    })
+}
+
+/// Issuer script hash, then metadata root.
+pub fn encode_collection_definition(collection: &CollectionDefinition) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_collection_definition_into(collection, &mut out);
+    out
+}
+
+fn encode_collection_definition_into(collection: &CollectionDefinition, out: &mut Vec<u8>) {
+    encode_hash(&collection.issuer_script_hash, out);
+    encode_hash(&collection.metadata_root, out);
+}
+
+pub fn decode_collection_definition(bytes: &[u8]) -> Result<CollectionDefinition, DecodeError> {
+    decode_all(bytes, read_collection_definition)
+}
+
+fn read_collection_definition(reader: &mut Reader) -> Result<CollectionDefinition, DecodeError> {
+    Ok(CollectionDefinition {
+        issuer_script_hash: reader.hash()?,
+        metadata_root: reader.hash()?,
+    })
+}
+
+pub fn encode_asset_definition(definition: &AssetDefinition) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_asset_definition_into(definition, &mut out);
+    out
+}
+
+fn encode_asset_definition_into(definition: &AssetDefinition, out: &mut Vec<u8>) {
+    encode_hash(&definition.issuer_script_hash, out);
+    let kind_tag = match definition.kind {
+        AssetKind::Fungible => 0,
+        AssetKind::NonFungible => 1,
+        AssetKind::SemiFungible => 2,
+    };
+    let policy = &definition.transfer_policy;
+    let controls = &definition.issuer_controls;
+    let mut tag = kind_tag;
+    if !policy.is_default() {
+        tag |= ASSET_TRANSFER_POLICY_FLAG;
+    }
+    if !controls.is_default() {
+        tag |= ASSET_ISSUER_CONTROLS_FLAG;
+    }
+    if definition.royalty.is_some() {
+        tag |= ASSET_ROYALTY_FLAG;
+    }
+    out.push(tag);
+    encode_hash(&definition.metadata_root, out);
+    match definition.max_supply {
+        Some(max) => {
+            out.push(1);
+            out.extend_from_slice(&max.to_le_bytes());
+        }
+        None => out.push(0),
+    }
+    out.push(definition.decimals);
+    if !policy.is_default() {
+        out.push(match policy.mode {
+            TransferMode::Transferable => 0,
+            TransferMode::Soulbound => 1,
+            TransferMode::IssuerApproved => 2,
+        });
+        out.push(u8::from(policy.burnable_by_holder));
+    }
+    if !controls.is_default() {
+        let mut bits = 0;
+        if controls.freeze {
+            bits |= ISSUER_CONTROL_FREEZE;
+        }
+        if controls.clawback {
+            bits |= ISSUER_CONTROL_CLAWBACK;
+        }
+        out.push(bits);
+    }
+    if let Some(royalty) = &definition.royalty {
+        encode_hash(&royalty.recipient_script_hash, out);
+        encode_hash(&royalty.payment_asset_id.0, out);
+        match royalty.amount {
+            RoyaltyAmount::BasisPoints(basis_points) => {
+                out.push(0);
+                out.extend_from_slice(&basis_points.to_le_bytes());
+            }
+            RoyaltyAmount::Fixed(amount) => {
+                out.push(1);
+                out.extend_from_slice(&amount.to_le_bytes());
+            }
+        }
+    }
+}
+
+pub fn decode_asset_definition(bytes: &[u8]) -> Result<AssetDefinition, DecodeError> {
+    decode_all(bytes, read_asset_definition)
+}
+
+fn read_asset_definition(reader: &mut Reader) -> Result<AssetDefinition, DecodeError> {
+    let issuer_script_hash = reader.hash()?;
+    let tag = reader.u8()?;
+    let kind = match tag & !ASSET_KIND_FLAGS {
+        0 => AssetKind::Fungible,
+        1 => AssetKind::NonFungible,
+        2 => AssetKind::SemiFungible,
+        tag => {
+            return Err(DecodeError::InvalidTag {
+                field: "asset kind",
+                tag,
+            })
+        }
+    };
+    let metadata_root = reader.hash()?;
+    let max_supply = if reader.present("max supply")? {
+        Some(reader.u128()?)
+    } else {
+        None
+    };
+    let decimals = reader.u8()?;
+    let transfer_policy = if tag & ASSET_TRANSFER_POLICY_FLAG != 0 {
+        let mode = match reader.u8()? {
+            0 => TransferMode::Transferable,
+            1 => TransferMode::Soulbound,
+            2 => TransferMode::IssuerApproved,
+            tag => {
+                return Err(DecodeError::InvalidTag {
+                    field: "transfer mode",
+                    tag,
+                })
+            }
+        };
+        let policy = TransferPolicy {
+            mode,
+            burnable_by_holder: reader.present("burnable by holder")?,
+        };
+        if policy.is_default() {
+            return Err(DecodeError::NonCanonical("default transfer policy"));
+        }
+        policy
+    } else {
+        TransferPolicy::default()
+    };
+    let issuer_controls = if tag & ASSET_ISSUER_CONTROLS_FLAG != 0 {
+        let bits = reader.u8()?;
+        if bits & !(ISSUER_CONTROL_FREEZE | ISSUER_CONTROL_CLAWBACK) != 0 {
+            return Err(DecodeError::InvalidTag {
+                field: "issuer controls",
+                tag: bits,
+            });
+        }
+        if bits == 0 {
+            return Err(DecodeError::NonCanonical("empty issuer controls"));
+        }
+        IssuerControls {
+            freeze: bits & ISSUER_CONTROL_FREEZE != 0,
+            clawback: bits & ISSUER_CONTROL_CLAWBACK != 0,
+        }
+    } else {
+        IssuerControls::default()
+    };
+    let royalty = if tag & ASSET_ROYALTY_FLAG != 0 {
+        let recipient_script_hash = reader.hash()?;
+        let payment_asset_id = AssetId(reader.hash()?);
+        let amount = match reader.u8()? {
+            0 => RoyaltyAmount::BasisPoints(reader.u16()?),
+            1 => RoyaltyAmount::Fixed(reader.u128()?),
+            tag => {
+                return Err(DecodeError::InvalidTag {
+                    field: "royalty amount",
+                    tag,
+                })
+            }
+        };
+        Some(RoyaltyRule {
+            recipient_script_hash,
+            payment_asset_id,
+            amount,
+        })
+    } else {
+        None
+    };
+    Ok(AssetDefinition {
+        issuer_script_hash,
+        metadata_root,
+        max_supply,
+        decimals,
+        kind,
+        transfer_policy,
+        issuer_controls,
+        royalty,
+    })
+}
+
+pub fn encode_output(output: &Output) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_output_into(output, &mut out);
+    out
+}
+
+pub fn encode_output_into(output: &Output, out: &mut Vec<u8>) {
+    encode_hash(&output.owner_script_hash, out);
+    encode_len(output.assets.len(), out);
+    for asset in &output.assets {
+        encode_asset_amount(asset, out);
+    }
+
+    match &output.metadata_hash {
+        Some(hash) => {
+            out.push(1);
+            encode_hash(hash, out);
+        }
+        None => out.push(0),
+    }
+}
+
+pub fn decode_output(bytes: &[u8]) -> Result<Output, DecodeError> {
+    decode_all(bytes, read_output)
+}
+
+fn read_output(reader: &mut Reader) -> Result<Output, DecodeError> {
+    let owner_script_hash = reader.hash()?;
+    let assets = reader.list(read_asset_amount)?;
+    let metadata_hash = if reader.present("metadata hash")? {
+        Some(reader.hash()?)
+    } else {
+        None
+    };
+    Ok(Output {
+        owner_script_hash,
+        assets,
+        metadata_hash,
+    })
+}
+
+pub fn encode_tx_core(core: &TransactionCore) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_tx_core_into(core, &mut out);
+    out
+}
+
+pub fn encode_tx_core_into(core: &TransactionCore, out: &mut Vec<u8>) {
+    let kind_tag = match &core.kind {
+        TransactionKind::Transfer => 0,
+        TransactionKind::CreateAsset { sub_ids, .. } if sub_ids.is_empty() => 1,
+        TransactionKind::CreateAsset { .. } => 2,
+        TransactionKind::CreateCollection { .. } => 3,
+        TransactionKind::CreateCollectionItem { .. } => 4,
+        TransactionKind::FreezeOutputs { .. } => 5,
+        TransactionKind::UnfreezeOutputs { .. } => 6,
+        TransactionKind::Clawback { .. } => 7,
+        TransactionKind::UpdateAssetMetadata { .. } => 8,
+        TransactionKind::Sale { .. } => 9,
+    };
+    if core.has_validity_window() {
+        out.push(kind_tag | TX_VALIDITY_WINDOW_FLAG);
+    } else {
+        out.push(kind_tag);
+    }
+
+    encode_len(core.inputs.len(), out);
+    for input in &core.inputs {
+        encode_transaction_input(input, out);
+    }
+
+    encode_len(core.outputs.len(), out);
+    for output in &core.outputs {
+        encode_output_into(output, out);
+    }
+
+    match &core.kind {
+        TransactionKind::Transfer => {}
+        TransactionKind::CreateAsset {
+            definition,
+            initial_supply,
+            sub_ids,
+        } => {
+            encode_asset_definition_into(definition, out);
+            out.extend_from_slice(&initial_supply.to_le_bytes());
+            if !sub_ids.is_empty() {
+                encode_len(sub_ids.len(), out);
+                for sub_id in sub_ids {
+                    out.extend_from_slice(&sub_id.to_le_bytes());
+                }
+            }
+        }
+        TransactionKind::CreateCollection { collection } => {
+            encode_collection_definition_into(collection, out);
+        }
+        TransactionKind::CreateCollectionItem {
+            collection_id,
+            serial,
+            definition,
+            initial_supply,
+        } => {
+            encode_hash(&collection_id.0, out);
+            out.extend_from_slice(&serial.to_le_bytes());
+            encode_asset_definition_into(definition, out);
+            out.extend_from_slice(&initial_supply.to_le_bytes());
+        }
+        TransactionKind::FreezeOutputs { asset_id, targets }
+        | TransactionKind::UnfreezeOutputs { asset_id, targets }
+        | TransactionKind::Clawback { asset_id, targets } => {
+            encode_hash(&asset_id.0, out);
+            encode_len(targets.len(), out);
+            for target in targets {
+                encode_transaction_input(target, out);
+            }
+        }
+        TransactionKind::UpdateAssetMetadata {
+            asset_id,
+            metadata_root,
+        } => {
+            encode_hash(&asset_id.0, out);
+            encode_hash(metadata_root, out);
+        }
+        TransactionKind::Sale { prices } => {
+            encode_len(prices.len(), out);
+            for sale_price in prices {
+                encode_hash(&sale_price.asset_id.0, out);
+                out.extend_from_slice(&sale_price.price.to_le_bytes());
+            }
+        }
+    }
+
+    if core.has_validity_window() {
+        for bound in [core.valid_from_height, core.valid_until_height] {
+            match bound {
+                Some(height) => {
+                    out.push(1);
+                    out.extend_from_slice(&height.to_le_bytes());
+                }
+                None => out.push(0),
+            }
+        }
+    }
+}
+
+pub fn decode_tx_core(bytes: &[u8]) -> Result<TransactionCore, DecodeError> {
+    decode_all(bytes, read_tx_core)
+}
+
+fn read_tx_core(reader: &mut Reader) -> Result<TransactionCore, DecodeError> {
+    let tag = reader.u8()?;
+    let kind_tag = tag & !TX_VALIDITY_WINDOW_FLAG;
+    let inputs = reader.list(read_transaction_input)?;
+    let outputs = reader.list(read_output)?;
+    let kind = match kind_tag {
+        0 => TransactionKind::Transfer,
+        1 => TransactionKind::CreateAsset {
+            definition: read_asset_definition(reader)?,
+            initial_supply: reader.u128()?,
+            sub_ids: Vec::new(),
+        },
+        2 => {
+            let definition = read_asset_definition(reader)?;
+            let initial_supply = reader.u128()?;
+            let sub_ids = reader.list(Reader::u64)?;
+            if sub_ids.is_empty() {
+                return Err(DecodeError::NonCanonical("empty sub-id list"));
+            }
+            TransactionKind::CreateAsset {
+                definition,
+                initial_supply,
+                sub_ids,
+            }
+        }
+        3 => TransactionKind::CreateCollection {
+            collection: read_collection_definition(reader)?,
+        },
+        4 => TransactionKind::CreateCollectionItem {
+            collection_id: CollectionId(reader.hash()?),
+            serial: reader.u64()?,
+            definition: read_asset_definition(reader)?,
+            initial_supply: reader.u128()?,
+        },
+        5..=7 => {
+            let asset_id = AssetId(reader.hash()?);
+            let targets = reader.list(read_transaction_input)?;
+            match kind_tag {
+                5 => TransactionKind::FreezeOutputs { asset_id, targets },
+                6 => TransactionKind::UnfreezeOutputs { asset_id, targets },
+                _ => TransactionKind::Clawback { asset_id, targets },
+            }
+        }
+        8 => TransactionKind::UpdateAssetMetadata {
+            asset_id: AssetId(reader.hash()?),
+            metadata_root: reader.hash()?,
+        },
+        9 => {
+            let prices = reader.list(read_sale_price)?;
+            if prices.is_empty() {
+                return Err(DecodeError::NonCanonical("empty sale price list"));
+            }
+            if !prices
+                .windows(2)
+                .all(|pair| pair[0].asset_id.0 < pair[1].asset_id.0)
+            {
+                return Err(DecodeError::NonCanonical("unsorted sale prices"));
+            }
+            TransactionKind::Sale { prices }
+        }
+        tag => {
+            return Err(DecodeError::InvalidTag {
+                field: "transaction kind",
+                tag,
+            })
+        }
+    };
+
+    let (mut valid_from_height, mut valid_until_height) = (None, None);
+    if tag & TX_VALIDITY_WINDOW_FLAG != 0 {
+        if reader.present("valid from height")? {
+            valid_from_height = Some(reader.u64()?);
+        }
+        if reader.present("valid until height")? {
+            valid_until_height = Some(reader.u64()?);
+        }
+        if valid_from_height.is_none() && valid_until_height.is_none() {
+            return Err(DecodeError::NonCanonical("empty validity window"));
+        }
+    }
+    Ok(TransactionCore {
+        kind,
+        inputs,
+        outputs,
+        valid_from_height,
+        valid_until_height,
+    })
+}
+
+fn read_sale_price(reader: &mut Reader) -> Result<SalePrice, DecodeError> {
+    Ok(SalePrice {
+        asset_id: AssetId(reader.hash()?),
+        price: reader.u128()?,
+    })
+}
+
+fn encode_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    encode_len(bytes.len(), out);
+    out.extend_from_slice(bytes);
+}
+
+/// Input count, then each encoded input witness with a length prefix.
+pub fn encode_tx_witness(witness: &TransactionWitness) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_tx_witness_into(witness, &mut out);
+    out
+}
+
+fn encode_tx_witness_into(witness: &TransactionWitness, out: &mut Vec<u8>) {
+    encode_len(witness.inputs.len(), out);
+    for input in &witness.inputs {
+        encode_bytes(input, out);
+    }
+}
+
+/// Inverse of [`encode_tx_witness`]. Input witnesses are returned as carried; they are
+/// decoded by the ledger, which also accepts older layouts.
+pub fn decode_tx_witness(bytes: &[u8]) -> Result<TransactionWitness, DecodeError> {
+    decode_all(bytes, read_tx_witness)
+}
+
+fn read_tx_witness(reader: &mut Reader) -> Result<TransactionWitness, DecodeError> {
+    Ok(TransactionWitness {
+        inputs: reader.list(Reader::bytes)?,
+    })
+}
+
+/// A full transaction: `encode_tx_core || encode_tx_witness`. The core prefix is what
+/// [`Transaction::tx_id`] hashes.
+pub fn encode_transaction(tx: &Transaction) -> Vec<u8> {
+    let mut out = Vec::new();
+    encode_transaction_into(tx, &mut out);
+    out
+}
+
+fn encode_transaction_into(tx: &Transaction, out: &mut Vec<u8>) {
+    encode_tx_core_into(&tx.core, out);
+    encode_tx_witness_into(&tx.witness, out);
+}
+
+pub fn decode_transaction(bytes: &[u8]) -> Result<Transaction, DecodeError> {
+    decode_all(bytes, read_transaction)
+}
+
+fn read_transaction(reader: &mut Reader) -> Result<Transaction, DecodeError> {
+    Ok(Transaction {
+        core: read_tx_core(reader)?,
+        witness: read_tx_witness(reader)?,
+    })
+}
+
+/// `magic || encoding version || script version || script || stack || metadata ||
+/// merkle path`, with `u32` length prefixes and `0`/`1` tags for the optional fields.
+pub fn encode_input_witness(witness: &InputWitness) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(WITNESS_MAGIC);
+    out.push(WITNESS_ENCODING_VERSION);
+    out.push(witness.script_version);
+    encode_bytes(&witness.script, &mut out);
+
+    encode_len(witness.stack.len(), &mut out);
+    for item in &witness.stack {
+        encode_bytes(item, &mut out);
+    }
+
+    match &witness.metadata {
+        Some(metadata) => {
+            out.push(1);
+            encode_bytes(metadata, &mut out);
+        }
+        None => out.push(0),
+    }
+
+    match &witness.merkle_path {
+        Some(path) => {
+            out.push(1);
+            encode_len(path.len(), &mut out);
+            for hash in path {
+                encode_hash(hash, &mut out);
+            }
+        }
+        None => out.push(0),
+    }
+    out
+}
+
+/// Inverse of [`encode_input_witness`]; rejects any bytes it would not produce.
+pub fn decode_input_witness(bytes: &[u8]) -> Result<InputWitness, DecodeError> {
+    let mut reader = Reader::new(bytes);
+    reader.magic(WITNESS_MAGIC)?;
+    let encoding_version = reader.u8()?;
+    if encoding_version != WITNESS_ENCODING_VERSION {
+        return Err(DecodeError::UnsupportedVersion(encoding_version.into()));
+    }
+
+    let script_version = reader.u8()?;
+    let script = reader.bytes()?;
+    let stack = reader.list(Reader::bytes)?;
+    let metadata = if reader.present("metadata")? {
+        Some(reader.bytes()?)
+    } else {
+        None
+    };
+    let merkle_path = if reader.present("merkle path")? {
+        Some(reader.list(Reader::hash)?)
+    } else {
+        None
+    };
+    reader.finish()?;
+
+    Ok(InputWitness {
+        script_version,
+        script,
+        stack,
+        metadata,
+        merkle_path,
+    })
+}
+
+/// Legacy headers keep the layout they were signed with. Versioned headers start with
+/// the version and encode every field, so no field can change without the signature.
+pub fn encode_block_header(header: &BlockHeader) -> Vec<u8> {
+    let mut out = Vec::new();
+    if header.version == BLOCK_HEADER_VERSION_LEGACY {
+        encode_header_core(header, &mut out);
+        if header.commits_witnesses() {
+            encode_hash(&header.witness_root, &mut out);
+        }
+        return out;
+    }
+
+    out.extend_from_slice(&header.version.to_le_bytes());
+    encode_header_core(header, &mut out);
+    encode_hash(&header.witness_root, &mut out);
+    encode_hash(&header.proposer_id, &mut out);
+    encode_hash(&header.validator_set_hash, &mut out);
+    encode_hash(&header.consensus_params_hash, &mut out);
+    out
+}
+
+fn encode_header_core(header: &BlockHeader, out: &mut Vec<u8>) {
+    encode_hash(&header.parent_hash, out);
+    encode_hash(&header.state_root, out);
+    encode_hash(&header.tx_root, out);
+    out.extend_from_slice(&header.height.to_le_bytes());
+    out.extend_from_slice(&header.timestamp.to_le_bytes());
+}
+
+/// Inverse of [`encode_block_header`]. The layout is told apart by length: legacy headers
+/// are 112 bytes, or 144 with a witness root, and versioned headers are longer. Any
+/// non-zero version is read with the version 1 layout; consensus decides which versions
+/// are valid.
+pub fn decode_block_header(bytes: &[u8]) -> Result<BlockHeader, DecodeError> {
+    decode_all(bytes, |reader| {
+        let legacy = matches!(bytes.len(), LEGACY_HEADER_LEN | LEGACY_WITNESS_HEADER_LEN);
+        let version = if legacy {
+            BLOCK_HEADER_VERSION_LEGACY
+        } else {
+            match reader.u32()? {
+                BLOCK_HEADER_VERSION_LEGACY => {
+                    return Err(DecodeError::NonCanonical(
+                        "legacy header with version prefix",
+                    ))
+                }
+                version => version,
+            }
+        };
+
+        let mut header = BlockHeader {
+            version,
+            parent_hash: reader.hash()?,
+            state_root: reader.hash()?,
+            tx_root: reader.hash()?,
+            height: reader.u64()?,
+            timestamp: reader.u64()?,
+            witness_root: LEGACY_WITNESS_ROOT,
+            proposer_id: Hash256::default(),
+            validator_set_hash: Hash256::default(),
+            consensus_params_hash: Hash256::default(),
+        };
+        if legacy {
+            if bytes.len() == LEGACY_WITNESS_HEADER_LEN {
+                header.witness_root = reader.hash()?;
+                if !header.commits_witnesses() {
+                    return Err(DecodeError::NonCanonical("encoded legacy witness root"));
+                }
+            }
+        } else {
+            header.witness_root = reader.hash()?;
+            header.proposer_id = reader.hash()?;
+            header.validator_set_hash = reader.hash()?;
+            header.consensus_params_hash = reader.hash()?;
+        }
+        Ok(header)
+    })
+}
+
+fn encode_keyed_bytes(scheme: SignatureSchemeId, bytes: &[u8], out: &mut Vec<u8>) {
+    out.extend_from_slice(&scheme.to_u16().to_le_bytes());
+    encode_bytes(bytes, out);
+}
+
+fn read_keyed_bytes(reader: &mut Reader) -> Result<(SignatureSchemeId, Vec<u8>), DecodeError> {
+    let id = reader.u16()?;
+    let scheme = SignatureSchemeId::from_u16(id);
+    Ok((scheme, reader.bytes()?))
+}
+
+/// A full block:
+///
+/// ```text
+/// magic "QBLK" | encoding version (u8) | u32 len + encode_block_header
+/// | u32 count + encode_transaction* | proposer key | signature
+/// ```
+///
+/// Keys and signatures are `scheme id (u16) || u32 len || bytes`. The header bytes are
+/// exactly what the proposer signed and what the block hash covers.
+pub fn encode_block(block: &Block) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(BLOCK_MAGIC);
+    out.push(BLOCK_ENCODING_VERSION);
+    encode_bytes(&encode_block_header(&block.header), &mut out);
+    encode_len(block.transactions.len(), &mut out);
+    for tx in &block.transactions {
+        encode_transaction_into(tx, &mut out);
+    }
+    encode_keyed_bytes(
+        block.proposer_public_key.scheme,
+        &block.proposer_public_key.bytes,
+        &mut out,
+    );
+    encode_keyed_bytes(block.signature.scheme, &block.signature.bytes, &mut out);
+    out
+}
+
+/// Inverse of [`encode_block`]. Key and signature sizes are not checked here; signature
+/// verification rejects malformed ones.
+pub fn decode_block(bytes: &[u8]) -> Result<Block, DecodeError> {
+    decode_all(bytes, |reader| {
+        reader.magic(BLOCK_MAGIC)?;
+        let encoding_version = reader.u8()?;
+        if encoding_version != BLOCK_ENCODING_VERSION {
+            return Err(DecodeError::UnsupportedVersion(encoding_version.into()));
+        }
+
+        let header = decode_block_header(&reader.bytes()?)?;
+        let transactions = reader.list(read_transaction)?;
+        let (scheme, key) = read_keyed_bytes(reader)?;
+        let proposer_public_key = PublicKey { scheme, bytes: key };
+        let (scheme, signature) = read_keyed_bytes(reader)?;
+        Ok(Block {
+            header,
+            transactions,
+            proposer_public_key,
+            signature: Signature {
+                scheme,
+                bytes: signature,
+            },
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BLOCK_HEADER_VERSION, QCOIN_ASSET_ID};
+
+    fn definition() -> AssetDefinition {
+        AssetDefinition {
+            issuer_script_hash: [1u8; 32],
+            metadata_root: [2u8; 32],
+            max_supply: Some(1_000),
+            decimals: 3,
+            kind: AssetKind::SemiFungible,
+            transfer_policy: TransferPolicy::default(),
+            issuer_controls: IssuerControls::default(),
+            royalty: None,
+        }
+    }
+
+    fn transaction(kind: TransactionKind) -> Transaction {
+        Transaction {
+            core: TransactionCore {
+                kind,
+                inputs: vec![TransactionInput {
+                    tx_id: [3u8; 32],
+                    index: 7,
+                }],
+                outputs: vec![
+                    Output {
+                        owner_script_hash: [4u8; 32],
+                        assets: vec![AssetAmount {
+                            asset_id: QCOIN_ASSET_ID,
+                            amount: u128::MAX,
+                        }],
+                        metadata_hash: Some([5u8; 32]),
+                    },
+                    Output {
+                        owner_script_hash: [6u8; 32],
+                        assets: Vec::new(),
+                        metadata_hash: None,
+                    },
+                ],
+                valid_from_height: None,
+                valid_until_height: None,
+            },
+            witness: TransactionWitness {
+                inputs: vec![vec![9, 9], Vec::new()],
+            },
+        }
+    }
+
+    fn block(header: BlockHeader) -> Block {
+        Block {
+            header,
+            transactions: vec![
+                transaction(TransactionKind::Transfer),
+                transaction(TransactionKind::CreateAsset {
+                    definition: definition(),
+                    initial_supply: 10,
+                    sub_ids: Vec::new(),
+                }),
+            ],
+            proposer_public_key: PublicKey {
+                scheme: SignatureSchemeId::Falcon512,
+                bytes: vec![7u8; 5],
+            },
+            signature: Signature {
+                scheme: SignatureSchemeId::Falcon512,
+                bytes: vec![8u8; 6],
+            },
+        }
+    }
+
+    fn header(version: u32, witness_root: Hash256) -> BlockHeader {
+        BlockHeader {
+            version,
+            parent_hash: [1u8; 32],
+            state_root: [2u8; 32],
+            tx_root: [3u8; 32],
+            height: 4,
+            timestamp: 5,
+            witness_root,
+            proposer_id: Hash256::default(),
+            validator_set_hash: Hash256::default(),
+            consensus_params_hash: Hash256::default(),
+        }
+    }
+
+    #[test]
+    fn every_encoder_round_trips() {
+        let definition = definition();
+        let bytes = encode_asset_definition(&definition);
+        assert_eq!(decode_asset_definition(&bytes), Ok(definition.clone()));
+        let soulbound = AssetDefinition {
+            transfer_policy: TransferPolicy {
+                mode: TransferMode::Soulbound,
+                burnable_by_holder: true,
+            },
+            ..definition.clone()
+        };
+        let bytes = encode_asset_definition(&soulbound);
+        assert_eq!(bytes[32], 2 | ASSET_TRANSFER_POLICY_FLAG);
+        assert_eq!(decode_asset_definition(&bytes), Ok(soulbound.clone()));
+        for controls in [
+            IssuerControls {
+                freeze: true,
+                clawback: false,
+            },
+            IssuerControls {
+                freeze: true,
+                clawback: true,
+            },
+        ] {
+            for base in [&definition, &soulbound] {
+                let controlled = AssetDefinition {
+                    issuer_controls: controls.clone(),
+                    ..base.clone()
+                };
+                let bytes = encode_asset_definition(&controlled);
+                assert_ne!(bytes[32] & ASSET_ISSUER_CONTROLS_FLAG, 0);
+                assert_eq!(decode_asset_definition(&bytes), Ok(controlled));
+            }
+        }
+        for amount in [RoyaltyAmount::BasisPoints(250), RoyaltyAmount::Fixed(7)] {
+            let royalty = AssetDefinition {
+                royalty: Some(RoyaltyRule {
+                    recipient_script_hash: [9u8; 32],
+                    payment_asset_id: QCOIN_ASSET_ID,
+                    amount,
+                }),
+                ..soulbound.clone()
+            };
+            let bytes = encode_asset_definition(&royalty);
+            assert_eq!(
+                bytes[32],
+                2 | ASSET_TRANSFER_POLICY_FLAG | ASSET_ROYALTY_FLAG
+            );
+            assert_eq!(decode_asset_definition(&bytes), Ok(royalty));
+        }
+
+        let create = transaction(TransactionKind::CreateAsset {
+            definition: definition.clone(),
+            initial_supply: 10,
+            sub_ids: Vec::new(),
+        });
+        let semi_fungible = transaction(TransactionKind::CreateAsset {
+            definition: definition.clone(),
+            initial_supply: 10,
+            sub_ids: vec![1, 7],
+        });
+        let collection = CollectionDefinition {
+            issuer_script_hash: [1u8; 32],
+            metadata_root: [4u8; 32],
+        };
+        assert_eq!(
+            decode_collection_definition(&encode_collection_definition(&collection)).as_ref(),
+            Ok(&collection)
+        );
+        let create_collection = transaction(TransactionKind::CreateCollection { collection });
+        let create_item = transaction(TransactionKind::CreateCollectionItem {
+            collection_id: CollectionId([5u8; 32]),
+            serial: 12,
+            definition,
+            initial_supply: 1,
+        });
+        let targets = vec![
+            TransactionInput {
+                tx_id: [6u8; 32],
+                index: 2,
+            },
+            TransactionInput {
+                tx_id: [7u8; 32],
+                index: 0,
+            },
+        ];
+        let freeze = transaction(TransactionKind::FreezeOutputs {
+            asset_id: AssetId([8u8; 32]),
+            targets: targets.clone(),
+        });
+        let unfreeze = transaction(TransactionKind::UnfreezeOutputs {
+            asset_id: AssetId([8u8; 32]),
+            targets: targets.clone(),
+        });
+        let clawback = transaction(TransactionKind::Clawback {
+            asset_id: AssetId([8u8; 32]),
+            targets,
+        });
+        let update_metadata = transaction(TransactionKind::UpdateAssetMetadata {
+            asset_id: AssetId([8u8; 32]),
+            metadata_root: [9u8; 32],
+        });
+        let sale = transaction(TransactionKind::Sale {
+            prices: vec![
+                SalePrice {
+                    asset_id: AssetId([8u8; 32]),
+                    price: 950,
+                },
+                SalePrice {
+                    asset_id: AssetId([9u8; 32]),
+                    price: 0,
+                },
+            ],
+        });
+        for output in &create.core.outputs {
+            assert_eq!(decode_output(&encode_output(output)).as_ref(), Ok(output));
+        }
+        let transfer = transaction(TransactionKind::Transfer);
+        let mut windowed = create.clone();
+        windowed.core.valid_until_height = Some(40);
+        let mut not_before = transfer.clone();
+        not_before.core.valid_from_height = Some(u64::MAX);
+        for tx in [
+            &transfer,
+            &create,
+            &semi_fungible,
+            &create_collection,
+            &create_item,
+            &freeze,
+            &unfreeze,
+            &clawback,
+            &update_metadata,
+            &sale,
+            &windowed,
+            &not_before,
+        ] {
+            assert_eq!(
+                decode_tx_core(&encode_tx_core(&tx.core)).as_ref(),
+                Ok(&tx.core)
+            );
+            assert_eq!(
+                decode_tx_witness(&encode_tx_witness(&tx.witness)).as_ref(),
+                Ok(&tx.witness)
+            );
+            let bytes = encode_transaction(tx);
+            assert!(bytes.starts_with(&encode_tx_core(&tx.core)));
+            assert_eq!(decode_transaction(&bytes).as_ref(), Ok(tx));
+        }
+
+        let mut versioned = header(BLOCK_HEADER_VERSION, [6u8; 32]);
+        versioned.proposer_id = [7u8; 32];
+        versioned.validator_set_hash = [8u8; 32];
+        versioned.consensus_params_hash = [9u8; 32];
+        for header in [
+            header(BLOCK_HEADER_VERSION_LEGACY, LEGACY_WITNESS_ROOT),
+            header(BLOCK_HEADER_VERSION_LEGACY, [6u8; 32]),
+            versioned,
+        ] {
+            let bytes = encode_block_header(&header);
+            assert_eq!(decode_block_header(&bytes).as_ref(), Ok(&header));
+
+            let block = block(header);
+            let bytes = encode_block(&block);
+            assert_eq!(&bytes[..4], BLOCK_MAGIC);
+            assert_eq!(decode_block(&bytes), Ok(block));
+        }
+    }
+
+    #[test]
+    fn decoders_reject_non_canonical_bytes() {
+        let tx = transaction(TransactionKind::Transfer);
+        let bytes = encode_transaction(&tx);
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            decode_transaction(&trailing),
+            Err(DecodeError::TrailingBytes(1))
+        );
+        for len in 0..bytes.len() {
+            assert_eq!(
+                decode_transaction(&bytes[..len]),
+                Err(DecodeError::UnexpectedEnd)
+            );
+        }
+
+        let mut bad_kind = bytes.clone();
+        bad_kind[0] = 10;
+        assert_eq!(
+            decode_transaction(&bad_kind),
+            Err(DecodeError::InvalidTag {
+                field: "transaction kind",
+                tag: 10,
+            })
+        );
+
+        // Sale prices are listed once each, by ascending asset id.
+        let sale_price = |byte| SalePrice {
+            asset_id: AssetId([byte; 32]),
+            price: 1,
+        };
+        for (prices, reason) in [
+            (Vec::new(), "empty sale price list"),
+            (vec![sale_price(2), sale_price(1)], "unsorted sale prices"),
+            (vec![sale_price(1), sale_price(1)], "unsorted sale prices"),
+        ] {
+            assert_eq!(
+                decode_tx_core(&encode_tx_core(
+                    &transaction(TransactionKind::Sale { prices }).core
+                )),
+                Err(DecodeError::NonCanonical(reason))
+            );
+        }
+
+        // Sub-ids are only encoded when there are some.
+        let mut empty_sub_ids = encode_tx_core(
+            &transaction(TransactionKind::CreateAsset {
+                definition: definition(),
+                initial_supply: 10,
+                sub_ids: Vec::new(),
+            })
+            .core,
+        );
+        empty_sub_ids[0] = 2;
+        empty_sub_ids.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(
+            decode_tx_core(&empty_sub_ids),
+            Err(DecodeError::NonCanonical("empty sub-id list"))
+        );
+
+        // The window flag without either bound has a shorter canonical encoding.
+        let mut empty_window = encode_tx_core(&tx.core);
+        empty_window[0] |= TX_VALIDITY_WINDOW_FLAG;
+        empty_window.extend_from_slice(&[0, 0]);
+        assert_eq!(
+            decode_tx_core(&empty_window),
+            Err(DecodeError::NonCanonical("empty validity window"))
+        );
+
+        let mut output = encode_output(&tx.core.outputs[1]);
+        *output.last_mut().unwrap() = 2;
+        assert!(matches!(
+            decode_output(&output),
+            Err(DecodeError::InvalidTag { tag: 2, .. })
+        ));
+
+        let mut definition = encode_asset_definition(&definition());
+        definition[32] = 3;
+        assert!(matches!(
+            decode_asset_definition(&definition),
+            Err(DecodeError::InvalidTag { tag: 3, .. })
+        ));
+        // A flagged definition must carry a policy other than the default.
+        let mut default_policy = encode_asset_definition(&self::definition());
+        default_policy[32] |= ASSET_TRANSFER_POLICY_FLAG;
+        default_policy.extend_from_slice(&[0, 0]);
+        assert!(matches!(
+            decode_asset_definition(&default_policy),
+            Err(DecodeError::NonCanonical(_))
+        ));
+        let mut controls = encode_asset_definition(&self::definition());
+        controls[32] |= ASSET_ISSUER_CONTROLS_FLAG;
+        controls.push(0);
+        assert!(matches!(
+            decode_asset_definition(&controls),
+            Err(DecodeError::NonCanonical(_))
+        ));
+        *controls.last_mut().unwrap() = 0x04;
+        assert!(matches!(
+            decode_asset_definition(&controls),
+            Err(DecodeError::InvalidTag { tag: 4, .. })
+        ));
+
+        // A legacy header may only carry a witness root that is set.
+        let mut zero_root = encode_block_header(&header(BLOCK_HEADER_VERSION_LEGACY, [0u8; 32]));
+        zero_root.extend_from_slice(&[0u8; 32]);
+        assert!(matches!(
+            decode_block_header(&zero_root),
+            Err(DecodeError::NonCanonical(_))
+        ));
+        // A versioned layout may not carry version 0.
+        let mut versioned = encode_block_header(&header(BLOCK_HEADER_VERSION, [6u8; 32]));
+        versioned[..4].copy_from_slice(&0u32.to_le_bytes());
+        assert!(matches!(
+            decode_block_header(&versioned),
+            Err(DecodeError::NonCanonical(_))
+        ));
+
+        let block_bytes = encode_block(&block(header(BLOCK_HEADER_VERSION, [6u8; 32])));
+        let mut bad_magic = block_bytes.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(decode_block(&bad_magic), Err(DecodeError::MissingMagic));
+        let mut bad_version = block_bytes;
+        bad_version[4] = 2;
+        assert_eq!(
+            decode_block(&bad_version),
+            Err(DecodeError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn list_counts_do_not_drive_preallocation() {
+        let body = [0u8; 1_024];
+        let reader = Reader::new(&body);
+        let capacity = reader.list_capacity::<Transaction>(u32::MAX as usize);
+        assert!(capacity * std::mem::size_of::<Transaction>() <= body.len());
+        assert_eq!(reader.list_capacity::<u8>(16), 16);
+
+        let mut claimed = BLOCK_MAGIC.to_vec();
+        claimed.push(1);
+        let header_bytes = encode_block_header(&header(BLOCK_HEADER_VERSION, [6u8; 32]));
+        encode_len(header_bytes.len(), &mut claimed);
+        claimed.extend(header_bytes);
+        claimed.extend_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(decode_block(&claimed), Err(DecodeError::UnexpectedEnd));
+    }
+}
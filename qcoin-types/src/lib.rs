@@ -5,11 +5,110 @@ pub type Hash256 = [u8; 32];
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct BlockHeader {
+    /// Header format, see [`BLOCK_HEADER_VERSION`]. Headers from before versioning
+    /// deserialize as [`BLOCK_HEADER_VERSION_LEGACY`].
+    #[serde(default)]
+    pub version: u32,
     pub parent_hash: Hash256,
     pub state_root: Hash256,
     pub tx_root: Hash256,
     pub height: u64,
     pub timestamp: u64,
+    /// Commitment to the witness ids of the block's transactions, or
+    /// [`LEGACY_WITNESS_ROOT`] in headers from before witnesses were committed.
+    #[serde(default)]
+    pub witness_root: Hash256,
+    /// [`validator_id`] of the proposer key that signs this header.
+    #[serde(default)]
+    pub proposer_id: Hash256,
+    /// [`validator_set_hash`] of the validator set the proposer was selected from.
+    #[serde(default)]
+    pub validator_set_hash: Hash256,
+    /// Hash of the consensus parameters the block was produced under.
+    #[serde(default)]
+    pub consensus_params_hash: Hash256,
+}
+
+/// Headers from before versioning. Their encoding stops at `timestamp`, plus the
+/// witness root when one is set; the later fields must be zero.
+pub const BLOCK_HEADER_VERSION_LEGACY: u32 = 0;
+/// The version new headers are produced with. Every field is encoded.
+pub const BLOCK_HEADER_VERSION: u32 = 2;
+/// First header version whose state root commits to per-asset supply counters. It has
+/// the same layout as version 1.
+pub const BLOCK_HEADER_VERSION_SUPPLY: u32 = 2;
+
+/// `witness_root` of headers that do not commit to witnesses. It is left out of the
+/// header encoding, so those headers keep the hash and signature they were created with.
+pub const LEGACY_WITNESS_ROOT: Hash256 = [0u8; 32];
+
+/// Heights at which consensus rule changes activate on a chain. A chain started after a
+/// rule existed runs it from genesis; chains with earlier history list their own heights
+/// so their stored blocks keep replaying.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainActivations {
+    /// Signatures are charged by scheme, see `GasSchedule::per_scheme_sig_verify`.
+    pub per_scheme_sig_verify: u64,
+    /// `CreateAsset` follows the rules of its asset kind.
+    pub asset_kind_rules: u64,
+}
+
+impl ChainActivations {
+    /// Every rule active from genesis.
+    pub const GENESIS: Self = Self {
+        per_scheme_sig_verify: 0,
+        asset_kind_rules: 0,
+    };
+
+    pub fn for_chain(chain_id: u32) -> Self {
+        CHAINS_WITH_HISTORY
+            .iter()
+            .find(|(id, _)| *id == chain_id)
+            .map_or(Self::GENESIS, |(_, activations)| *activations)
+    }
+}
+
+/// Chains with blocks produced before the rules in [`ChainActivations`] existed.
+const CHAINS_WITH_HISTORY: &[(u32, ChainActivations)] = &[(
+    0,
+    ChainActivations {
+        per_scheme_sig_verify: 100_000,
+        asset_kind_rules: 100_000,
+    },
+)];
+
+impl BlockHeader {
+    pub fn commits_witnesses(&self) -> bool {
+        self.witness_root != LEGACY_WITNESS_ROOT
+    }
+}
+
+/// Identifies a validator key: `blake3("QCOIN_VALIDATOR_ID_V1" || scheme || len || key)`.
+pub fn validator_id(public_key: &PublicKey) -> Hash256 {
+    let mut preimage = Vec::new();
+    const DOMAIN_SEPARATOR: &[u8] = b"QCOIN_VALIDATOR_ID_V1";
+
+    preimage.extend_from_slice(DOMAIN_SEPARATOR);
+    preimage.extend_from_slice(&public_key.scheme.to_u16().to_le_bytes());
+    preimage.extend_from_slice(&(public_key.bytes.len() as u32).to_le_bytes());
+    preimage.extend_from_slice(&public_key.bytes);
+
+    *blake3::hash(&preimage).as_bytes()
+}
+
+/// Commits to an ordered validator set. Order is part of the commitment because it
+/// decides proposer selection.
+pub fn validator_set_hash(validators: &[PublicKey]) -> Hash256 {
+    let mut preimage = Vec::new();
+    const DOMAIN_SEPARATOR: &[u8] = b"QCOIN_VALIDATOR_SET_V1";
+
+    preimage.extend_from_slice(DOMAIN_SEPARATOR);
+    preimage.extend_from_slice(&(validators.len() as u32).to_le_bytes());
+    for validator in validators {
+        preimage.extend_from_slice(&validator_id(validator));
+    }
+
+    *blake3::hash(&preimage).as_bytes()
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -52,6 +151,103 @@ pub fn is_qcoin_asset_id(asset_id: &AssetId) -> bool {
     asset_id.0 == QCOIN_ASSET_ID.0
 }
 
+/// Whether units of an existing asset may move to a different owner script.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferMode {
+    #[default]
+    Transferable,
+    /// Units stay with the owner script they were minted to.
+    Soulbound,
+    /// Units change owner only in transactions that also spend an input owned by the
+    /// asset's issuer.
+    IssuerApproved,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferPolicy {
+    #[serde(default)]
+    pub mode: TransferMode,
+    /// Holders may destroy units by spending them without a matching output.
+    #[serde(default)]
+    pub burnable_by_holder: bool,
+}
+
+impl TransferPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Issuer powers over outputs holding an asset. Both are off unless enabled at creation.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IssuerControls {
+    /// The issuer may freeze and unfreeze outputs holding the asset.
+    #[serde(default)]
+    pub freeze: bool,
+    /// The issuer may reclaim the asset from outputs holding only the asset.
+    #[serde(default)]
+    pub clawback: bool,
+}
+
+impl IssuerControls {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// Largest [`RoyaltyAmount::BasisPoints`] value: the whole sale price.
+pub const MAX_ROYALTY_BASIS_POINTS: u16 = 10_000;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RoyaltyAmount {
+    /// Hundredths of a percent of the sale price, rounded up.
+    BasisPoints(u16),
+    /// A fixed amount per transaction in which the asset changes owner.
+    Fixed(u128),
+}
+
+/// Payment owed to `recipient_script_hash`, in `payment_asset_id`, whenever units of the
+/// asset change owner. A percentage is charged on the price declared by the
+/// [`TransactionKind::Sale`] that moves the asset.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoyaltyRule {
+    pub recipient_script_hash: Hash256,
+    /// QCOIN ([`QCOIN_ASSET_ID`]) or any other asset.
+    pub payment_asset_id: AssetId,
+    pub amount: RoyaltyAmount,
+}
+
+impl RoyaltyRule {
+    /// Royalty due for a sale at `sale_price` units of the payment asset.
+    pub fn required_payment(&self, sale_price: u128) -> u128 {
+        match self.amount {
+            RoyaltyAmount::BasisPoints(basis_points) => {
+                // Split the price so the product cannot overflow.
+                let basis_points = u128::from(basis_points);
+                let scale = u128::from(MAX_ROYALTY_BASIS_POINTS);
+                (sale_price / scale)
+                    .saturating_mul(basis_points)
+                    .saturating_add((sale_price % scale * basis_points).div_ceil(scale))
+            }
+            RoyaltyAmount::Fixed(amount) => amount,
+        }
+    }
+
+    /// The output a wallet adds to a transfer at `sale_price` to pay the royalty, or
+    /// `None` when nothing is due.
+    pub fn payment_output(&self, sale_price: u128) -> Option<Output> {
+        let amount = self.required_payment(sale_price);
+        (amount > 0).then(|| Output {
+            owner_script_hash: self.recipient_script_hash,
+            assets: vec![AssetAmount {
+                asset_id: self.payment_asset_id.clone(),
+                amount,
+            }],
+            metadata_hash: None,
+        })
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AssetDefinition {
     pub issuer_script_hash: Hash256,
@@ -59,6 +255,12 @@ pub struct AssetDefinition {
     pub max_supply: Option<u128>,
     pub decimals: u8,
     pub kind: AssetKind,
+    #[serde(default)]
+    pub transfer_policy: TransferPolicy,
+    #[serde(default)]
+    pub issuer_controls: IssuerControls,
+    #[serde(default)]
+    pub royalty: Option<RoyaltyRule>,
 }
 
 pub fn derive_asset_id(definition: &AssetDefinition, chain_id: u32) -> AssetId {
@@ -72,6 +274,54 @@ pub fn derive_asset_id(definition: &AssetDefinition, chain_id: u32) -> AssetId {
     AssetId(*blake3::hash(&preimage).as_bytes())
 }
 
+/// Issuer policy and metadata shared by a family of assets, such as a game's item set.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CollectionDefinition {
+    /// Every item of the collection must be created by, and name, this issuer.
+    pub issuer_script_hash: Hash256,
+    pub metadata_root: Hash256,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct CollectionId(pub Hash256);
+
+pub fn derive_collection_id(collection: &CollectionDefinition, chain_id: u32) -> CollectionId {
+    let mut preimage = Vec::new();
+    const DOMAIN_SEPARATOR: &[u8] = b"QCOIN_COLLECTION_ID_V1";
+
+    preimage.extend_from_slice(DOMAIN_SEPARATOR);
+    preimage.extend_from_slice(&chain_id.to_le_bytes());
+    preimage.extend(consensus_codec::encode_collection_definition(collection));
+
+    CollectionId(*blake3::hash(&preimage).as_bytes())
+}
+
+/// Asset id of item `serial` in a collection. It depends only on the collection and the
+/// serial, so each serial can be created once.
+pub fn derive_collection_item_id(collection_id: &CollectionId, serial: u64) -> AssetId {
+    let mut preimage = Vec::new();
+    const DOMAIN_SEPARATOR: &[u8] = b"QCOIN_COLLECTION_ITEM_ID_V1";
+
+    preimage.extend_from_slice(DOMAIN_SEPARATOR);
+    preimage.extend_from_slice(&collection_id.0);
+    preimage.extend_from_slice(&serial.to_le_bytes());
+
+    AssetId(*blake3::hash(&preimage).as_bytes())
+}
+
+/// Id of the `sub_id` units within the `SemiFungible` class `class_id`. Units with the
+/// same sub-id are interchangeable; units with different sub-ids are not.
+pub fn derive_sub_asset_id(class_id: &AssetId, sub_id: u64) -> AssetId {
+    let mut preimage = Vec::new();
+    const DOMAIN_SEPARATOR: &[u8] = b"QCOIN_SUB_ASSET_ID_V1";
+
+    preimage.extend_from_slice(DOMAIN_SEPARATOR);
+    preimage.extend_from_slice(&class_id.0);
+    preimage.extend_from_slice(&sub_id.to_le_bytes());
+
+    AssetId(*blake3::hash(&preimage).as_bytes())
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AssetAmount {
     pub asset_id: AssetId,
@@ -97,20 +347,112 @@ pub enum TransactionKind {
     CreateAsset {
         definition: AssetDefinition,
         initial_supply: u128,
+        /// Sub-identifiers minted under a `SemiFungible` class, in ascending order. Each
+        /// one is its own asset, [`derive_sub_asset_id`]; `initial_supply` is their total.
+        #[serde(default)]
+        sub_ids: Vec<u64>,
+    },
+    /// Registers a collection under [`derive_collection_id`]. Authorized like
+    /// `CreateAsset`, by spending an output of the collection's issuer.
+    CreateCollection {
+        collection: CollectionDefinition,
+    },
+    /// Creates item `serial` of a registered collection: an asset with id
+    /// [`derive_collection_item_id`], whose definition names the collection's issuer.
+    CreateCollectionItem {
+        collection_id: CollectionId,
+        serial: u64,
+        definition: AssetDefinition,
+        initial_supply: u128,
+    },
+    /// Freezes `targets`, unspent outputs holding `asset_id`, so they cannot be spent.
+    /// Needs the asset's `freeze` control and an input owned by its issuer.
+    FreezeOutputs {
+        asset_id: AssetId,
+        targets: Vec<TransactionInput>,
+    },
+    /// Lifts a freeze placed on `targets` by `FreezeOutputs` for `asset_id`.
+    UnfreezeOutputs {
+        asset_id: AssetId,
+        targets: Vec<TransactionInput>,
+    },
+    /// Spends `targets`, outputs holding only `asset_id`, without their owners' scripts.
+    /// Their units count as inputs of this transaction. Needs the asset's `clawback`
+    /// control and an input owned by its issuer.
+    Clawback {
+        asset_id: AssetId,
+        targets: Vec<TransactionInput>,
+    },
+    /// Sets the current metadata root of `asset_id`. The definition's `metadata_root`,
+    /// and so the asset id, never change. Needs an input owned by the asset's issuer.
+    UpdateAssetMetadata {
+        asset_id: AssetId,
+        metadata_root: Hash256,
+    },
+    /// A transfer that declares the sale price of each asset it sells under a
+    /// percentage royalty. Prices are in the royalty's payment asset, sorted by
+    /// asset id, and committed to by every signature.
+    Sale {
+        prices: Vec<SalePrice>,
     },
     // later: MintAsset, BurnAsset, etc.
 }
 
+/// The price a [`TransactionKind::Sale`] declares for `asset_id`, in units of its
+/// royalty's payment asset.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SalePrice {
+    pub asset_id: AssetId,
+    pub price: u128,
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TransactionCore {
     pub kind: TransactionKind,
     pub inputs: Vec<TransactionInput>,
     pub outputs: Vec<Output>,
+    /// First block height the transaction may be included at.
+    #[serde(default)]
+    pub valid_from_height: Option<u64>,
+    /// Last block height the transaction may be included at; mempools evict it after.
+    #[serde(default)]
+    pub valid_until_height: Option<u64>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct TransactionWitness {
-    pub inputs: Vec<Vec<u8>>, // raw script/witness data for now
+    /// One encoded witness per input. New witnesses use [`InputWitness::encode`]; the
+    /// ledger still accepts the baseline `{script, metadata}` bincode layout.
+    pub inputs: Vec<Vec<u8>>,
+}
+
+/// Everything needed to satisfy one input: the script revealed for the spent output and
+/// the data that script reads.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+pub struct InputWitness {
+    /// Script version; 0 is the legacy opcode format.
+    pub script_version: u8,
+    /// The script body as committed to by the output: for version 0, the consensus
+    /// encoding of its opcodes.
+    pub script: Vec<u8>,
+    /// Items read by `PushWitness`, such as signatures and hash preimages.
+    pub stack: Vec<Vec<u8>>,
+    /// Metadata whose hash must match the spent output's `metadata_hash`.
+    pub metadata: Option<Vec<u8>>,
+    /// Sibling hashes proving `script` is a leaf of the script tree committed to by the
+    /// spent output. `None` means the output commits to `script` directly.
+    pub merkle_path: Option<Vec<Hash256>>,
+}
+
+impl InputWitness {
+    /// Canonical encoding, see [`consensus_codec::encode_input_witness`].
+    pub fn encode(&self) -> Vec<u8> {
+        consensus_codec::encode_input_witness(self)
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self, consensus_codec::DecodeError> {
+        consensus_codec::decode_input_witness(bytes)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -124,6 +466,24 @@ impl Transaction {
         self.core.tx_id()
     }
 
+    /// Id over the core and the witness. Unlike [`Transaction::tx_id`], it changes when a
+    /// signature, stack item or revealed metadata changes.
+    pub fn witness_tx_id(&self) -> Hash256 {
+        let mut preimage = Vec::new();
+        const DOMAIN_SEPARATOR: &[u8] = b"QCOIN_WTXID_V1";
+
+        preimage.extend_from_slice(DOMAIN_SEPARATOR);
+        preimage.extend_from_slice(&self.tx_id());
+        preimage.extend(consensus_codec::encode_tx_witness(&self.witness));
+
+        *blake3::hash(&preimage).as_bytes()
+    }
+
+    /// Signature hash for `input_index`. `flags` selects which inputs and outputs are
+    /// committed; [`SighashFlags::ALL`] commits the whole transaction core.
+    ///
+    /// With [`SighashFlags::SINGLE`] and no output at `input_index`, no output is committed;
+    /// verifiers must reject that combination rather than accept such a signature.
     pub fn sighash(
         &self,
         input_index: usize,
@@ -137,14 +497,61 @@ impl Transaction {
 
         preimage.extend_from_slice(DOMAIN_SEPARATOR);
         preimage.extend_from_slice(&chain_id.to_le_bytes());
-        preimage.extend(consensus_codec::encode_tx_core(&self.core));
+        if flags == SighashFlags::ALL {
+            preimage.extend(consensus_codec::encode_tx_core(&self.core));
+        } else {
+            preimage.extend(consensus_codec::encode_tx_core(
+                &self.sighash_core(input_index, flags),
+            ));
+        }
         preimage.extend(consensus_codec::encode_output(prev_output));
-        preimage.extend_from_slice(&(input_index as u64).to_le_bytes());
+        // Under ANYONECANPAY other inputs may be added around this one, so its position
+        // is not committed.
+        let committed_index = if flags.anyone_can_pay() {
+            0
+        } else {
+            input_index as u64
+        };
+        preimage.extend_from_slice(&committed_index.to_le_bytes());
         preimage.extend_from_slice(&script_hash);
         preimage.extend_from_slice(&flags.0.to_le_bytes());
 
         *blake3::hash(&preimage).as_bytes()
     }
+
+    /// The part of the core covered by a non-`ALL` signature.
+    fn sighash_core(&self, input_index: usize, flags: SighashFlags) -> TransactionCore {
+        let inputs = if flags.anyone_can_pay() {
+            self.core
+                .inputs
+                .get(input_index)
+                .cloned()
+                .into_iter()
+                .collect()
+        } else {
+            self.core.inputs.clone()
+        };
+
+        let outputs = match flags.base() {
+            SighashFlags::NONE => Vec::new(),
+            SighashFlags::SINGLE => self
+                .core
+                .outputs
+                .get(input_index)
+                .cloned()
+                .into_iter()
+                .collect(),
+            _ => self.core.outputs.clone(),
+        };
+
+        TransactionCore {
+            kind: self.core.kind.clone(),
+            inputs,
+            outputs,
+            valid_from_height: self.core.valid_from_height,
+            valid_until_height: self.core.valid_until_height,
+        }
+    }
 }
 
 impl TransactionCore {
@@ -152,11 +559,56 @@ impl TransactionCore {
         let serialized = consensus_codec::encode_tx_core(self);
         *blake3::hash(&serialized).as_bytes()
     }
+
+    pub fn has_validity_window(&self) -> bool {
+        self.valid_from_height.is_some() || self.valid_until_height.is_some()
+    }
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
 pub struct SighashFlags(pub u32);
 
+impl SighashFlags {
+    /// Commits to every input and output. This is the default, and the only mode whose
+    /// flag byte is omitted from the witness signature.
+    pub const ALL: Self = Self(0x00);
+    /// Commits to no outputs.
+    pub const NONE: Self = Self(0x01);
+    /// Commits only to the output at the same index as the signed input.
+    pub const SINGLE: Self = Self(0x02);
+    /// Modifier: commits only to the signed input, so others can be added later.
+    pub const ANYONECANPAY: Self = Self(0x80);
+
+    const BASE_MASK: u32 = 0x7f;
+
+    /// Parses a witness flag byte, rejecting unknown modes.
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        let flags = Self(byte as u32);
+        matches!(flags.base(), Self::ALL | Self::NONE | Self::SINGLE).then_some(flags)
+    }
+
+    pub fn to_byte(self) -> u8 {
+        self.0 as u8
+    }
+
+    /// The output mode with the ANYONECANPAY modifier removed.
+    pub fn base(self) -> Self {
+        Self(self.0 & Self::BASE_MASK)
+    }
+
+    pub fn anyone_can_pay(self) -> bool {
+        self.0 & Self::ANYONECANPAY.0 != 0
+    }
+}
+
+impl std::ops::BitOr for SighashFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 pub fn create_asset_transaction(
     issuer_script_hash: Hash256,
     kind: AssetKind,
@@ -173,6 +625,9 @@ pub fn create_asset_transaction(
         max_supply,
         decimals,
         kind,
+        transfer_policy: TransferPolicy::default(),
+        issuer_controls: IssuerControls::default(),
+        royalty: None,
     };
 
     let asset_id = derive_asset_id(&definition, chain_id);
@@ -182,6 +637,7 @@ pub fn create_asset_transaction(
             kind: TransactionKind::CreateAsset {
                 definition: definition.clone(),
                 initial_supply,
+                sub_ids: Vec::new(),
             },
             inputs: vec![],
             outputs: vec![Output {
@@ -192,6 +648,8 @@ pub fn create_asset_transaction(
                 }],
                 metadata_hash: None,
             }],
+            valid_from_height: None,
+            valid_until_height: None,
         },
         witness: TransactionWitness::default(),
     };
@@ -199,121 +657,8 @@ pub fn create_asset_transaction(
     (definition, transaction)
 }
 
-pub mod consensus_codec {
-    use super::{
-        AssetAmount, AssetDefinition, AssetKind, BlockHeader, Hash256, Output, TransactionCore,
-        TransactionInput, TransactionKind,
-    };
-
-    fn encode_len(len: usize, out: &mut Vec<u8>) {
-        let len: u32 = len
-            .try_into()
-            .expect("consensus encoding length should fit into u32");
-        out.extend_from_slice(&len.to_le_bytes());
-    }
-
-    pub fn encode_hash(hash: &Hash256, out: &mut Vec<u8>) {
-        out.extend_from_slice(hash);
-    }
-
-    fn encode_transaction_input(input: &TransactionInput, out: &mut Vec<u8>) {
-        encode_hash(&input.tx_id, out);
-        out.extend_from_slice(&input.index.to_le_bytes());
-    }
-
-    fn encode_asset_amount(asset: &AssetAmount, out: &mut Vec<u8>) {
-        encode_hash(&asset.asset_id.0, out);
-        out.extend_from_slice(&asset.amount.to_le_bytes());
-    }
-
-    pub fn encode_asset_definition(definition: &AssetDefinition) -> Vec<u8> {
-        let mut out = Vec::new();
-        encode_asset_definition_into(definition, &mut out);
-        out
-    }
-
-    fn encode_asset_definition_into(definition: &AssetDefinition, out: &mut Vec<u8>) {
-        encode_hash(&definition.issuer_script_hash, out);
-        out.push(match definition.kind {
-            AssetKind::Fungible => 0,
-            AssetKind::NonFungible => 1,
-            AssetKind::SemiFungible => 2,
-        });
-        encode_hash(&definition.metadata_root, out);
-        match definition.max_supply {
-            Some(max) => {
-                out.push(1);
-                out.extend_from_slice(&max.to_le_bytes());
-            }
-            None => out.push(0),
-        }
-        out.push(definition.decimals);
-    }
-
-    pub fn encode_output(output: &Output) -> Vec<u8> {
-        let mut out = Vec::new();
-        encode_output_into(output, &mut out);
-        out
-    }
-
-    pub fn encode_output_into(output: &Output, out: &mut Vec<u8>) {
-        encode_hash(&output.owner_script_hash, out);
-        encode_len(output.assets.len(), out);
-        for asset in &output.assets {
-            encode_asset_amount(asset, out);
-        }
-
-        match &output.metadata_hash {
-            Some(hash) => {
-                out.push(1);
-                encode_hash(hash, out);
-            }
-            None => out.push(0),
-        }
-    }
-
-    pub fn encode_tx_core(core: &TransactionCore) -> Vec<u8> {
-        let mut out = Vec::new();
-        encode_tx_core_into(core, &mut out);
-        out
-    }
-
-    pub fn encode_tx_core_into(core: &TransactionCore, out: &mut Vec<u8>) {
-        out.push(match core.kind {
-            TransactionKind::Transfer => 0,
-            TransactionKind::CreateAsset { .. } => 1,
-        });
-
-        encode_len(core.inputs.len(), out);
-        for input in &core.inputs {
-            encode_transaction_input(input, out);
-        }
-
-        encode_len(core.outputs.len(), out);
-        for output in &core.outputs {
-            encode_output_into(output, out);
-        }
-
-        if let TransactionKind::CreateAsset {
-            definition,
-            initial_supply,
-        } = &core.kind
-        {
-            encode_asset_definition_into(definition, out);
-            out.extend_from_slice(&initial_supply.to_le_bytes());
-        }
-    }
-
-    pub fn encode_block_header(header: &BlockHeader) -> Vec<u8> {
-        let mut out = Vec::new();
-        encode_hash(&header.parent_hash, &mut out);
-        encode_hash(&header.state_root, &mut out);
-        encode_hash(&header.tx_root, &mut out);
-        out.extend_from_slice(&header.height.to_le_bytes());
-        out.extend_from_slice(&header.timestamp.to_le_bytes());
-        out
-    }
-}
+pub mod address;
+pub mod consensus_codec;
 
 #[cfg(test)]
 mod tests {
@@ -332,6 +677,8 @@ mod tests {
                     }],
                     metadata_hash: None,
                 }],
+                valid_from_height: None,
+                valid_until_height: None,
             },
             witness: TransactionWitness::default(),
         }
@@ -411,4 +758,246 @@ mod tests {
         assert!(QCOIN_ASSET_ID.0[7..].iter().all(|byte| *byte == 0));
         assert!(is_qcoin_asset_id(&QCOIN_ASSET_ID));
     }
+
+    #[test]
+    fn witness_tx_id_covers_witness_but_tx_id_does_not() {
+        let tx = base_transaction();
+        let mut signed = tx.clone();
+        signed.witness.inputs.push(vec![1, 2, 3]);
+        assert_eq!(tx.tx_id(), signed.tx_id());
+        assert_ne!(tx.witness_tx_id(), signed.witness_tx_id());
+
+        let mut resigned = signed.clone();
+        resigned.witness.inputs[0] = vec![1, 2, 4];
+        assert_ne!(signed.witness_tx_id(), resigned.witness_tx_id());
+
+        // Moving a byte across the boundary between two inputs changes the id too.
+        let mut split = tx.clone();
+        split.witness.inputs = vec![vec![1], vec![2, 3]];
+        let mut joined = tx.clone();
+        joined.witness.inputs = vec![vec![1, 2], vec![3]];
+        assert_ne!(split.witness_tx_id(), joined.witness_tx_id());
+    }
+
+    #[test]
+    fn header_encoding_depends_on_version() {
+        let mut header = BlockHeader {
+            version: BLOCK_HEADER_VERSION_LEGACY,
+            parent_hash: [1u8; 32],
+            state_root: [2u8; 32],
+            tx_root: [3u8; 32],
+            height: 4,
+            timestamp: 5,
+            witness_root: LEGACY_WITNESS_ROOT,
+            proposer_id: [0u8; 32],
+            validator_set_hash: [0u8; 32],
+            consensus_params_hash: [0u8; 32],
+        };
+        let legacy = consensus_codec::encode_block_header(&header);
+        assert_eq!(legacy.len(), 3 * 32 + 8 + 8);
+
+        header.witness_root = [6u8; 32];
+        let committed = consensus_codec::encode_block_header(&header);
+        assert_eq!(&committed[..legacy.len()], legacy.as_slice());
+        assert_eq!(&committed[legacy.len()..], &[6u8; 32]);
+
+        header.version = BLOCK_HEADER_VERSION;
+        header.proposer_id = [7u8; 32];
+        header.validator_set_hash = [8u8; 32];
+        header.consensus_params_hash = [9u8; 32];
+        let versioned = consensus_codec::encode_block_header(&header);
+        assert_eq!(versioned.len(), 4 + legacy.len() + 4 * 32);
+        assert_eq!(&versioned[..4], &BLOCK_HEADER_VERSION.to_le_bytes());
+        assert_eq!(&versioned[versioned.len() - 32..], &[9u8; 32]);
+    }
+
+    #[test]
+    fn validator_set_hash_commits_to_order() {
+        let key = |byte: u8| PublicKey {
+            scheme: qcoin_crypto::SignatureSchemeId::Dilithium2,
+            bytes: vec![byte; 4],
+        };
+        let forward = validator_set_hash(&[key(1), key(2)]);
+        assert_ne!(forward, validator_set_hash(&[key(2), key(1)]));
+        assert_ne!(forward, validator_set_hash(&[key(1)]));
+        assert_ne!(validator_id(&key(1)), validator_id(&key(2)));
+    }
+
+    #[test]
+    fn input_witness_encoding_round_trips_and_is_strict() {
+        let witness = InputWitness {
+            script_version: 0,
+            script: vec![6, 6],
+            stack: vec![vec![1, 2, 3], Vec::new()],
+            metadata: Some(b"meta".to_vec()),
+            merkle_path: Some(vec![[7u8; 32]]),
+        };
+        let bytes = witness.encode();
+        assert_eq!(&bytes[..4], consensus_codec::WITNESS_MAGIC);
+        assert_eq!(InputWitness::decode(&bytes), Ok(witness.clone()));
+
+        let bare = InputWitness {
+            script: vec![6],
+            ..InputWitness::default()
+        };
+        assert_eq!(InputWitness::decode(&bare.encode()), Ok(bare));
+
+        use consensus_codec::DecodeError;
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            InputWitness::decode(&trailing),
+            Err(DecodeError::TrailingBytes(1))
+        );
+        assert_eq!(
+            InputWitness::decode(&bytes[..bytes.len() - 1]),
+            Err(DecodeError::UnexpectedEnd)
+        );
+        let mut bad_version = bytes.clone();
+        bad_version[4] = 2;
+        assert_eq!(
+            InputWitness::decode(&bad_version),
+            Err(DecodeError::UnsupportedVersion(2))
+        );
+        // The merkle path tag sits right before its 4-byte count and one hash.
+        let mut bad_tag = bytes.clone();
+        let tag_at = bytes.len() - 32 - 4 - 1;
+        bad_tag[tag_at] = 2;
+        assert!(matches!(
+            InputWitness::decode(&bad_tag),
+            Err(DecodeError::InvalidTag { tag: 2, .. })
+        ));
+        assert_eq!(
+            InputWitness::decode(b"nope"),
+            Err(DecodeError::MissingMagic)
+        );
+    }
+
+    #[test]
+    fn sighash_modes_commit_only_selected_inputs_and_outputs() {
+        let mut tx = base_transaction();
+        tx.core.inputs = vec![
+            TransactionInput {
+                tx_id: [5u8; 32],
+                index: 0,
+            },
+            TransactionInput {
+                tx_id: [6u8; 32],
+                index: 1,
+            },
+        ];
+        tx.core.outputs.push(Output {
+            owner_script_hash: [7u8; 32],
+            assets: vec![],
+            metadata_hash: None,
+        });
+        let prev_output = tx.core.outputs[0].clone();
+        let sighash = |tx: &Transaction, input_index: usize, flags: SighashFlags| {
+            tx.sighash(input_index, &prev_output, [1u8; 32], 0, flags)
+        };
+
+        let mut other_output_changed = tx.clone();
+        other_output_changed.core.outputs[1].owner_script_hash = [8u8; 32];
+        let mut input_added = tx.clone();
+        input_added.core.inputs.insert(
+            0,
+            TransactionInput {
+                tx_id: [9u8; 32],
+                index: 0,
+            },
+        );
+
+        let all = SighashFlags::ALL;
+        let single = SighashFlags::SINGLE;
+        let none = SighashFlags::NONE;
+        let single_acp = SighashFlags::SINGLE | SighashFlags::ANYONECANPAY;
+
+        assert_ne!(sighash(&tx, 0, all), sighash(&other_output_changed, 0, all));
+        assert_eq!(
+            sighash(&tx, 0, single),
+            sighash(&other_output_changed, 0, single)
+        );
+        assert_ne!(
+            sighash(&tx, 1, single),
+            sighash(&other_output_changed, 1, single)
+        );
+        assert_eq!(
+            sighash(&tx, 0, none),
+            sighash(&other_output_changed, 0, none)
+        );
+        assert_ne!(sighash(&tx, 0, single), sighash(&tx, 0, none));
+
+        // ANYONECANPAY survives inputs being added in front of the signed one.
+        assert_ne!(sighash(&tx, 1, all), sighash(&input_added, 2, all));
+        assert_eq!(
+            sighash(&tx, 0, SighashFlags::NONE | SighashFlags::ANYONECANPAY),
+            sighash(
+                &input_added,
+                1,
+                SighashFlags::NONE | SighashFlags::ANYONECANPAY
+            )
+        );
+        assert_ne!(sighash(&tx, 0, single_acp), sighash(&tx, 0, single));
+
+        assert_eq!(SighashFlags::from_byte(0x82), Some(single_acp));
+        assert_eq!(SighashFlags::from_byte(0x03), None);
+        assert_eq!(single_acp.base(), single);
+    }
+
+    #[test]
+    fn validity_window_is_committed_by_id_and_sighash() {
+        let tx = base_transaction();
+        // Without a window the kind byte, and so the encoding, is unchanged.
+        assert_eq!(consensus_codec::encode_tx_core(&tx.core)[0], 0);
+
+        let prev_output = tx.core.outputs[0].clone();
+        let mut expiring = tx.clone();
+        expiring.core.valid_until_height = Some(10);
+        assert!(expiring.core.has_validity_window());
+        assert_ne!(expiring.tx_id(), tx.tx_id());
+        for flags in [
+            SighashFlags::ALL,
+            SighashFlags::NONE | SighashFlags::ANYONECANPAY,
+        ] {
+            assert_ne!(
+                tx.sighash(0, &prev_output, [1u8; 32], 0, flags),
+                expiring.sighash(0, &prev_output, [1u8; 32], 0, flags)
+            );
+        }
+    }
+
+    #[test]
+    fn royalty_payment_rounds_up_and_builds_an_output() {
+        let rule = RoyaltyRule {
+            recipient_script_hash: [3u8; 32],
+            payment_asset_id: QCOIN_ASSET_ID,
+            amount: RoyaltyAmount::BasisPoints(250),
+        };
+        assert_eq!(rule.required_payment(1_000), 25);
+        assert_eq!(rule.required_payment(1), 1);
+        assert_eq!(rule.required_payment(0), 0);
+        assert_eq!(rule.payment_output(0), None);
+        assert_eq!(
+            rule.payment_output(401),
+            Some(Output {
+                owner_script_hash: [3u8; 32],
+                assets: vec![AssetAmount {
+                    asset_id: QCOIN_ASSET_ID,
+                    amount: 11,
+                }],
+                metadata_hash: None,
+            })
+        );
+
+        let full = RoyaltyRule {
+            amount: RoyaltyAmount::BasisPoints(MAX_ROYALTY_BASIS_POINTS),
+            ..rule.clone()
+        };
+        assert_eq!(full.required_payment(u128::MAX), u128::MAX);
+        let fixed = RoyaltyRule {
+            amount: RoyaltyAmount::Fixed(5),
+            ..rule
+        };
+        assert_eq!(fixed.required_payment(0), 5);
+    }
 }